@@ -0,0 +1,128 @@
+// `bf5d!` parses a program at compile time, re-exported from the `bf5d` crate.
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+// `bf5d` re-exports this crate so it can't be a dependency, compile its parser in instead
+#[allow(dead_code, unexpected_cfgs)]
+#[path = "../../src/parser/mod.rs"]
+mod parser;
+
+/// Expands to a `&'static [Token]` or a compile error pointing at the literal.
+///
+/// ```ignore
+/// const ADD: &[bf5d::parser::types::Token] = bf5d::bf5d!("[->+<]");
+/// ```
+#[proc_macro]
+pub fn bf5d(input: TokenStream) -> TokenStream {
+    let (source, span) = match string_literal(input) {
+        Ok(literal) => literal,
+        Err(span) => return error("bf5d! expects a single string literal", span),
+    };
+
+    match parser::bf5d::parse(source.as_str()) {
+        // the Debug form of a token is valid Rust once the variants are in scope
+        Ok(tokens) => format!(
+            "{{
+                use ::bf5d::parser::types::{{
+                    JumpType::*, MoveDirection::*, Token, Token::*, UpdateType::*,
+                }};
+                const TOKENS: &[Token] = &{:?};
+                TOKENS
+            }}",
+            tokens
+        )
+        .parse()
+        .expect("token Debug output is valid Rust"),
+        Err(e) => error(format!("invalid BF5D program: {}", e).as_str(), span),
+    }
+}
+
+fn string_literal(input: TokenStream) -> Result<(String, Span), Span> {
+    let mut trees = input.into_iter();
+    match (trees.next(), trees.next()) {
+        // literals forwarded through `macro_rules!` arrive wrapped in an invisible group
+        (Some(TokenTree::Group(group)), None) if group.delimiter() == Delimiter::None => {
+            string_literal(group.stream())
+        }
+        (Some(TokenTree::Literal(literal)), None) => {
+            let span = literal.span();
+            unquote(literal.to_string().as_str())
+                .map(|source| (source, span))
+                .ok_or(span)
+        }
+        (Some(tree), _) => Err(tree.span()),
+        (None, _) => Err(Span::call_site()),
+    }
+}
+
+// undoes the quoting of a (raw) string literal, `None` for any other kind of literal
+fn unquote(literal: &str) -> Option<String> {
+    if let Some(raw) = literal.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let fence = "#".repeat(hashes);
+        return raw
+            .strip_prefix(fence.as_str())?
+            .strip_suffix(fence.as_str())?
+            .strip_prefix('"')?
+            .strip_suffix('"')
+            .map(str::to_string);
+    }
+
+    let body = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut unquoted = String::with_capacity(body.len());
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => unquoted.push('\n'),
+            'r' => unquoted.push('\r'),
+            't' => unquoted.push('\t'),
+            '0' => unquoted.push('\0'),
+            '\\' => unquoted.push('\\'),
+            '\'' => unquoted.push('\''),
+            '"' => unquoted.push('"'),
+            'x' => {
+                let hex = chars.next()?.to_string() + chars.next()?.to_string().as_str();
+                unquoted.push(u8::from_str_radix(hex.as_str(), 16).ok()? as char);
+            }
+            'u' => {
+                let hex = chars
+                    .by_ref()
+                    .skip(1) // '{'
+                    .take_while(|&c| c != '}')
+                    .filter(|&c| c != '_')
+                    .collect::<String>();
+                unquoted.push(std::char::from_u32(u32::from_str_radix(hex.as_str(), 16).ok()?)?);
+            }
+            // line continuation, skips the newline and leading whitespace
+            '\n' => {
+                while chars.peek().map_or(false, |c| c.is_whitespace()) {
+                    chars.next();
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(unquoted)
+}
+
+fn error(message: &str, span: Span) -> TokenStream {
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut arguments = Group::new(Delimiter::Parenthesis, TokenTree::from(message).into());
+    arguments.set_span(span);
+
+    vec![
+        TokenTree::from(Ident::new("compile_error", span)),
+        TokenTree::from(bang),
+        TokenTree::from(arguments),
+    ]
+    .into_iter()
+    .collect()
+}
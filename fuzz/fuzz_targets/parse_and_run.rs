@@ -0,0 +1,39 @@
+// Any bytes: the first two pick the extensions, the rest is the program, then a NUL, then input.
+#![no_main]
+use bf5d::{
+    interpreter::{
+        bf5d::Engine,
+        sandbox::{Limits, SandboxedRun},
+    },
+    parser::types::Extensions,
+};
+use libfuzzer_sys::fuzz_target;
+
+const MAX_STEPS: usize = 1_000;
+// spawning every round doubles the timelines, without a cap a few steps run out of memory
+const MAX_TIMELINES: usize = 64;
+// `^` merges the pointers into the timeline above, a loop of spawns doing it doubles them
+const MAX_MEMORY: usize = 16 << 20;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let extensions = Extensions::from_bits_truncate(u16::from_le_bytes([data[0], data[1]]).into());
+    let text = String::from_utf8_lossy(&data[2..]);
+    let (program, input) = match text.find('\0') {
+        Some(end) => (&text[..end], &text[end + 1..]),
+        None => (&text[..], ""),
+    };
+
+    // errors are fine, panics are what we're after
+    if let Ok(engine) = Engine::from_source_with(program, input, extensions) {
+        let limits = Limits {
+            steps: Some(MAX_STEPS),
+            timelines: Some(MAX_TIMELINES),
+            memory: Some(MAX_MEMORY),
+            ..Limits::default()
+        };
+        let _ = SandboxedRun::new(engine, limits).run();
+    }
+});
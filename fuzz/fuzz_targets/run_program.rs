@@ -0,0 +1,31 @@
+// Only programs that parse, so the fuzzer spends its time in the engine.
+#![no_main]
+use bf5d::{
+    interpreter::{
+        bf5d::Engine,
+        sandbox::{Limits, SandboxedRun},
+        types::BF5DContext,
+    },
+    parser::{arbitrary::Program, types::Extensions},
+};
+use libfuzzer_sys::fuzz_target;
+
+const MAX_STEPS: usize = 1_000;
+const MAX_TIMELINES: usize = 64;
+const MAX_MEMORY: usize = 16 << 20;
+
+fuzz_target!(|input: (Program, String)| {
+    let (Program(tokens), program_input) = input;
+    let mut context = BF5DContext::new();
+    context.extensions = Extensions::all();
+    context.set_tokens(tokens);
+    context.program_input = program_input;
+
+    let limits = Limits {
+        steps: Some(MAX_STEPS),
+        timelines: Some(MAX_TIMELINES),
+        memory: Some(MAX_MEMORY),
+        ..Limits::default()
+    };
+    let _ = SandboxedRun::new(Engine::new(context), limits).run();
+});
@@ -0,0 +1,1540 @@
+// Command line front-end, the web app lives in `main.rs`.
+use std::{
+    any::Any,
+    env, fs, io,
+    ops::Range,
+    panic,
+    path::Path,
+    process,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use bf5d::{
+    interpreter::{
+        bf5d::Engine,
+        error::RuntimeError,
+        explain::Narrator,
+        progress::{Progress, Reporter},
+        reload::Reload,
+        rng::Rng,
+        sandbox::Limits,
+        scheduling::SchedulingPolicy,
+        types::{AwaitPolicy, CommitPolicy, FuelPolicy, OverflowPolicy, SpawnIfPolicy},
+    },
+    lsp,
+    optimizer::{
+        ir::{Machine, Program},
+        obfuscate, partial, symbolic, PassError, Pipeline,
+    },
+    parser::{
+        bf5d::parse_with_spans,
+        grammar::{self, GrammarFormat},
+        link::SourceMap,
+        stats, tape,
+        termination::{self, LoopVerdict, Verdict},
+        timelines::{self, TimelineBound},
+        types::Extensions,
+    },
+    testing::{
+        batch::{self, ReportFormat},
+        golden,
+    },
+};
+
+const USAGE: &str = "\
+usage: bf5d-cli <command> [options]
+
+commands:
+    run FILE... [--input TEXT] [--seed N] [--extensions LIST] [--schedule POLICY]
+        [--await POLICY] [--overflow POLICY] [--spawn-if POLICY] [--fuel N]
+        [--max-steps N] [--max-timelines N] [--deterministic] [--snapshot FILE]
+        [--opt-level N] [--passes LIST] [--explain] [--progress N] [--keep-dead]
+        [--commit POLICY] [--fuel-policy POLICY] [--watch]
+                                run a program and print its output, the
+                                files of one given in several one after the
+                                other, each closing the loops it opens,
+                                --fuel is instructions per timeline per round,
+                                --max-timelines stops it once more are alive,
+                                --deterministic numbers timelines from zero
+                                and seeds all randomness with --seed,
+                                Ctrl-C stops it and summarizes the timelines,
+                                writing the whole state to --snapshot if given,
+                                a crash writes it to a file for the bug report,
+                                --opt-level 1 or 2 (0 by default) optimizes
+                                programs of <>+-[].,, --passes runs the
+                                passes of LIST in its order instead, with
+                                --max-steps counting optimized instructions,
+                                --explain says what each timeline does at
+                                every step on stderr, --progress N updates a
+                                line there with the steps, timelines and memory
+                                every N steps, both running it unoptimized,
+                                --keep-dead shows the timelines that died
+                                on the way once it's done, --watch runs it
+                                unoptimized and swaps in a file as it's
+                                saved, the timelines going on from the same
+                                instructions in it, starting over if one was
+                                at an instruction the edit took out or the
+                                run had ended
+    test DIR [--max-steps N]    run the golden files (*.bf5d, *.in, *.out) in DIR,
+                                the //! lines a program starts with set its
+                                extensions and limits, e.g. //! steps 500
+    batch MANIFEST [--workers N] [--format FORMAT]
+                                run the programs a TOML manifest lists, each
+                                with its own input, expected output and
+                                limits, on N threads (one per core by
+                                default), and print a report of them, FORMAT
+                                is json (default) or csv
+    check FILE [--extensions LIST] [--analyze] [--max-timelines N]
+                                parse a program without running it, --analyze
+                                also says whether it stops, which loops
+                                can spin forever, which cells each timeline
+                                can reach and how many timelines can be
+                                alive at once, failing if that can't be
+                                shown to stay within --max-timelines
+    explore FILE [--input-length N] [--max-steps N] [--max-paths N]
+        [--extensions LIST]
+                                experimental: run a program of <>+-[]., on
+                                every input of up to N bytes (2 by default)
+                                at once and list the outputs it can write,
+                                each with what the input has to be for it,
+                                following at most --max-paths ways of
+                                --max-steps each
+    stats FILE [--extensions LIST]
+                                count the instructions of a program by kind,
+                                how deep its loops nest, where it spawns,
+                                awaits and rewinds, and how much tape it
+                                needs
+    specialize FILE --input TEXT [--extensions LIST] [--max-steps N]
+                                run a program of <>+-[]., ahead on the input
+                                it's going to get and print what's left of
+                                it, a program writing the output so far that
+                                goes on from the last point outside every
+                                loop, if it didn't halt within --max-steps
+    obfuscate FILE [--seed N] [--extensions LIST] [--inputs N] [--max-steps N]
+                                print a program that does what one of a
+                                single timeline does in another way, the
+                                same for the same --seed, after checking
+                                the two agree on N random inputs (32 by
+                                default) and the empty one, running the
+                                original for up to --max-steps (100000)
+    lsp [--extensions LIST]     speak the language server protocol on stdin
+                                and stdout for editors, every extension is on
+                                unless --extensions says otherwise
+    grammar [--format FORMAT] [--extensions LIST]
+                                print a syntax highlighting grammar for the
+                                instructions of LIST, FORMAT is textmate
+                                (default) or tree-sitter
+    serve [--address ADDR] [--max-steps N]
+                                answer POST /run with the output and stats of
+                                a sandboxed run, streamed as events over a
+                                WebSocket at /trace, on 127.0.0.1:8000 unless
+                                --address says otherwise, needs the server
+                                feature
+    daemon [--socket PATH] [--max-steps N]
+                                keep programs parsed and run them for requests
+                                sent as JSON lines to a Unix socket, at
+                                bf5d.sock in the temporary directory unless
+                                --socket says otherwise
+    kernel CONNECTION_FILE [--extensions LIST]
+                                be the Jupyter kernel of a notebook, needs the
+                                kernel feature
+
+extensions (comma separated, or all): debug_dump, random, numeric_io, labels,
+    procedures, priority, sleep, copy, split, fork,
+    spawn_if, barrier, markers, reset
+
+scheduling policies: top_down (default), bottom_up, newest_first,
+    priority (highest first, the only one raising a priority with ! changes),
+    random (shuffled with --seed)
+
+await policies: below_pointerless (default), below_dead, all_children
+
+overflow policies: wrap (default), trap (stops at + on 255 or - on 0)
+
+fuel policies: quantum (default), fair_share (what a turn cut short by a
+    command didn't use carries over to the next)
+
+spawn_if policies: any (default, I spawns if a cell under the pointers isn't 0),
+    all (only if none of them is 0)
+
+commit policies: never (default), io (a timeline as it writes or reads), N (all
+    of them every N rounds), ~ and \\ don't go back past a commit, so the
+    history behind it is dropped
+
+passes (comma separated): fuse, clear-loop, dead-loop, offsets, bounded-tape,
+    verify (checks the passes before it, after each one in debug builds)
+
+input: --input TEXT, --input-env VAR for the value of an environment
+    variable, or -- ARGS... last for the arguments after it joined by
+    --separator (a space by default), none of them for no input";
+
+const DEFAULT_MAX_STEPS: usize = 1_000_000;
+// exit code of a run stopped by Ctrl-C, as if the signal had killed it
+const INTERRUPTED: i32 = 130;
+// a warning inside a loop can come up every round, the first few say enough
+const MAX_WARNINGS: usize = 20;
+// what an uncaught panic would have exited with
+const CRASHED: i32 = 101;
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+
+    let code = match args.first().map(String::as_str) {
+        Some("run") => run(&args[1..]),
+        Some("test") => test(&args[1..]),
+        Some("batch") => batch(&args[1..]),
+        Some("check") => check(&args[1..]),
+        Some("stats") => stats(&args[1..]),
+        Some("explore") => explore(&args[1..]),
+        Some("specialize") => specialize(&args[1..]),
+        Some("obfuscate") => obfuscate(&args[1..]),
+        Some("lsp") => lsp(&args[1..]),
+        Some("grammar") => grammar(&args[1..]),
+        Some("serve") => serve(&args[1..]),
+        Some("daemon") => daemon(&args[1..]),
+        Some("kernel") => kernel(&args[1..]),
+        _ => {
+            eprintln!("{}", USAGE);
+            2
+        }
+    };
+
+    process::exit(code);
+}
+
+fn run(args: &[String]) -> i32 {
+    let paths = positional(args);
+    if paths.is_empty() {
+        eprintln!("{}", USAGE);
+        return 2;
+    }
+    let (max_steps, seed, fuel) = match (
+        number_option(args, "--max-steps", DEFAULT_MAX_STEPS),
+        number_option(args, "--seed", 0),
+        number_option(args, "--fuel", 1),
+    ) {
+        (Ok(max_steps), Ok(seed), Ok(fuel)) => (max_steps, seed, fuel),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    let mut map = match link_files(&paths) {
+        Ok(map) => map,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let path = paths.join(", ");
+    let extensions = match option(args, "--extensions")
+        .unwrap_or("")
+        .parse::<Extensions>()
+    {
+        Ok(extensions) => extensions,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let max_timelines = match number_option(args, "--max-timelines", usize::MAX) {
+        Ok(max_timelines) => max_timelines,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let mut reporter = match number_option(args, "--progress", 0) {
+        Ok(0) => None,
+        Ok(every) => Some(Reporter::new(every)),
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let input = match program_input(args) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let input = input.as_str();
+    let mut engine = match Engine::from_source_with(map.source.as_str(), input, extensions) {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("{}: {}{}", path, e, located(&map, e.span().as_ref()));
+            return 1;
+        }
+    };
+    if let Err(e) = map.check_brackets(&engine.context.tokens, &engine.context.spans) {
+        eprintln!("{}: {}", path, e.describe(&map));
+        return 1;
+    }
+    engine.context.rng = Rng::new(seed);
+    engine.context.fuel = fuel;
+    engine.context.fuel_policy = match option(args, "--fuel-policy").unwrap_or("quantum") {
+        "quantum" => FuelPolicy::Quantum,
+        "fair_share" => FuelPolicy::FairShare,
+        name => {
+            eprintln!("unknown fuel policy {:?}", name);
+            return 2;
+        }
+    };
+    engine.context.scheduling = match option(args, "--schedule").unwrap_or("top_down") {
+        "top_down" => SchedulingPolicy::TopDown,
+        "bottom_up" => SchedulingPolicy::BottomUp,
+        "random" => SchedulingPolicy::Random { seed },
+        "newest_first" => SchedulingPolicy::NewestFirst,
+        "priority" => SchedulingPolicy::Priority,
+        name => {
+            eprintln!("unknown scheduling policy {:?}", name);
+            return 2;
+        }
+    };
+    engine.context.await_policy = match option(args, "--await").unwrap_or("below_pointerless") {
+        "below_dead" => AwaitPolicy::BelowDead,
+        "below_pointerless" => AwaitPolicy::BelowPointerless,
+        "all_children" => AwaitPolicy::AllChildren,
+        name => {
+            eprintln!("unknown await policy {:?}", name);
+            return 2;
+        }
+    };
+    engine.context.overflow = match option(args, "--overflow").unwrap_or("wrap") {
+        "wrap" => OverflowPolicy::Wrap,
+        "trap" => OverflowPolicy::Trap,
+        name => {
+            eprintln!("unknown overflow policy {:?}", name);
+            return 2;
+        }
+    };
+    engine.context.spawn_if = match option(args, "--spawn-if").unwrap_or("any") {
+        "any" => SpawnIfPolicy::Any,
+        "all" => SpawnIfPolicy::All,
+        name => {
+            eprintln!("unknown spawn_if policy {:?}", name);
+            return 2;
+        }
+    };
+    engine.context.commit = match option(args, "--commit").unwrap_or("never") {
+        "never" => CommitPolicy::Never,
+        "io" => CommitPolicy::OnIo,
+        rounds => match rounds.parse() {
+            Ok(every) if every > 0 => CommitPolicy::Every(every),
+            _ => {
+                eprintln!("unknown commit policy {:?}", rounds);
+                return 2;
+            }
+        },
+    };
+    if flag(args, "--deterministic") {
+        engine.make_deterministic(seed);
+    }
+    engine.keep_dead_timelines(flag(args, "--keep-dead"));
+    let pipeline = match option(args, "--passes") {
+        Some(passes) => passes.parse::<Pipeline>(),
+        None => number_option(args, "--opt-level", 0).map(Pipeline::level),
+    };
+    let mut pipeline = match pipeline {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let mut narrator = if flag(args, "--explain") {
+        Some(Narrator::default())
+    } else {
+        None
+    };
+    // the optimized ops are no longer the instructions there are to explain, nor do they keep
+    // count of the timelines and cells
+    let watch = flag(args, "--watch");
+    if narrator.is_some() || reporter.is_some() || watch {
+        pipeline.passes.clear();
+    }
+    let optimized = match optimize(&engine, &pipeline) {
+        Ok(optimized) => optimized,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    catch_interrupt(interrupted.clone());
+    report_panics();
+    if let Some(program) = optimized {
+        let mut machine = Machine::new(&program, input);
+        while !machine.is_halted() && machine.steps < max_steps {
+            if interrupted.load(Ordering::SeqCst) {
+                break;
+            }
+            machine.step();
+        }
+        print!("{}", machine.output);
+        return finish_optimized(
+            args,
+            &machine,
+            max_steps,
+            interrupted.load(Ordering::SeqCst),
+        );
+    }
+    // where the run starts over once the program changes under a watch and can't carry on
+    let fresh = engine.clone();
+    let mut watcher = if watch {
+        Some(Watcher::new(&paths))
+    } else {
+        None
+    };
+    // bytes of the output printed so far
+    let mut printed = 0;
+    loop {
+        let mut changed = false;
+        let halted = match panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let max_steps = max_steps.saturating_sub(engine.context.steps);
+            engine.run_while(max_steps, |engine| {
+                changed = watcher.as_mut().is_some_and(Watcher::changed);
+                let keep_going = !interrupted.load(Ordering::SeqCst)
+                    && engine.timelines.len() <= max_timelines
+                    && !changed;
+                if let Some(narrator) = narrator.as_mut() {
+                    for line in narrator.after_step(engine) {
+                        eprintln!("{}", line);
+                    }
+                    if keep_going {
+                        narrator.before_step(engine);
+                    }
+                }
+                if let Some(progress) = reporter.as_mut().and_then(|r| r.poll(engine)) {
+                    // over the last one, clearing what's left of it
+                    eprint!("\r{}\x1b[K", progress);
+                }
+                keep_going
+            })
+        })) {
+            Ok(halted) => halted,
+            Err(payload) => {
+                crash_dump(args, &engine, payload.as_ref());
+                return CRASHED;
+            }
+        };
+        if let Some(narrator) = narrator.as_mut() {
+            for line in narrator.after_step(&engine) {
+                eprintln!("{}", line);
+            }
+        }
+        if reporter.is_some() && !changed {
+            eprintln!("\r{}\x1b[K", Progress::of(&engine));
+        }
+        print_output(&engine, &mut printed);
+        if changed && !interrupted.load(Ordering::SeqCst) {
+            if let Some(reloaded) = reload(&path, &paths, &mut engine, &fresh) {
+                map = reloaded;
+                if engine.context.steps == 0 {
+                    printed = 0;
+                }
+            }
+            continue;
+        }
+
+        let code = finish(
+            args,
+            &engine,
+            &map,
+            halted,
+            max_steps,
+            max_timelines,
+            interrupted.load(Ordering::SeqCst),
+        );
+        let watcher = match watcher.as_mut() {
+            Some(watcher) if !interrupted.load(Ordering::SeqCst) => watcher,
+            _ => return code,
+        };
+        eprintln!("{}: waiting for a change", path);
+        while !watcher.changed() {
+            if interrupted.load(Ordering::SeqCst) {
+                return code;
+            }
+            thread::sleep(WATCH_INTERVAL);
+        }
+        // what's left of a run that ended can only start over
+        engine = fresh.clone();
+        printed = 0;
+        if let Some(reloaded) = reload(&path, &paths, &mut engine, &fresh) {
+            map = reloaded;
+        }
+    }
+}
+
+// the files of a program read and linked in the order given
+fn link_files(paths: &[&str]) -> Result<SourceMap, String> {
+    let mut sources = vec![];
+    for &path in paths.iter() {
+        match fs::read_to_string(path) {
+            Ok(source) => sources.push(source),
+            Err(e) => return Err(format!("{}: {}", path, e)),
+        }
+    }
+    // several files run as the one they make in the order given
+    Ok(SourceMap::link(
+        paths
+            .iter()
+            .copied()
+            .zip(sources.iter().map(String::as_str)),
+    ))
+}
+
+// the output `engine` wrote since the last time
+fn print_output(engine: &Engine, printed: &mut usize) {
+    let output = &engine.context.program_output;
+    if output.len() > *printed {
+        print!("{}", &output[*printed..]);
+        let _ = io::Write::flush(&mut io::stdout());
+        *printed = output.len();
+    }
+}
+
+// how often the files of a program run with --watch are looked at
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+// when the files of a program were last modified, to tell when one was saved
+struct Watcher {
+    paths: Vec<String>,
+    modified: Vec<Option<SystemTime>>,
+    looked: Instant,
+}
+
+impl Watcher {
+    fn new(paths: &[&str]) -> Self {
+        let paths = paths
+            .iter()
+            .map(|path| path.to_string())
+            .collect::<Vec<_>>();
+        Watcher {
+            modified: modified(&paths),
+            paths,
+            looked: Instant::now(),
+        }
+    }
+
+    // whether a file changed since the last time it said so, looking every `WATCH_INTERVAL`
+    fn changed(&mut self) -> bool {
+        if self.looked.elapsed() < WATCH_INTERVAL {
+            return false;
+        }
+        self.looked = Instant::now();
+        let modified = modified(&self.paths);
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+fn modified(paths: &[String]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+// swaps the files as they are now into `engine`, starting over from `fresh` if a timeline can't
+// go on in them; the new map, `None` if they don't make a program and `engine` goes on as it was
+fn reload(path: &str, paths: &[&str], engine: &mut Engine, fresh: &Engine) -> Option<SourceMap> {
+    let map = match link_files(paths) {
+        Ok(map) => map,
+        Err(e) => {
+            eprintln!("{}, going on with the program as it was", e);
+            return None;
+        }
+    };
+    let (tokens, spans) = match parse_with_spans(map.source.as_str(), engine.context.extensions) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!(
+                "{}: {}{}, going on with the program as it was",
+                path,
+                e,
+                located(&map, e.span().as_ref())
+            );
+            return None;
+        }
+    };
+    if let Err(e) = map.check_brackets(&tokens, &spans) {
+        eprintln!(
+            "{}: {}, going on with the program as it was",
+            path,
+            e.describe(&map)
+        );
+        return None;
+    }
+
+    let lost = match engine.reload(map.source.as_str()) {
+        Ok(Reload::Mapped) if engine.context.steps == 0 => {
+            eprintln!("{}: starting over", path);
+            return Some(map);
+        }
+        Ok(Reload::Mapped) => {
+            eprintln!(
+                "{}: reloaded at step {}, {} timelines carry on",
+                path,
+                engine.context.steps,
+                engine.timelines.len()
+            );
+            return Some(map);
+        }
+        Ok(Reload::Lost {
+            timeline,
+            instruction,
+        }) => (timeline, instruction),
+        // it parsed a moment ago
+        Err(_) => return None,
+    };
+    let (timeline, instruction) = lost;
+    let at = engine
+        .context
+        .spans
+        .get(instruction)
+        .map_or_else(String::new, |span| {
+            format!(" (bytes {}..{})", span.start, span.end)
+        });
+    eprintln!(
+        "{}: timeline {} is at instruction {}{}, which isn't in the new program, starting over",
+        path, timeline, instruction, at
+    );
+    *engine = fresh.clone();
+    engine.reload(map.source.as_str()).ok()?;
+    Some(map)
+}
+
+// what's left to say once a run ended, and the exit code for it
+fn finish(
+    args: &[String],
+    engine: &Engine,
+    map: &SourceMap,
+    halted: Result<bool, RuntimeError>,
+    max_steps: usize,
+    max_timelines: usize,
+    interrupted: bool,
+) -> i32 {
+    let path = map
+        .files
+        .iter()
+        .map(|file| file.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    for dump in engine.context.dumps.iter() {
+        eprintln!("{}", dump);
+    }
+    for timeline in engine.context.dead.iter() {
+        let step = timeline.died_at_step.unwrap_or(0);
+        eprintln!("died at step {}: {}", step, timeline.debug_dump());
+    }
+    let warnings = &engine.context.warnings;
+    for warning in warnings.iter().take(MAX_WARNINGS) {
+        eprintln!(
+            "warning: {}{}",
+            warning,
+            located(map, warning.span.as_ref())
+        );
+    }
+    let count = engine.context.warning_count();
+    if count > MAX_WARNINGS {
+        eprintln!("and {} more warnings", count - MAX_WARNINGS);
+    }
+
+    match halted {
+        Ok(true) => 0,
+        Ok(false) if interrupted => {
+            eprintln!(
+                "interrupted after {} steps, {} timelines",
+                engine.context.steps,
+                engine.timelines.len()
+            );
+            for timeline in engine.timelines.iter() {
+                eprintln!("{}", timeline.debug_dump());
+            }
+            if let Some(snapshot) = option(args, "--snapshot") {
+                if let Err(e) = fs::write(snapshot, format!("{:#?}\n", engine)) {
+                    eprintln!("{}: {}", snapshot, e);
+                }
+            }
+            INTERRUPTED
+        }
+        Ok(false) if engine.timelines.len() > max_timelines => {
+            eprintln!(
+                "stopped after {} steps, {} timelines are more than --max-timelines {}",
+                engine.context.steps,
+                engine.timelines.len(),
+                max_timelines
+            );
+            1
+        }
+        Ok(false) => {
+            eprintln!("stopped after {} steps", max_steps);
+            1
+        }
+        Err(e) => {
+            eprintln!("{}: {}{}", path, e, located(map, e.span.as_ref()));
+            1
+        }
+    }
+}
+
+// where `span` is in the files a program of several came from
+fn located(map: &SourceMap, span: Option<&Range<usize>>) -> String {
+    match span.and_then(|span| map.locate(span.start)) {
+        Some(location) if map.files.len() > 1 => format!(" ({})", location),
+        _ => "".to_string(),
+    }
+}
+
+// the program `pipeline` made of what `engine` was going to run, `None` to run it unoptimized
+// `finish` for the ops `optimize` gave, those of <>+-[]., never warn or dump and there's only the
+// root timeline to summarize
+fn finish_optimized(
+    args: &[String],
+    machine: &Machine,
+    max_steps: usize,
+    interrupted: bool,
+) -> i32 {
+    if machine.is_halted() {
+        return 0;
+    }
+    if !interrupted {
+        eprintln!("stopped after {} steps", max_steps);
+        return 1;
+    }
+    let state = format!(
+        "pointer {}, optimized op {}, cells {:?}",
+        machine.pointer,
+        machine.ip,
+        machine.nonzero_cells()
+    );
+    eprintln!("interrupted after {} steps, {}", machine.steps, state);
+    if let Some(snapshot) = option(args, "--snapshot") {
+        if let Err(e) = fs::write(snapshot, format!("{}\n", state)) {
+            eprintln!("{}: {}", snapshot, e);
+        }
+    }
+    INTERRUPTED
+}
+
+fn optimize(engine: &Engine, pipeline: &Pipeline) -> Result<Option<Program>, PassError> {
+    if pipeline.passes.is_empty() {
+        return Ok(None);
+    }
+    if engine.context.overflow == OverflowPolicy::Trap {
+        eprintln!("warning: the optimizer wraps cells around, --overflow trap runs unoptimized");
+        return Ok(None);
+    }
+    let mut program = match Program::lower(&engine.context.tokens) {
+        Ok(program) => program,
+        Err(instruction) => {
+            let span = &engine.context.spans[instruction];
+            eprintln!(
+                "warning: instruction {} (bytes {}..{}) isn't one of the <>+-[]., the optimizer \
+                 takes, running unoptimized",
+                instruction, span.start, span.end
+            );
+            return Ok(None);
+        }
+    };
+    pipeline.run(&mut program)?;
+    Ok(Some(program))
+}
+
+fn test(args: &[String]) -> i32 {
+    let dir = match positional(args).first() {
+        Some(&dir) => Path::new(dir),
+        None => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let max_steps = match number_option(args, "--max-steps", DEFAULT_MAX_STEPS) {
+        Ok(max_steps) => max_steps,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    let results = match golden::run_dir(dir, max_steps) {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!("{}: {}", dir.display(), e);
+            return 1;
+        }
+    };
+
+    for result in results.iter() {
+        println!("{}", result);
+    }
+    let failed = results.iter().filter(|r| !r.passed()).count();
+    println!("{} passed, {} failed", results.len() - failed, failed);
+
+    if failed == 0 {
+        0
+    } else {
+        1
+    }
+}
+
+fn batch(args: &[String]) -> i32 {
+    let manifest = match positional(args).first() {
+        Some(&manifest) => Path::new(manifest),
+        None => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+    let (workers, format) = match (
+        number_option(args, "--workers", cores),
+        option(args, "--format")
+            .unwrap_or("json")
+            .parse::<ReportFormat>(),
+    ) {
+        (Ok(workers), Ok(format)) => (workers, format),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    let entries = match batch::load_manifest(manifest) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let results = batch::run_batch(&entries, workers);
+
+    // the report is for tools, people get the summary
+    print!("{}", batch::report(&results, format));
+    for result in results.iter() {
+        eprintln!("{}", result);
+    }
+    let failed = results.iter().filter(|r| !r.passed()).count();
+    eprintln!("{} passed, {} failed", results.len() - failed, failed);
+
+    if failed == 0 {
+        0
+    } else {
+        1
+    }
+}
+
+fn check(args: &[String]) -> i32 {
+    let path = match positional(args).first() {
+        Some(&path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let extensions = match option(args, "--extensions")
+        .unwrap_or("")
+        .parse::<Extensions>()
+    {
+        Ok(extensions) => extensions,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let max_timelines = match option(args, "--max-timelines").map(str::parse::<usize>) {
+        None => None,
+        Some(Ok(max_timelines)) => Some(max_timelines),
+        Some(Err(_)) => {
+            eprintln!("--max-timelines expects a number");
+            return 2;
+        }
+    };
+    let raw_program = match fs::read_to_string(path) {
+        Ok(raw_program) => raw_program,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+    let (tokens, spans) = match parse_with_spans(raw_program.as_str(), extensions) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+    if !flag(args, "--analyze") {
+        println!("{}: ok", path);
+        return 0;
+    }
+
+    let analysis = termination::analyze(&tokens);
+    println!("{}: {}", path, analysis.verdict);
+    for report in analysis.loops.iter() {
+        if let LoopVerdict::Infinite | LoopVerdict::InfiniteIfEntered = report.verdict {
+            println!(
+                "{}: loop at instruction {} (bytes {}..{}) is {}",
+                path,
+                report.start,
+                spans[report.start].start,
+                spans[report.end].end,
+                report.verdict
+            );
+        }
+    }
+
+    let bound = timelines::max_timelines(&tokens);
+    match bound {
+        TimelineBound::Unbounded { instruction } | TimelineBound::Unknown { instruction } => {
+            println!(
+                "{}: {} (bytes {}..{})",
+                path, bound, spans[instruction].start, spans[instruction].end
+            )
+        }
+        TimelineBound::AtMost(_) => println!("{}: {}", path, bound),
+    }
+    match tape::tape_bounds(&tokens) {
+        Ok(bounds) => {
+            println!("{}: the root timeline reaches {}", path, bounds.root);
+            for (site, range) in bounds.spawned.iter() {
+                println!(
+                    "{}: the timelines instruction {} (bytes {}..{}) makes reach {}",
+                    path, site, spans[*site].start, spans[*site].end, range
+                );
+            }
+        }
+        Err(instruction) => println!(
+            "{}: can't tell which cells are reached, instruction {} (bytes {}..{}) is beyond \
+             the analysis",
+            path, instruction, spans[instruction].start, spans[instruction].end
+        ),
+    }
+    // an upper bound over the limit doesn't mean a run gets there, only that it might
+    let within = match (max_timelines, bound) {
+        (None, _) => true,
+        (Some(max), TimelineBound::AtMost(at_most)) if at_most <= max => true,
+        (Some(max), _) => {
+            println!("{}: may go over --max-timelines {}", path, max);
+            false
+        }
+    };
+
+    match analysis.verdict {
+        Verdict::NeverStops { .. } => 1,
+        _ if !within => 1,
+        _ => 0,
+    }
+}
+
+fn stats(args: &[String]) -> i32 {
+    let path = match positional(args).first() {
+        Some(&path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let extensions = match option(args, "--extensions")
+        .unwrap_or("")
+        .parse::<Extensions>()
+    {
+        Ok(extensions) => extensions,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let raw_program = match fs::read_to_string(path) {
+        Ok(raw_program) => raw_program,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+    let (tokens, spans) = match parse_with_spans(raw_program.as_str(), extensions) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+
+    let stats = stats::stats(&tokens);
+    println!(
+        "{}: {} instructions in {} bytes",
+        path,
+        stats.instructions,
+        raw_program.len()
+    );
+    if !stats.counts.is_empty() {
+        let counts = stats
+            .counts
+            .iter()
+            .map(|(symbol, count)| format!("{} {}", symbol, count))
+            .collect::<Vec<_>>();
+        println!("{}: {}", path, counts.join(", "));
+    }
+    println!("{}: loops nest {} deep", path, stats.max_nesting);
+    println!(
+        "{}: {} spawn sites, {} await sites, {} rewind sites",
+        path, stats.spawn_sites, stats.await_sites, stats.rewind_sites
+    );
+    match stats.tape {
+        Ok(reach) => match reach.cells() {
+            Some(cells) => println!("{}: needs {} cells of tape, {}", path, cells, reach),
+            None => println!(
+                "{}: can reach {}, there's no bound on the tape",
+                path, reach
+            ),
+        },
+        Err(instruction) => println!(
+            "{}: can't tell how much tape it needs, instruction {} (bytes {}..{}) is beyond \
+             the analysis",
+            path, instruction, spans[instruction].start, spans[instruction].end
+        ),
+    }
+    0
+}
+
+fn explore(args: &[String]) -> i32 {
+    let path = match positional(args).first() {
+        Some(&path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let (input, max_steps, max_paths) = match (
+        number_option(args, "--input-length", 2),
+        number_option(args, "--max-steps", 10_000),
+        number_option(args, "--max-paths", 1_000),
+    ) {
+        (Ok(input), Ok(max_steps), Ok(max_paths)) => (input, max_steps, max_paths),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let extensions = match option(args, "--extensions")
+        .unwrap_or("")
+        .parse::<Extensions>()
+    {
+        Ok(extensions) => extensions,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let raw_program = match fs::read_to_string(path) {
+        Ok(raw_program) => raw_program,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+    let (tokens, spans) = match parse_with_spans(raw_program.as_str(), extensions) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+    let mut program = match Program::lower(&tokens) {
+        Ok(program) => program,
+        Err(instruction) => {
+            eprintln!(
+                "{}: instruction {} (bytes {}..{}) isn't one of the <>+-[]., that are explored",
+                path, instruction, spans[instruction].start, spans[instruction].end
+            );
+            return 1;
+        }
+    };
+    // fewer ops and a clear loop on an unknown taken at once instead of split 256 ways
+    if let Err(e) = Pipeline::level(1).run(&mut program) {
+        eprintln!("{}: {}", path, e);
+        return 1;
+    }
+
+    let bounds = symbolic::Bounds {
+        input,
+        max_steps,
+        max_paths,
+    };
+    let exploration = symbolic::explore(&program, bounds);
+    let mut seen = vec![];
+    for ending in exploration.endings.iter() {
+        let output = ending.describe_output();
+        if seen.contains(&(output.clone(), ending.halted)) {
+            continue;
+        }
+        let example = ending
+            .input
+            .iter()
+            .map(|unknown| unknown.example())
+            .collect::<Vec<_>>();
+        println!(
+            "{}: {}{} when {}, like the input {:?}",
+            path,
+            output,
+            if ending.halted { "" } else { " and on" },
+            ending.describe_input(),
+            example
+        );
+        seen.push((output, ending.halted));
+    }
+    let cut_off = exploration.endings.iter().filter(|e| !e.halted).count();
+    println!(
+        "{}: {} ways, {} cut off by --max-steps, {} not followed past --max-paths",
+        path,
+        exploration.endings.len(),
+        cut_off,
+        exploration.unexplored
+    );
+    0
+}
+
+fn specialize(args: &[String]) -> i32 {
+    let path = match positional(args).first() {
+        Some(&path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let max_steps = match number_option(args, "--max-steps", DEFAULT_MAX_STEPS) {
+        Ok(max_steps) => max_steps,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let extensions = match option(args, "--extensions")
+        .unwrap_or("")
+        .parse::<Extensions>()
+    {
+        Ok(extensions) => extensions,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let raw_program = match fs::read_to_string(path) {
+        Ok(raw_program) => raw_program,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+    let input = match program_input(args) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let engine = match Engine::from_source_with(raw_program.as_str(), &input, extensions) {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+
+    let residual = match partial::specialize(&engine, max_steps) {
+        Ok(residual) => residual,
+        Err(instruction) => {
+            let span = &engine.context.spans[instruction];
+            eprintln!(
+                "{}: instruction {} (bytes {}..{}) isn't one of the <>+-[]., that run ahead",
+                path, instruction, span.start, span.end
+            );
+            return 1;
+        }
+    };
+    print!("{}", residual.source);
+    match residual.resumes_at {
+        None => eprintln!(
+            "{}: halted after {} steps, what's left only writes the output",
+            path, residual.steps
+        ),
+        Some(instruction) => eprintln!(
+            "{}: stopped after {} steps, what's left carries on from instruction {} (byte {}) \
+             reading the input from character {} on",
+            path,
+            residual.steps,
+            instruction,
+            engine.context.spans[instruction].start,
+            residual.input_read
+        ),
+    }
+    0
+}
+
+fn obfuscate(args: &[String]) -> i32 {
+    let path = match positional(args).first() {
+        Some(&path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let (seed, inputs, max_steps) = match (
+        number_option(args, "--seed", 0),
+        number_option(args, "--inputs", 32),
+        number_option(args, "--max-steps", 100_000),
+    ) {
+        (Ok(seed), Ok(inputs), Ok(max_steps)) => (seed, inputs, max_steps),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let extensions = match option(args, "--extensions")
+        .unwrap_or("")
+        .parse::<Extensions>()
+    {
+        Ok(extensions) => extensions,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let raw_program = match fs::read_to_string(path) {
+        Ok(raw_program) => raw_program,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+    let engine = match Engine::from_source_with(raw_program.as_str(), "", extensions) {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+
+    let obfuscated = match obfuscate::obfuscate(&engine, seed) {
+        Ok(obfuscated) => obfuscated,
+        Err(instruction) => {
+            let span = &engine.context.spans[instruction];
+            eprintln!(
+                "{}: instruction {} (bytes {}..{}) spawns a timeline, programs of more than one \
+                 aren't obfuscated",
+                path, instruction, span.start, span.end
+            );
+            return 1;
+        }
+    };
+    let inputs = obfuscate::sample_inputs(inputs, seed);
+    match obfuscate::verify(
+        &raw_program,
+        &obfuscated,
+        extensions,
+        inputs.iter().map(String::as_str),
+        max_steps,
+    ) {
+        Ok(None) => {
+            print!("{}", obfuscated);
+            0
+        }
+        Ok(Some(divergence)) => {
+            eprintln!(
+                "{}: the obfuscated program doesn't do the same, left is the original\n{}",
+                path, divergence
+            );
+            1
+        }
+        Err(e) => {
+            eprintln!("{}: the obfuscated program doesn't parse: {}", path, e);
+            1
+        }
+    }
+}
+
+fn lsp(args: &[String]) -> i32 {
+    let extensions = match option(args, "--extensions")
+        .unwrap_or("all")
+        .parse::<Extensions>()
+    {
+        Ok(extensions) => extensions,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let stdin = io::stdin();
+    match lsp::serve(stdin.lock(), io::stdout(), extensions) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn grammar(args: &[String]) -> i32 {
+    match (
+        option(args, "--format")
+            .unwrap_or("textmate")
+            .parse::<GrammarFormat>(),
+        option(args, "--extensions")
+            .unwrap_or("")
+            .parse::<Extensions>(),
+    ) {
+        (Ok(format), Ok(extensions)) => {
+            print!("{}", grammar::grammar(format, extensions));
+            0
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}", e);
+            2
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+fn serve(args: &[String]) -> i32 {
+    let mut ceiling = bf5d::server::CEILING;
+    let max_steps = match number_option(args, "--max-steps", ceiling.steps.unwrap_or(0)) {
+        Ok(max_steps) => max_steps,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let address = option(args, "--address").unwrap_or("127.0.0.1:8000");
+    ceiling.steps = Some(max_steps);
+    eprintln!("serving on http://{}", address);
+    match bf5d::server::serve(address, ceiling) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}: {}", address, e);
+            1
+        }
+    }
+}
+
+#[cfg(not(feature = "server"))]
+fn serve(_: &[String]) -> i32 {
+    eprintln!("built without the server feature, try cargo run --features server");
+    2
+}
+
+#[cfg(unix)]
+fn daemon(args: &[String]) -> i32 {
+    let max_steps = match number_option(args, "--max-steps", DEFAULT_MAX_STEPS) {
+        Ok(max_steps) => max_steps,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let socket = match option(args, "--socket") {
+        Some(socket) => socket.into(),
+        None => env::temp_dir().join("bf5d.sock"),
+    };
+    let ceiling = Limits {
+        steps: Some(max_steps),
+        ..Limits::default()
+    };
+    eprintln!("listening on {}", socket.display());
+    match bf5d::daemon::serve(&socket, ceiling) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}: {}", socket.display(), e);
+            1
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn daemon(_: &[String]) -> i32 {
+    eprintln!("the daemon listens on a Unix socket, there are none on this platform");
+    2
+}
+
+#[cfg(feature = "kernel")]
+fn kernel(args: &[String]) -> i32 {
+    use bf5d::kernel::{self, ConnectionInfo};
+
+    let path = match positional(args).first() {
+        Some(&path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let extensions = match option(args, "--extensions")
+        .unwrap_or("")
+        .parse::<Extensions>()
+    {
+        Ok(extensions) => extensions,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let connection = match fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| ConnectionInfo::parse(&text))
+    {
+        Ok(connection) => connection,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+    match kernel::serve(&connection, extensions) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+#[cfg(not(feature = "kernel"))]
+fn kernel(_: &[String]) -> i32 {
+    eprintln!("built without the kernel feature, try cargo run --features kernel");
+    2
+}
+
+// the engine only looks at the flag between rounds, a second Ctrl-C doesn't wait for that
+#[cfg(not(target_arch = "wasm32"))]
+fn catch_interrupt(interrupted: Arc<AtomicBool>) {
+    let caught = ctrlc::set_handler(move || {
+        if interrupted.swap(true, Ordering::SeqCst) {
+            process::exit(INTERRUPTED);
+        }
+    });
+    if let Err(e) = caught {
+        eprintln!("can't catch Ctrl-C: {}", e);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn catch_interrupt(_: Arc<AtomicBool>) {}
+
+// the default message, plus a hint that the state is on its way to disk
+fn report_panics() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        eprintln!("bf5d crashed, this is a bug, saving the state for the report");
+    }));
+}
+
+// the engine as the panic left it, with the command that got it there
+fn crash_dump(args: &[String], engine: &Engine, payload: &(dyn Any + Send)) {
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    let command = env::args()
+        .take(1)
+        .chain(Some("run".to_string()))
+        .chain(args.iter().cloned())
+        .map(|arg| format!("{:?}", arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let path = env::temp_dir().join(format!("bf5d-crash-{}.txt", process::id()));
+    let dump = format!(
+        "panic: {}\nreproduce with: {}\n\n{:#?}\n",
+        message, command, engine
+    );
+    match fs::write(&path, dump) {
+        Ok(()) => eprintln!(
+            "wrote {}, please attach it to a bug report\nreproduce with: {}",
+            path.display(),
+            command
+        ),
+        Err(e) => eprintln!("{}: {}", path.display(), e),
+    }
+}
+
+// options that don't take a value
+const FLAGS: &[&str] = &[
+    "--deterministic",
+    "--analyze",
+    "--explain",
+    "--keep-dead",
+    "--watch",
+];
+
+// arguments that aren't options or option values
+fn positional(args: &[String]) -> Vec<&str> {
+    let mut positional = vec![];
+    let mut iter = own(args).iter();
+    while let Some(arg) = iter.next() {
+        if FLAGS.contains(&arg.as_str()) {
+            continue;
+        } else if arg.starts_with("--") {
+            iter.next();
+        } else {
+            positional.push(arg.as_str());
+        }
+    }
+    positional
+}
+
+// what comes before `--`, the arguments after it are the program's
+fn own(args: &[String]) -> &[String] {
+    let end = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    &args[..end]
+}
+
+fn flag(args: &[String], name: &str) -> bool {
+    own(args).iter().any(|arg| arg == name)
+}
+
+fn option<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let args = own(args);
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+// what `--input`, `--input-env` or the arguments after `--` give the program to read
+fn program_input(args: &[String]) -> Result<String, String> {
+    let rest = args
+        .iter()
+        .position(|arg| arg == "--")
+        .map(|i| &args[i + 1..]);
+    match (option(args, "--input"), option(args, "--input-env"), rest) {
+        (Some(text), None, None) => Ok(text.to_string()),
+        (None, Some(name), None) => env::var(name).map_err(|e| format!("{}: {}", name, e)),
+        (None, None, Some(rest)) => Ok(rest.join(option(args, "--separator").unwrap_or(" "))),
+        (None, None, None) => Ok(String::new()),
+        _ => Err("--input, --input-env and -- give the input three ways, pick one".to_string()),
+    }
+}
+
+fn number_option<T: FromStr>(args: &[String], name: &str, default: T) -> Result<T, String> {
+    match option(args, name) {
+        Some(value) => value
+            .parse()
+            .map_err(|_| format!("{} expects a number, got {:?}", name, value)),
+        None => Ok(default),
+    }
+}
@@ -0,0 +1,227 @@
+// `bf5d-cli daemon`: programs stay parsed between runs, for editors and test runners that run many
+// short ones and would otherwise pay for a process start each time.
+//
+// Clients connect to a Unix socket and write one JSON request per line, the ones `protocol`
+// describes. Each gets one line back: the report, the `status` the playground server would have
+// answered with, whether the program was `cached` already, and the request's `id` if it had one.
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    interpreter::{bf5d::Engine, sandbox::Limits},
+    parser::types::Extensions,
+    protocol::{error, prepare_with, report, CRASHED},
+};
+
+// parsed programs kept around, the one run longest ago makes room
+const CACHE_SIZE: usize = 256;
+// a longer line closes the connection, it isn't a program someone is editing
+const MAX_LINE: usize = 1 << 20;
+
+/// Answers on the socket at `path` until the process is stopped.
+///
+/// A socket file nobody answers on is what a daemon that was killed leaves behind, it's replaced.
+pub fn serve(path: &Path, ceiling: Limits) -> io::Result<()> {
+    if path.exists() && UnixStream::connect(path).is_err() {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let cache = Arc::new(Mutex::new(Cache::default()));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let ceiling = ceiling.clone();
+        let cache = cache.clone();
+        thread::spawn(move || handle_connection(stream, &ceiling, &cache));
+    }
+    Ok(())
+}
+
+fn handle_connection(stream: UnixStream, ceiling: &Limits, cache: &Mutex<Cache>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    loop {
+        let mut line = String::new();
+        if reader
+            .by_ref()
+            .take(MAX_LINE as u64 + 1)
+            .read_line(&mut line)?
+            == 0
+        {
+            return Ok(());
+        }
+        if line.len() > MAX_LINE {
+            let answer = with_status(413, error("the request is too large"), &Value::Null);
+            return writeln!(writer, "{}", answer);
+        }
+        if !line.trim().is_empty() {
+            writeln!(writer, "{}", answer(&line, ceiling, cache))?;
+        }
+    }
+}
+
+/// The line to answer the request `line` with.
+pub fn answer(line: &str, ceiling: &Limits, cache: &Mutex<Cache>) -> Value {
+    let body = match serde_json::from_str::<Value>(line) {
+        Ok(body) => body,
+        Err(_) => return with_status(400, error("the line isn't JSON"), &Value::Null),
+    };
+
+    let mut cached = false;
+    // parsing runs under it too, a program that trips the parser up is as much a crash
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        let prepared = prepare_with(&body, ceiling, |program, extensions| {
+            if let Some(engine) = cache.lock().unwrap().get(program, extensions) {
+                cached = true;
+                return Ok(engine);
+            }
+            // parsed outside the lock, the other connections don't wait on it
+            let engine = Engine::from_source_with(program, "", extensions)?;
+            cache
+                .lock()
+                .unwrap()
+                .insert(program, extensions, engine.clone());
+            Ok(engine)
+        });
+        match prepared {
+            Ok(mut sandbox) => {
+                let result = sandbox.run();
+                (200, report(&sandbox.engine, &result))
+            }
+            Err(rejected) => rejected,
+        }
+    }));
+    let (status, answer) = outcome.unwrap_or_else(|_| (500, error(CRASHED)));
+
+    let mut answer = with_status(status, answer, &body["id"]);
+    answer["cached"] = cached.into();
+    answer
+}
+
+fn with_status(status: u16, answer: Value, id: &Value) -> Value {
+    let mut fields = json!({ "status": status });
+    if !id.is_null() {
+        fields["id"] = id.clone();
+    }
+    if let Value::Object(mut rest) = answer {
+        fields.as_object_mut().unwrap().append(&mut rest);
+    }
+    fields
+}
+
+/// Engines that haven't run yet, by the program and extensions they were parsed from.
+#[derive(Debug, Default)]
+pub struct Cache {
+    engines: HashMap<(String, Extensions), (Engine, usize)>,
+    // counts lookups, the number next to an engine is when it was last used
+    clock: usize,
+}
+
+impl Cache {
+    pub fn get(&mut self, program: &str, extensions: Extensions) -> Option<Engine> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.engines
+            .get_mut(&(program.to_string(), extensions))
+            .map(|(engine, used)| {
+                *used = clock;
+                engine.clone()
+            })
+    }
+
+    pub fn insert(&mut self, program: &str, extensions: Extensions, engine: Engine) {
+        if self.engines.len() >= CACHE_SIZE {
+            let oldest = self
+                .engines
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.engines.remove(&oldest);
+            }
+        }
+        self.engines
+            .insert((program.to_string(), extensions), (engine, self.clock));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(program: &str) -> String {
+        json!({ "program": program, "id": 7 }).to_string()
+    }
+
+    #[test]
+    fn the_engine_used_longest_ago_makes_room() {
+        let mut cache = Cache::default();
+        let programs = (0..=CACHE_SIZE).map(|i| "+".repeat(i)).collect::<Vec<_>>();
+        // the way `answer` uses it, every insert follows a lookup that missed
+        for program in &programs[..CACHE_SIZE] {
+            assert!(cache.get(program, Extensions::default()).is_none());
+            let engine = Engine::from_source(program, "").unwrap();
+            cache.insert(program, Extensions::default(), engine);
+        }
+        assert!(cache.get(&programs[0], Extensions::default()).is_some());
+        let engine = Engine::from_source(&programs[CACHE_SIZE], "").unwrap();
+        cache.insert(&programs[CACHE_SIZE], Extensions::default(), engine);
+
+        assert!(cache.get(&programs[1], Extensions::default()).is_none());
+        assert!(cache.get(&programs[0], Extensions::default()).is_some());
+        assert!(cache
+            .get(&programs[CACHE_SIZE], Extensions::default())
+            .is_some());
+        assert!(cache.get(&programs[0], Extensions::all()).is_none());
+    }
+
+    #[test]
+    fn says_whether_the_program_was_cached() {
+        let cache = Mutex::new(Cache::default());
+        let first = answer(&request("+."), &Limits::default(), &cache);
+        assert_eq!(first["status"], 200);
+        assert_eq!(first["id"], 7);
+        assert_eq!(first["cached"], false);
+        let second = answer(&request("+."), &Limits::default(), &cache);
+        assert_eq!(second["cached"], true);
+        assert_eq!(first["output"], second["output"]);
+    }
+
+    #[test]
+    fn answers_a_line_that_isnt_json_with_400() {
+        let cache = Mutex::new(Cache::default());
+        let answer = answer("{\"program\":", &Limits::default(), &cache);
+        assert_eq!(answer["status"], 400);
+        assert!(answer["error"].is_string());
+    }
+
+    #[test]
+    fn closes_the_connection_on_a_line_too_long() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let handler = thread::spawn(move || {
+            handle_connection(server, &Limits::default(), &Mutex::new(Cache::default()))
+        });
+        client.write_all(request("+.").as_bytes()).unwrap();
+        client.write_all(b"\n").unwrap();
+        client.write_all(&vec![b'+'; MAX_LINE + 1]).unwrap();
+
+        let mut lines = BufReader::new(client).lines();
+        let mut answer = || serde_json::from_str::<Value>(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(answer()["status"], 200);
+        assert_eq!(answer()["status"], 413);
+        assert!(lines.next().is_none());
+        handler.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,331 @@
+// Runs an `Engine` on its own thread and talks to it over channels.
+// Needs a target with thread support, the web front-end drives the engine from an `Interval`.
+use std::{
+    collections::BTreeSet,
+    ops::Range,
+    sync::mpsc::{self, Receiver, SendError, Sender, TryRecvError},
+    thread::{self, JoinHandle},
+};
+
+use super::{
+    bf5d::Engine,
+    error::{RuntimeError, RuntimeErrorKind},
+    types::{Deadlock, ID},
+};
+use crate::parser::validate::Violation;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActorCommand {
+    Run,
+    Pause,
+    Step,
+    SetBreakpoint(usize),
+    ClearBreakpoint(usize),
+    FeedInput(String),
+    /// Answered with `ActorEvent::Cells`, see `Engine::cells`.
+    QueryCells(Range<isize>),
+    Shutdown,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActorEvent {
+    Output(String),
+    Cells(Vec<(ID, Vec<u8>)>),
+    Stopped(StopReason),
+    Completed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    Paused,
+    Stepped,
+    Breakpoint { id: ID, instruction: usize },
+    Deadlock(Deadlock),
+    Error(RuntimeError),
+}
+
+pub struct EngineActor {
+    commands: Sender<ActorCommand>,
+    events: Receiver<ActorEvent>,
+    // taken by `shutdown`, dropping the actor without it joins the worker too
+    handle: Option<JoinHandle<Engine>>,
+}
+
+impl EngineActor {
+    /// Moves `engine` onto a new thread, it stays paused until a `Run` or `Step` arrives.
+    ///
+    /// The tokens are validated first, so an engine that doesn't come from the parser never
+    /// starts with an index the worker would step into.
+    pub fn spawn(engine: Engine) -> Result<Self, Vec<Violation>> {
+        engine.validate()?;
+        let (commands, command_rx) = mpsc::channel();
+        let (event_tx, events) = mpsc::channel();
+        let handle = thread::spawn(move || Worker::new(engine, command_rx, event_tx).run());
+
+        Ok(EngineActor {
+            commands,
+            events,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn send(&self, command: ActorCommand) -> Result<(), SendError<ActorCommand>> {
+        self.commands.send(command)
+    }
+
+    pub fn events(&self) -> &Receiver<ActorEvent> {
+        &self.events
+    }
+
+    /// Stops the worker and hands back the engine in whatever state it was left.
+    pub fn shutdown(mut self) -> thread::Result<Engine> {
+        self.stop()
+            .expect("only `shutdown` and `drop` take the handle")
+    }
+
+    fn stop(&mut self) -> Option<thread::Result<Engine>> {
+        // the worker may have exited already, joining tells us how it went
+        let _ = self.commands.send(ActorCommand::Shutdown);
+        self.handle.take().map(JoinHandle::join)
+    }
+}
+
+impl Drop for EngineActor {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+struct Worker {
+    engine: Engine,
+    commands: Receiver<ActorCommand>,
+    events: Sender<ActorEvent>,
+    breakpoints: BTreeSet<usize>,
+    running: bool,
+    // bytes of `program_output` already sent out
+    emitted: usize,
+}
+
+impl Worker {
+    fn new(engine: Engine, commands: Receiver<ActorCommand>, events: Sender<ActorEvent>) -> Self {
+        Worker {
+            emitted: engine.context.program_output.len(),
+            engine,
+            commands,
+            events,
+            breakpoints: BTreeSet::new(),
+            running: false,
+        }
+    }
+
+    fn run(mut self) -> Engine {
+        loop {
+            // only block on the channel while paused
+            let command = if self.running {
+                match self.commands.try_recv() {
+                    Ok(command) => Some(command),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match self.commands.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => break,
+                }
+            };
+
+            match command {
+                Some(ActorCommand::Shutdown) => break,
+                Some(command) => self.handle(command),
+                None => {
+                    self.advance();
+                }
+            }
+        }
+
+        self.engine
+    }
+
+    fn handle(&mut self, command: ActorCommand) {
+        use ActorCommand::*;
+        match command {
+            Run => {
+                if self.engine.is_halted() {
+                    self.emit(ActorEvent::Completed);
+                } else {
+                    self.running = true;
+                }
+            }
+            Pause => {
+                self.running = false;
+                self.emit(ActorEvent::Stopped(StopReason::Paused));
+            }
+            Step => {
+                self.running = false;
+                if self.advance() {
+                    self.emit(ActorEvent::Stopped(StopReason::Stepped));
+                }
+            }
+            SetBreakpoint(instruction) => {
+                self.breakpoints.insert(instruction);
+            }
+            ClearBreakpoint(instruction) => {
+                self.breakpoints.remove(&instruction);
+            }
+            FeedInput(input) => self.engine.context.program_input.push_str(input.as_str()),
+            QueryCells(range) => self.emit(ActorEvent::Cells(self.engine.cells(range))),
+            Shutdown => (),
+        }
+    }
+
+    /// Steps once, returns `false` if the engine stopped on its own (completion, error or
+    /// breakpoint).
+    fn advance(&mut self) -> bool {
+        let stepped = self.engine.step();
+        self.flush_output();
+
+        if let Err(e) = stepped {
+            self.running = false;
+            self.emit(ActorEvent::Stopped(match e.kind {
+                RuntimeErrorKind::Deadlock(deadlock) => StopReason::Deadlock(deadlock),
+                _ => StopReason::Error(e),
+            }));
+            return false;
+        }
+
+        if self.engine.is_halted() {
+            self.running = false;
+            self.emit(ActorEvent::Completed);
+            return false;
+        }
+
+        let hit = self
+            .engine
+            .timelines
+            .iter()
+            .find(|t| self.breakpoints.contains(&t.instruction_pointer))
+            .map(|t| (t.id, t.instruction_pointer));
+
+        if let Some((id, instruction)) = hit {
+            self.running = false;
+            self.emit(ActorEvent::Stopped(StopReason::Breakpoint {
+                id,
+                instruction,
+            }));
+            return false;
+        }
+
+        true
+    }
+
+    fn flush_output(&mut self) {
+        let output = &self.engine.context.program_output;
+        if output.len() > self.emitted {
+            let chunk = output[self.emitted..].to_string();
+            self.emitted = output.len();
+            self.emit(ActorEvent::Output(chunk));
+        }
+    }
+
+    fn emit(&self, event: ActorEvent) {
+        // nobody listening is not our problem, the command channel decides when to stop
+        let _ = self.events.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        interpreter::types::BF5DContext,
+        parser::types::{JumpType, Token},
+    };
+
+    fn actor(source: &str) -> EngineActor {
+        EngineActor::spawn(Engine::from_source(source, "").unwrap()).unwrap()
+    }
+
+    fn next(actor: &EngineActor) -> ActorEvent {
+        actor
+            .events()
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the worker answers")
+    }
+
+    #[test]
+    fn refuses_tokens_that_dont_validate() {
+        let mut context = BF5DContext::new();
+        context.set_tokens(vec![Token::Jump {
+            type_: JumpType::IfZero,
+            index: 5,
+        }]);
+        let violations = EngineActor::spawn(Engine::new(context)).err().unwrap();
+        assert_eq!(violations[0].instruction, 0);
+    }
+
+    #[test]
+    fn steps_one_round_at_a_time() {
+        let actor = actor("+.");
+        actor.send(ActorCommand::Step).unwrap();
+        assert_eq!(next(&actor), ActorEvent::Stopped(StopReason::Stepped));
+        actor.send(ActorCommand::Step).unwrap();
+        assert_eq!(next(&actor), ActorEvent::Output("\u{1}".to_string()));
+        assert_eq!(next(&actor), ActorEvent::Completed);
+    }
+
+    #[test]
+    fn runs_to_completion() {
+        let actor = actor("+++.>++.");
+        actor.send(ActorCommand::Run).unwrap();
+        let mut output = String::new();
+        loop {
+            match next(&actor) {
+                ActorEvent::Output(chunk) => output.push_str(&chunk),
+                ActorEvent::Completed => break,
+                event => panic!("unexpected {:?}", event),
+            }
+        }
+        assert_eq!(output, "\u{3}\u{2}");
+    }
+
+    #[test]
+    fn answers_cell_queries() {
+        let actor = actor("+>++");
+        for _ in 0..3 {
+            actor.send(ActorCommand::Step).unwrap();
+            assert_eq!(next(&actor), ActorEvent::Stopped(StopReason::Stepped));
+        }
+        actor.send(ActorCommand::QueryCells(0..3)).unwrap();
+        match next(&actor) {
+            ActorEvent::Cells(cells) => assert_eq!(cells[0].1, vec![1, 1, 0]),
+            event => panic!("unexpected {:?}", event),
+        }
+    }
+
+    #[test]
+    fn shutdown_hands_back_the_engine() {
+        let actor = actor("+[]");
+        actor.send(ActorCommand::Step).unwrap();
+        assert_eq!(next(&actor), ActorEvent::Stopped(StopReason::Stepped));
+        actor.send(ActorCommand::Run).unwrap();
+        let engine = actor.shutdown().unwrap();
+        assert!(!engine.is_halted());
+        assert_eq!(engine.timelines[0].cell(0), 1);
+    }
+
+    #[test]
+    fn dropping_stops_the_worker() {
+        let mut engine = Engine::from_source("+[]", "").unwrap();
+        let (rounds, rounds_rx) = mpsc::channel();
+        engine.on_every(1, move |_| rounds.send(()).is_ok());
+        let actor = EngineActor::spawn(engine).unwrap();
+        actor.send(ActorCommand::Run).unwrap();
+        drop(actor);
+
+        // the engine and with it the callback are gone once `drop` returns
+        while rounds_rx.try_recv().is_ok() {}
+        assert_eq!(rounds_rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+}
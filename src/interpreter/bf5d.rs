@@ -1,6 +1,658 @@
-// use std::num::Wrapping;
+use std::{fmt, ops::Range};
 
-// use crate::parser::types::{MoveDirection, Token, UpdateType};
+use super::error::{RuntimeError, RuntimeErrorKind};
+use super::hash;
+use super::outcome::RunOutcome;
+use super::progress::{Progress, Reporter};
+use super::reload::{self, Reload};
+use super::types::{
+    BF5DContext, Birth, Command, CommitPolicy, FuelPolicy, InputRead, OutputChunk, Timeline, ID,
+};
+use crate::parser::{
+    bf5d,
+    types::{BF5DParseError, Extensions, Token},
+    validate::{self, Violation},
+};
 
-// use super::types::{BF5DContext, MutationRecord, Timeline};
+/// Gives every scheduled timeline its turn, then applies their commands.
+///
+/// A turn is up to `context.fuel` instructions, cut short by anything that has to wait for the
+/// other timelines: a command, a timeline that didn't move on, `%`, and `@` or `|` even once they
+/// let it through, so batching never lets a timeline run ahead of a synchronisation point.
+/// Under `FuelPolicy::FairShare` a turn has the fuel the last ones left over on top.
+///
+/// An error leaves the round half done, the timelines and commands before it already ran.
+pub fn step(context: &mut BF5DContext, timelines: &mut Vec<Timeline>) -> Result<(), RuntimeError> {
+    context.collect_timeline_metadata(timelines);
+    context.release_barrier(timelines);
+    context.deadlock = context.find_deadlock(timelines);
+    if let Some(deadlock) = context.deadlock.clone() {
+        let (id, instruction) = deadlock.blocked[0];
+        return Err(context.error(RuntimeErrorKind::Deadlock(deadlock), id, Some(instruction)));
+    }
 
+    let round = context.steps;
+    let mut commands = vec![];
+    for i in context.scheduling.order(timelines, round) {
+        let timeline = &mut timelines[i];
+        if timeline.suspended {
+            continue;
+        } else if timeline.sleeping > 0 {
+            timeline.sleeping -= 1;
+        } else if timeline.runs_in(round) {
+            let fuel = context.fuel.max(1);
+            let allowance = match context.fuel_policy {
+                FuelPolicy::Quantum => fuel,
+                FuelPolicy::FairShare => fuel + timeline.credit,
+            };
+            let mut used = 0;
+            let mut waits = false;
+            for _ in 0..allowance {
+                let instruction_pointer = timeline.instruction_pointer;
+                let token = context.tokens.get(instruction_pointer);
+                let synchronizes = matches!(token, Some(Token::Await) | Some(Token::Barrier));
+                let io = matches!(
+                    token,
+                    Some(Token::Write) | Some(Token::WriteNumber) | Some(Token::Read)
+                );
+                let (_, cmd) = timeline.update(context)?;
+                if io && context.commit == CommitPolicy::OnIo {
+                    timeline.commit();
+                }
+                timeline.steps_executed += 1;
+                context.instructions += 1;
+                used += 1;
+                timeline.last_active_step = Some(round);
+                waits = timeline.instruction_pointer == instruction_pointer;
+                let yields =
+                    synchronizes || !matches!(cmd, Command::None) || waits || timeline.sleeping > 0;
+                commands.push(cmd);
+                if yields {
+                    break;
+                }
+            }
+            // waiting and sleeping aren't turns cut short, there was nothing to run
+            if context.fuel_policy == FuelPolicy::FairShare {
+                timeline.credit = if waits || timeline.sleeping > 0 {
+                    0
+                } else {
+                    allowance - used
+                };
+            }
+        }
+    }
+
+    for cmd in commands {
+        context.execute_command(cmd, timelines)?;
+    }
+    context.steps += 1;
+    if let CommitPolicy::Every(every) = context.commit {
+        if context.steps.is_multiple_of(every.max(1)) {
+            timelines.iter_mut().for_each(Timeline::commit);
+        }
+    }
+    Ok(())
+}
+
+/// Picks a timeline out of an engine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimelineSelector {
+    Id(ID),
+    /// Counting from the root down, the order timelines are laid out in.
+    Position(usize),
+    Root,
+    Bottom,
+}
+
+/// What `Engine::on_every` calls, returning `false` stops the run it's called from.
+pub type IntervalCallback = Box<dyn FnMut(&Engine) -> bool + Send>;
+
+struct Interval {
+    every: usize,
+    // the instruction count it's called at next
+    next: usize,
+    callback: IntervalCallback,
+}
+
+// a clone of an engine starts without them, a callback belongs to the host that set it
+#[derive(Default)]
+struct Intervals(Vec<Interval>);
+
+impl Clone for Intervals {
+    fn clone(&self) -> Self {
+        Intervals::default()
+    }
+}
+
+impl fmt::Debug for Intervals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|interval| interval.every))
+            .finish()
+    }
+}
+
+/// Owns a whole multiverse, for hosts that don't keep the context and timelines apart.
+#[derive(Debug, Clone)]
+pub struct Engine {
+    pub context: BF5DContext,
+    pub timelines: Vec<Timeline>,
+    intervals: Intervals,
+    // a callback returned `false` since the run started
+    interrupted: bool,
+}
+
+impl Engine {
+    pub fn new(context: BF5DContext) -> Self {
+        Engine {
+            context,
+            timelines: vec![Timeline::new()],
+            intervals: Intervals::default(),
+            interrupted: false,
+        }
+    }
+
+    pub fn from_source(raw_program: &str, program_input: &str) -> Result<Self, BF5DParseError> {
+        Self::from_source_with(raw_program, program_input, Extensions::default())
+    }
+
+    pub fn from_source_with(
+        raw_program: &str,
+        program_input: &str,
+        extensions: Extensions,
+    ) -> Result<Self, BF5DParseError> {
+        let (tokens, spans) = bf5d::parse_with_spans(raw_program, extensions)?;
+        let mut context = BF5DContext::new();
+        context.extensions = extensions;
+        context.set_tokens(tokens);
+        context.spans = spans;
+        context.raw_program = raw_program.to_string();
+        context.program_input = program_input.to_string();
+        Ok(Self::new(context))
+    }
+
+    /// Steps a round, then calls the `on_every` callbacks that are due.
+    pub fn step(&mut self) -> Result<(), RuntimeError> {
+        step(&mut self.context, &mut self.timelines)?;
+        self.call_intervals();
+        Ok(())
+    }
+
+    /// Calls `callback` every `every` instructions the timelines run, 0 taken as 1, until the
+    /// engine is dropped; those of a clone start out without it.
+    ///
+    /// It's called between rounds, once for a round that took the count past one or more
+    /// multiples of `every`, so a turn never sees the state half done. A `false` from it makes
+    /// `run` and the like return after the round, as if `max_steps` were used up, for limits of
+    /// the host's own.
+    pub fn on_every(
+        &mut self,
+        every: usize,
+        callback: impl FnMut(&Engine) -> bool + Send + 'static,
+    ) {
+        let every = every.max(1);
+        self.intervals.0.push(Interval {
+            every,
+            next: (self.context.instructions / every + 1) * every,
+            callback: Box::new(callback),
+        });
+    }
+
+    fn call_intervals(&mut self) {
+        let instructions = self.context.instructions;
+        // taken out so they can see the engine
+        let mut intervals = std::mem::take(&mut self.intervals.0);
+        for interval in intervals.iter_mut() {
+            if instructions >= interval.next {
+                interval.next = (instructions / interval.every + 1) * interval.every;
+                if !(interval.callback)(self) {
+                    self.interrupted = true;
+                }
+            }
+        }
+        self.intervals.0 = intervals;
+    }
+
+    pub fn priority(&self, id: ID) -> Option<isize> {
+        self.timelines
+            .iter()
+            .find(|t| t.id == id)
+            .map(|t| t.priority)
+    }
+
+    /// Same as the timeline running `!` or `_` until it gets there, returns whether it exists.
+    ///
+    /// A negative priority skips rounds under every scheduling policy, a positive one only makes
+    /// a difference with `SchedulingPolicy::Priority`, which runs the highest first.
+    pub fn set_priority(&mut self, id: ID, priority: isize) -> bool {
+        match self.timelines.iter_mut().find(|t| t.id == id) {
+            Some(timeline) => {
+                timeline.priority = priority;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn timeline(&self, selector: TimelineSelector) -> Option<&Timeline> {
+        match selector {
+            TimelineSelector::Id(id) => self.timelines.iter().find(|t| t.id == id),
+            TimelineSelector::Position(position) => self.timelines.get(position),
+            TimelineSelector::Root => self.timelines.first(),
+            TimelineSelector::Bottom => self.timelines.last(),
+        }
+    }
+
+    /// `None` only if there is no such timeline, cells never touched read as zero.
+    pub fn cell(&self, selector: TimelineSelector, index: isize) -> Option<u8> {
+        self.timeline(selector).map(|t| t.cell(index))
+    }
+
+    /// The same stretch of tape of every timeline, from the root down.
+    pub fn cells(&self, range: Range<isize>) -> Vec<(ID, Vec<u8>)> {
+        self.timelines
+            .iter()
+            .map(|t| (t.id, range.clone().map(|i| t.cell(i)).collect()))
+            .collect()
+    }
+
+    /// See `hash::state_hash`.
+    pub fn state_hash(&self) -> u64 {
+        hash::state_hash(self)
+    }
+
+    pub fn output_log(&self) -> &[OutputChunk] {
+        &self.context.output_log
+    }
+
+    /// Everything timeline `id` wrote, even if it was killed since.
+    pub fn output_of(&self, id: ID) -> Vec<u8> {
+        self.output_chunks(|chunk| chunk.timeline == id)
+    }
+
+    /// Everything written during the rounds in `steps`.
+    pub fn output_between(&self, steps: Range<usize>) -> Vec<u8> {
+        self.output_chunks(|chunk| steps.contains(&chunk.step))
+    }
+
+    fn output_chunks(&self, filter: impl Fn(&OutputChunk) -> bool) -> Vec<u8> {
+        self.context
+            .output_log
+            .iter()
+            .filter(|chunk| filter(chunk))
+            .flat_map(|chunk| chunk.bytes.iter().copied())
+            .collect()
+    }
+
+    pub fn input_log(&self) -> &[InputRead] {
+        &self.context.input_log
+    }
+
+    /// The `,` that consumed the input character at `offset`, if one did yet.
+    pub fn read_of(&self, offset: usize) -> Option<&InputRead> {
+        self.context
+            .input_log
+            .iter()
+            .find(|read| read.offset == Some(offset))
+    }
+
+    /// Also knows about timelines that were killed since.
+    pub fn parent_of(&self, id: ID) -> Option<ID> {
+        self.birth(id).map(|birth| birth.parent_id)
+    }
+
+    pub fn children_of(&self, id: ID) -> Vec<ID> {
+        self.context
+            .births
+            .iter()
+            .filter(|birth| birth.parent_id == id)
+            .map(|birth| birth.id)
+            .collect()
+    }
+
+    /// From the parent up to the root.
+    pub fn ancestors_of(&self, id: ID) -> Vec<ID> {
+        let mut ancestors = vec![];
+        let mut current = id;
+        while let Some(parent_id) = self.parent_of(current) {
+            ancestors.push(parent_id);
+            current = parent_id;
+        }
+        ancestors
+    }
+
+    /// Depth first, in the order they were spawned.
+    pub fn descendants_of(&self, id: ID) -> Vec<ID> {
+        self.children_of(id)
+            .into_iter()
+            .flat_map(|child| std::iter::once(child).chain(self.descendants_of(child)))
+            .collect()
+    }
+
+    pub fn birth(&self, id: ID) -> Option<&Birth> {
+        self.context.births.iter().find(|birth| birth.id == id)
+    }
+
+    /// Has timelines that are removed kept in `context.dead` from now on, or no longer.
+    pub fn keep_dead_timelines(&mut self, keep: bool) {
+        self.context.keep_dead = keep;
+    }
+
+    /// The live timelines from the root down, then the dead ones kept in the order they went.
+    pub fn all_timelines(&self) -> impl Iterator<Item = &Timeline> {
+        self.timelines.iter().chain(self.context.dead.iter())
+    }
+
+    /// Commits every timeline, for hosts that know the program won't rewind past this point,
+    /// see `Timeline::commit`; `context.commit` has it done as the program runs.
+    pub fn commit(&mut self) {
+        self.timelines.iter_mut().for_each(Timeline::commit);
+    }
+
+    /// Commits one timeline, returns whether it exists.
+    pub fn commit_timeline(&mut self, id: ID) -> bool {
+        match self.timelines.iter_mut().find(|t| t.id == id) {
+            Some(timeline) => {
+                timeline.commit();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Keeps the timeline from running until `resume_timeline`, returns whether it exists.
+    pub fn suspend_timeline(&mut self, id: ID) -> bool {
+        self.set_suspended(id, true)
+    }
+
+    pub fn resume_timeline(&mut self, id: ID) -> bool {
+        self.set_suspended(id, false)
+    }
+
+    fn set_suspended(&mut self, id: ID, suspended: bool) -> bool {
+        match self.timelines.iter_mut().find(|t| t.id == id) {
+            Some(timeline) => {
+                timeline.suspended = suspended;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// See `BF5DContext::make_deterministic`, renumbers the timelines so call it before stepping.
+    pub fn make_deterministic(&mut self, seed: u64) {
+        self.context.make_deterministic(seed);
+        for timeline in self.timelines.iter_mut() {
+            timeline.id = self.context.new_id();
+        }
+    }
+
+    /// Steps until the program halts, fails or `max_steps` is used up, then gives back what it
+    /// wrote, why it stopped and how far it got.
+    pub fn run(&mut self, max_steps: usize) -> RunOutcome {
+        let result = self.run_while(max_steps, |_| true);
+        RunOutcome::of(self, result, self.interrupted)
+    }
+
+    /// Steps until the program halts, fails, `max_steps` is used up or `keep_going` says so, it's
+    /// asked before every step; returns whether it halted.
+    ///
+    /// A callback set with `on_every` can stop it too, the run after that goes on as usual.
+    ///
+    /// Tokens that don't come from the parser are validated before the first step.
+    pub fn run_while(
+        &mut self,
+        max_steps: usize,
+        mut keep_going: impl FnMut(&Engine) -> bool,
+    ) -> Result<bool, RuntimeError> {
+        if self.context.steps == 0 {
+            if let Err(violations) = self.validate() {
+                return Err(self.context.error(
+                    RuntimeErrorKind::InvalidProgram(violations.clone()),
+                    self.timelines.first().map_or(0, |t| t.id),
+                    Some(violations[0].instruction),
+                ));
+            }
+        }
+        self.interrupted = false;
+        for _ in 0..max_steps {
+            if self.is_halted() || self.interrupted || !keep_going(self) {
+                break;
+            }
+            self.step()?;
+        }
+        Ok(self.is_halted())
+    }
+
+    /// Like `run_while`, calling `report` every `every` steps instead of asking.
+    pub fn run_reporting(
+        &mut self,
+        max_steps: usize,
+        every: usize,
+        mut report: impl FnMut(&Progress),
+    ) -> Result<bool, RuntimeError> {
+        let mut reporter = Reporter::new(every);
+        self.run_while(max_steps, |engine| {
+            if let Some(progress) = reporter.poll(engine) {
+                report(&progress);
+            }
+            true
+        })
+    }
+
+    /// What all the timelines take up, the dead ones kept included, see `Timeline::memory_usage`.
+    pub fn memory_usage(&self) -> usize {
+        self.all_timelines().map(Timeline::memory_usage).sum()
+    }
+
+    /// Swaps in the program `raw_program` parses into, with the extensions of this one, each
+    /// timeline going on from where the instruction it was at ended up, see
+    /// `reload::map_instructions`. An engine that hasn't stepped yet starts the new program from
+    /// the top.
+    ///
+    /// The timelines `keep_dead_timelines` kept, the logs and the warnings still count the
+    /// instructions of the old program.
+    pub fn reload(&mut self, raw_program: &str) -> Result<Reload, BF5DParseError> {
+        let (tokens, spans) = bf5d::parse_with_spans(raw_program, self.context.extensions)?;
+        if self.context.steps > 0 {
+            let map = reload::map_instructions(&self.context.tokens, &tokens);
+            let end = self.context.tokens.len();
+            let moved = |instruction: usize| map[instruction.min(end)];
+            for timeline in self.timelines.iter() {
+                let instructions = Some(&timeline.instruction_pointer)
+                    .into_iter()
+                    .chain(timeline.call_stack.iter());
+                for &instruction in instructions {
+                    if moved(instruction).is_none() {
+                        return Ok(Reload::Lost {
+                            timeline: timeline.id,
+                            instruction,
+                        });
+                    }
+                }
+            }
+            for timeline in self.timelines.iter_mut() {
+                timeline.instruction_pointer = moved(timeline.instruction_pointer).unwrap_or(0);
+                for address in timeline.call_stack.iter_mut() {
+                    *address = moved(*address).unwrap_or(0);
+                }
+            }
+        }
+        self.context.set_tokens(tokens);
+        self.context.spans = spans;
+        self.context.raw_program = raw_program.to_string();
+        Ok(Reload::Mapped)
+    }
+
+    /// See `validate::validate`.
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let violations = validate::validate(&self.context.tokens, &self.context.spans);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// The root timeline is never removed, so the program is done once nothing is left to run.
+    pub fn is_halted(&self) -> bool {
+        self.timelines
+            .iter()
+            .all(|t| t.instruction_pointer >= self.context.tokens.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{
+        error::WarningKind,
+        outcome::ExitReason,
+        types::{AwaitPolicy, SpawnIfPolicy, WARNINGS_KEPT},
+    };
+
+    fn run(source: &str, max_steps: usize) -> Engine {
+        let mut engine = Engine::from_source_with(source, "", Extensions::all()).unwrap();
+        engine.run(max_steps);
+        engine
+    }
+
+    // what `source` writes under every await policy, with one and with several instructions a turn
+    fn output_every_way(source: &str) -> Vec<String> {
+        let policies = [
+            AwaitPolicy::BelowDead,
+            AwaitPolicy::BelowPointerless,
+            AwaitPolicy::AllChildren,
+        ];
+        let mut outputs = vec![];
+        for policy in policies.iter() {
+            for fuel in [1, 4].iter() {
+                let mut engine = Engine::from_source_with(source, "", Extensions::all()).unwrap();
+                engine.context.await_policy = *policy;
+                engine.context.fuel = *fuel;
+                let outcome = engine.run(1000);
+                assert!(
+                    outcome.halted(),
+                    "{} {:?}: {:?}",
+                    source,
+                    policy,
+                    outcome.exit
+                );
+                outputs.push(outcome.output);
+            }
+        }
+        outputs.dedup();
+        outputs
+    }
+
+    #[test]
+    fn await_on_a_live_child() {
+        // the child counts its cell down before writing, the parent only goes on after
+        assert_eq!(output_every_way("+(+++[-]:)@+:"), ["0 2 "]);
+    }
+
+    #[test]
+    fn await_on_a_dead_child() {
+        assert_eq!(output_every_way("+(+:)%%%%@+:"), ["2 2 "]);
+    }
+
+    #[test]
+    fn await_on_self() {
+        assert_eq!(output_every_way("@:"), ["0 "]);
+        assert_eq!(output_every_way("+(@)@:"), ["1 "]);
+    }
+
+    #[test]
+    fn await_before_the_metadata_knows_the_timeline() {
+        let mut engine = Engine::from_source("(@)@", "").unwrap();
+        let id = engine.context.new_id();
+        assert!(engine.context.awaiting(id));
+        assert_eq!(engine.run(100).exit, ExitReason::Halted);
+    }
+
+    #[test]
+    fn barrier_released_onto_another_barrier() {
+        for source in ["||", "|+|.", "+(|)|@."] {
+            let mut engine = Engine::from_source_with(source, "", Extensions::all()).unwrap();
+            assert_eq!(engine.run(50).exit, ExitReason::Halted, "{}", source);
+        }
+    }
+
+    #[test]
+    fn spawning_from_the_root_is_no_kill() {
+        let engine = run("+[(-)@].", 1000);
+        assert_eq!(engine.context.warnings, vec![]);
+        let engine = run("+I-)@", 1000);
+        assert_eq!(engine.context.warnings, vec![]);
+    }
+
+    #[test]
+    fn root_kill_warns() {
+        // a goto into the body of a spawn
+        let engine = run("\"a\"(+'a')", 10);
+        let kinds = engine.context.warnings.iter().map(|w| w.kind);
+        assert_eq!(kinds.collect::<Vec<_>>(), [WarningKind::RootKillIgnored]);
+    }
+
+    #[test]
+    fn warnings_are_capped() {
+        let engine = run("+[\"a\"(-'a')]", 10 * WARNINGS_KEPT);
+        assert_eq!(engine.context.warnings.len(), WARNINGS_KEPT);
+        assert!(engine.context.warning_count() > WARNINGS_KEPT);
+    }
+
+    #[test]
+    fn splitting_copies_the_pointer_in_place() {
+        let engine = run(">>*<", 10);
+        assert_eq!(engine.timelines[0].pointers, [1, 1]);
+        // both pointers add to the cell they share
+        let engine = run("*+=:", 10);
+        assert_eq!(engine.context.program_output, "2 ");
+    }
+
+    #[test]
+    fn spawn_if_under_each_policy() {
+        // the child hands the root a second pointer, on the zero cell right of the first
+        let source = "+(>^)@I:)";
+        let output = |policy| {
+            let mut engine = Engine::from_source_with(source, "", Extensions::all()).unwrap();
+            engine.context.spawn_if = policy;
+            let outcome = engine.run(100);
+            assert_eq!(outcome.exit, ExitReason::Halted);
+            outcome.output
+        };
+        assert_eq!(output(SpawnIfPolicy::Any), "1 0 ");
+        assert_eq!(output(SpawnIfPolicy::All), "");
+    }
+
+    #[test]
+    fn a_busy_timeline_gets_no_more_than_its_share() {
+        // the child spins, the root keeps stopping to spawn one that's gone at once
+        let source = format!("(+[]){}", "()+++++++".repeat(400));
+        let behind = |policy| {
+            let mut engine = Engine::from_source_with(&source, "", Extensions::all()).unwrap();
+            engine.context.fuel = 4;
+            engine.context.fuel_policy = policy;
+            engine.run(300);
+            let busy = engine.context.births[0].id;
+            let executed = |id| {
+                let timeline = engine.timelines.iter().find(|t| t.id == id).unwrap();
+                timeline.steps_executed as isize
+            };
+            executed(busy) - executed(engine.timelines[0].id)
+        };
+        // falling further behind with every stop, against never more than a turn's fuel off
+        assert!(behind(FuelPolicy::Quantum) > 100);
+        assert!(behind(FuelPolicy::FairShare).abs() <= 4);
+    }
+
+    #[test]
+    fn moving_down_from_the_bottom_keeps_the_pointers() {
+        let mut engine = Engine::from_source_with("(v+:)", "", Extensions::all()).unwrap();
+        let outcome = engine.run(100);
+        assert_eq!(outcome.exit, ExitReason::Halted);
+        assert_eq!(outcome.output, "1 ");
+        let kinds = engine.context.warnings.iter().map(|w| w.kind);
+        assert_eq!(kinds.collect::<Vec<_>>(), [WarningKind::NoTimelineBelow]);
+    }
+}
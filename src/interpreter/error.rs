@@ -0,0 +1,129 @@
+use std::{fmt, ops::Range};
+
+use thiserror::Error;
+
+use super::{
+    sandbox::Limit,
+    types::{Deadlock, ID},
+};
+use crate::parser::{types::UpdateType, validate::Violation};
+
+/// Why a step couldn't finish, and where it gave up.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{kind} in timeline {timeline}{}, step {step}", location(.instruction, .span))]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub timeline: ID,
+    // `None` for commands, those are applied after the instruction that issued them is gone
+    pub instruction: Option<usize>,
+    // bytes of `raw_program` the instruction was parsed from, `None` for hand built tokens
+    pub span: Option<Range<usize>>,
+    pub step: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum RuntimeErrorKind {
+    #[error("pointer moved past the end of the tape")]
+    TapeOverflow,
+    // `history` is what the cell held before, oldest first
+    #[error(
+        "cell {cell} would {} from {value}, earlier values {history:?}",
+        direction(.update)
+    )]
+    CellOverflow {
+        cell: isize,
+        value: u8,
+        update: UpdateType,
+        history: Vec<u8>,
+    },
+    #[error("{0} limit exceeded")]
+    LimitExceeded(Limit),
+    #[error("invalid command: {0}")]
+    InvalidCommand(String),
+    #[error("deadlock, {} timelines waiting", .0.blocked.len())]
+    Deadlock(Deadlock),
+    #[error("invalid program: {}", list(.0))]
+    InvalidProgram(Vec<Violation>),
+    // a callback set with `Engine::on_every` stopped a run that has to halt
+    #[error("interrupted")]
+    Interrupted,
+}
+
+fn direction(update: &UpdateType) -> &'static str {
+    match update {
+        UpdateType::Increment => "overflow",
+        UpdateType::Decrement => "underflow",
+    }
+}
+
+fn list(violations: &[Violation]) -> String {
+    violations
+        .iter()
+        .map(Violation::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Something the program did that the engine got past, likely not what the author meant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub timeline: ID,
+    pub instruction: Option<usize>,
+    pub span: Option<Range<usize>>,
+    pub step: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WarningKind {
+    /// `)` in the root timeline, which is never removed.
+    RootKillIgnored,
+    /// `^` in the root timeline, there is nowhere to go so the pointers are gone.
+    PointersDroppedAbove,
+    /// `v` in the root timeline drops the pointers as well.
+    PointersDroppedBelow,
+    /// `v` in the bottom timeline, the pointers stay where they are.
+    NoTimelineBelow,
+    /// `~` with nothing recorded to undo.
+    EmptyHistory,
+    /// `\\` without a `` ` `` to go back to.
+    NoMarker,
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            WarningKind::RootKillIgnored => "the root timeline can't be killed",
+            WarningKind::PointersDroppedAbove => "pointers moved up off the root timeline",
+            WarningKind::PointersDroppedBelow => "pointers moved down from the root timeline",
+            WarningKind::NoTimelineBelow => "pointers moved down from the bottom timeline",
+            WarningKind::EmptyHistory => "rewind with no history",
+            WarningKind::NoMarker => "rewind to marker without a marker",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in timeline {}{}, step {}",
+            self.kind,
+            self.timeline,
+            location(&self.instruction, &self.span),
+            self.step
+        )
+    }
+}
+
+fn location(instruction: &Option<usize>, span: &Option<Range<usize>>) -> String {
+    match (instruction, span) {
+        (Some(instruction), Some(span)) => format!(
+            " at instruction {} (bytes {}..{})",
+            instruction, span.start, span.end
+        ),
+        (Some(instruction), None) => format!(" at instruction {}", instruction),
+        (None, _) => "".to_string(),
+    }
+}
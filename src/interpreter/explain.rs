@@ -0,0 +1,275 @@
+// A line of plain words for each turn a timeline takes, for people learning the language.
+//
+// What an instruction does is mostly known before it runs, from the cells under the pointers. The
+// rest, whether `@` and `|` let the timeline through and how many more instructions its fuel ran,
+// only shows after the round, so a line is finished once the round is over.
+use super::{
+    bf5d::Engine,
+    types::{BF5DContext, Timeline, ID},
+};
+use crate::parser::types::{JumpType, MoveDirection, Token, UpdateType};
+
+/// Narrates an engine a round at a time, `before_step` and `after_step` around each `step`.
+#[derive(Debug, Clone, Default)]
+pub struct Narrator {
+    turns: Vec<Turn>,
+    // the round `turns` were taken down before
+    round: usize,
+}
+
+// what a timeline was about to do when the round started
+#[derive(Debug, Clone)]
+struct Turn {
+    id: ID,
+    instruction: usize,
+    steps_executed: usize,
+    line: String,
+    // `@` or `|`, which may let it through or not
+    waits: bool,
+}
+
+impl Narrator {
+    /// Takes down what each timeline is about to do, those asleep or suspended included.
+    pub fn before_step(&mut self, engine: &Engine) {
+        let context = &engine.context;
+        self.round = context.steps;
+        self.turns = engine
+            .timelines
+            .iter()
+            .filter(|timeline| !timeline.suspended)
+            .map(|timeline| {
+                let token = context.tokens.get(timeline.instruction_pointer);
+                Turn {
+                    id: timeline.id,
+                    instruction: timeline.instruction_pointer,
+                    steps_executed: timeline.steps_executed,
+                    line: match token {
+                        _ if timeline.sleeping > 0 => {
+                            format!("sleeps, {} more rounds", timeline.sleeping)
+                        }
+                        _ if !timeline.runs_in(context.steps) => {
+                            "sits this round out, its priority is low".to_string()
+                        }
+                        Some(token) => narrate(context, timeline, token),
+                        None => "gets to the end of the program".to_string(),
+                    },
+                    waits: matches!(token, Some(Token::Await) | Some(Token::Barrier)),
+                }
+            })
+            .collect();
+    }
+
+    /// The lines for the round that just ran, one per timeline that was there when it started,
+    /// none if the engine didn't get through it.
+    pub fn after_step(&mut self, engine: &Engine) -> Vec<String> {
+        if engine.context.steps == self.round {
+            self.turns.clear();
+        }
+        let step = self.round;
+        self.turns
+            .drain(..)
+            .map(|turn| {
+                let after = engine.timelines.iter().find(|t| t.id == turn.id);
+                let mut line = turn.line.clone();
+                if let Some(after) = after.filter(|_| turn.waits) {
+                    if after.instruction_pointer == turn.instruction {
+                        line.push_str(" and waits");
+                    } else {
+                        line.push_str(" and goes on");
+                    }
+                }
+                let executed = after.map_or(1, |t| t.steps_executed - turn.steps_executed);
+                match executed {
+                    0 | 1 => (),
+                    2 => line.push_str(", then 1 more instruction"),
+                    n => line.push_str(&format!(", then {} more instructions", n - 1)),
+                }
+                format!("step {}, timeline {}: {}", step, turn.id, line)
+            })
+            .collect()
+    }
+}
+
+// what `token` will do for `timeline`, with the cells under its pointers as they are now
+fn narrate(context: &BF5DContext, timeline: &Timeline, token: &Token) -> String {
+    let source = quoted(context, timeline.instruction_pointer);
+    let pointers = timeline.pointers.len();
+    let the_pointers = match pointers {
+        1 => "the pointer".to_string(),
+        n => format!("{} pointers", n),
+    };
+    let first = timeline.pointers.first().copied().unwrap_or(0);
+    let value = timeline.cell(first);
+    let cells = cells(timeline);
+    let all_zero = timeline.pointers.iter().all(|&ptr| timeline.cell(ptr) == 0);
+    let zero_because = if pointers == 1 {
+        format!("cell {} is 0", first)
+    } else {
+        "all cells are 0".to_string()
+    };
+    let nonzero_because = if pointers == 1 {
+        format!("cell {} is {}", first, value)
+    } else {
+        "a cell isn't 0".to_string()
+    };
+
+    let what = match *token {
+        Token::Move(MoveDirection::Left) => format!("moves {} left", the_pointers),
+        Token::Move(MoveDirection::Right) => format!("moves {} right", the_pointers),
+        Token::Move(MoveDirection::Up) => format!("moves {} to the timeline above", the_pointers),
+        Token::Move(MoveDirection::Down) => {
+            format!("moves {} to the timeline below", the_pointers)
+        }
+        Token::Update(UpdateType::Increment) if pointers == 1 => format!(
+            "adds 1 to cell {}, making it {}",
+            first,
+            value.wrapping_add(1)
+        ),
+        Token::Update(UpdateType::Decrement) if pointers == 1 => format!(
+            "takes 1 from cell {}, making it {}",
+            first,
+            value.wrapping_sub(1)
+        ),
+        Token::Update(UpdateType::Increment) => format!("adds 1 to {}", cells),
+        Token::Update(UpdateType::Decrement) => format!("takes 1 from {}", cells),
+        Token::Jump {
+            type_: JumpType::IfZero,
+            index,
+        } if all_zero => format!("jumps to {} because {}", index, zero_because),
+        Token::Jump {
+            type_: JumpType::IfZero,
+            ..
+        } => format!("enters the loop because {}", nonzero_because),
+        Token::Jump {
+            type_: JumpType::IfNotZero,
+            index,
+        } if !all_zero => format!("goes back to {} because {}", index, nonzero_because),
+        Token::Jump {
+            type_: JumpType::IfNotZero,
+            ..
+        } => format!("leaves the loop because {}", zero_because),
+        Token::Write => {
+            let written = timeline
+                .pointers
+                .iter()
+                .map(|&ptr| character(timeline.cell(ptr)))
+                .collect::<Vec<_>>();
+            format!("writes {}", listed(written))
+        }
+        Token::WriteNumber => format!("writes the number in {}", cells),
+        // a character for each pointer
+        Token::Read => {
+            let read = context
+                .program_input
+                .chars()
+                .take(pointers)
+                .collect::<String>();
+            if read.is_empty() {
+                format!("reads 0 into {}, the input is used up", cells)
+            } else {
+                format!("reads {:?} into {}", read, cells)
+            }
+        }
+        Token::Rewind if timeline.tape.is_empty() => "has no change to undo".to_string(),
+        Token::Rewind => "undoes the last change to its cells".to_string(),
+        Token::Spawn { index } => format!(
+            "spawns a timeline running {}..{} and skips to {}",
+            timeline.instruction_pointer + 1,
+            index,
+            index
+        ),
+        Token::SpawnIf { index } if all_zero => {
+            format!(
+                "skips to {} without spawning because {}",
+                index, zero_because
+            )
+        }
+        Token::SpawnIf { index } if !timeline.spawns_if(context.spawn_if) => {
+            format!("skips to {} without spawning because a cell is 0", index)
+        }
+        Token::SpawnIf { index } => format!(
+            "spawns a timeline running {}..{} because {}",
+            timeline.instruction_pointer + 1,
+            index,
+            nonzero_because
+        ),
+        Token::Fork { index } => format!(
+            "forks a timeline for each of its {} pointers, running {}..{}",
+            pointers,
+            timeline.instruction_pointer + 1,
+            index
+        ),
+        Token::Kill if context.metadata.first().map(|meta| meta.id) == Some(timeline.id) => {
+            "can't end the root timeline, carries on".to_string()
+        }
+        Token::Kill => "ends the timeline".to_string(),
+        Token::Await => "checks on the timelines below".to_string(),
+        Token::Barrier => "gets to the barrier".to_string(),
+        Token::DebugDump => "dumps the cells around its pointers".to_string(),
+        Token::Random => format!("puts a random number in {}", cells),
+        Token::Goto { index } => format!("jumps to the label at {}", index),
+        Token::Call { index } => format!("calls the procedure at {}", index),
+        Token::Return => match timeline.call_stack.last() {
+            Some(address) => format!("returns to {}", address),
+            None => "isn't in a procedure, carries on".to_string(),
+        },
+        Token::RaisePriority => format!("raises its priority to {}", timeline.priority + 1),
+        Token::LowerPriority => format!("lowers its priority to {}", timeline.priority - 1),
+        Token::Sleep => format!("goes to sleep for {} rounds", value),
+        Token::CopyToBelow => "copies its cells to the timeline below".to_string(),
+        Token::CopyFromBelow => "copies the cells of the timeline below".to_string(),
+        Token::SplitPointer => {
+            let first = timeline.pointers.first().copied().unwrap_or(0);
+            format!("adds a pointer on cell {}", first)
+        }
+        Token::MergePointers => "drops every pointer but the first".to_string(),
+        Token::SetMarker => "marks this point in its history".to_string(),
+        Token::RewindToMarker => "undoes everything since its last marker".to_string(),
+        Token::ResetTape => "sets every cell to 0".to_string(),
+    };
+    format!("{} {}", source, what)
+}
+
+// the instruction as it was written, or its number for tokens that weren't parsed
+fn quoted(context: &BF5DContext, instruction: usize) -> String {
+    match context.spans.get(instruction) {
+        Some(span) => format!("`{}`", &context.raw_program[span.clone()]),
+        None => format!("instruction {}", instruction),
+    }
+}
+
+// the cells under the pointers, `cell 3` or `cells 3, 4 and 5`
+fn cells(timeline: &Timeline) -> String {
+    match timeline.pointers.len() {
+        0 => "no cell".to_string(),
+        1 => format!("cell {}", timeline.pointers[0]),
+        _ => format!(
+            "cells {}",
+            listed(
+                timeline
+                    .pointers
+                    .iter()
+                    .map(|ptr| ptr.to_string())
+                    .collect()
+            )
+        ),
+    }
+}
+
+// `a, b and c`
+fn listed(mut items: Vec<String>) -> String {
+    match items.pop() {
+        None => "nothing".to_string(),
+        Some(last) if items.is_empty() => last,
+        Some(last) => format!("{} and {}", items.join(", "), last),
+    }
+}
+
+// a byte as the character it prints, by its number if it doesn't show
+fn character(value: u8) -> String {
+    if value.is_ascii_graphic() || value == b' ' {
+        format!("{:?}", value as char)
+    } else {
+        format!("byte {}", value)
+    }
+}
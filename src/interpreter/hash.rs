@@ -0,0 +1,111 @@
+// FNV-1a over a canonical encoding of the machine state, stable across runs and platforms.
+use super::{bf5d::Engine, types::Timeline};
+
+const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+struct Fnv(u64);
+
+impl Fnv {
+    fn bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(PRIME);
+        }
+    }
+
+    // everything goes in as fixed width little endian so 32 and 64 bit targets agree
+    fn number(&mut self, number: u64) {
+        self.bytes(&number.to_le_bytes());
+    }
+
+    fn signed(&mut self, number: i64) {
+        self.bytes(&number.to_le_bytes());
+    }
+
+    fn list<T>(
+        &mut self,
+        items: impl ExactSizeIterator<Item = T>,
+        mut item: impl FnMut(&mut Self, T),
+    ) {
+        self.number(items.len() as u64);
+        for x in items {
+            item(self, x);
+        }
+    }
+}
+
+/// Two engines with the same hash behave the same from here on, as far as a 64 bit hash can tell.
+///
+/// Ids, untouched zero cells and what was already printed don't change what happens next, so
+/// they are left out.
+pub fn state_hash(engine: &Engine) -> u64 {
+    let mut hash = Fnv(OFFSET_BASIS);
+    let context = &engine.context;
+
+    hash.bytes(context.program_input.as_bytes());
+    hash.number(context.rng.state());
+    hash.list(engine.timelines.iter(), |hash, timeline| {
+        timeline_hash(hash, timeline)
+    });
+    hash.0
+}
+
+fn timeline_hash(hash: &mut Fnv, timeline: &Timeline) {
+    hash.number(timeline.instruction_pointer as u64);
+    hash.list(timeline.pointers.iter(), |hash, ptr| {
+        hash.signed(*ptr as i64)
+    });
+
+    let cells = timeline.iter_nonzero_cells().collect::<Vec<_>>();
+    hash.list(cells.into_iter(), |hash, (i, x)| {
+        hash.signed(i as i64);
+        hash.bytes(&[x]);
+    });
+    hash.list(timeline.tape.iter(), |hash, slice_of_time| {
+        hash.list(slice_of_time.iter(), |hash, (i, x)| {
+            hash.signed(*i as i64);
+            hash.bytes(&[x.0]);
+        })
+    });
+
+    hash.list(timeline.call_stack.iter(), |hash, x| hash.number(*x as u64));
+    hash.list(timeline.markers.iter(), |hash, x| hash.number(*x as u64));
+    hash.signed(timeline.priority as i64);
+    hash.number(timeline.sleeping as u64);
+    hash.number(timeline.credit as u64);
+    hash.bytes(&[timeline.suspended as u8]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ran(source: &str, steps: usize) -> Engine {
+        let mut engine = Engine::from_source(source, "ab").unwrap();
+        for _ in 0..steps {
+            engine.step().unwrap();
+        }
+        engine
+    }
+
+    #[test]
+    fn ids_dont_change_the_hash() {
+        let source = "+(>+(-)<)++(,)";
+        for steps in 0..8 {
+            let (a, b) = (ran(source, steps), ran(source, steps));
+            let ids = |engine: &Engine| engine.timelines.iter().map(|t| t.id).collect::<Vec<_>>();
+            assert_ne!(ids(&a), ids(&b));
+            assert_eq!(state_hash(&a), state_hash(&b), "after {} steps", steps);
+        }
+    }
+
+    #[test]
+    fn what_was_printed_doesnt_change_the_hash() {
+        let mut printed = ran("+.-+", 2);
+        printed.context.program_output.push_str("more");
+        assert_eq!(state_hash(&printed), state_hash(&ran("+.-+", 2)));
+        assert_ne!(state_hash(&ran("+-+", 3)), state_hash(&ran("+-+", 2)));
+        assert_ne!(state_hash(&ran("+,", 2)), state_hash(&ran("+", 1)));
+    }
+}
@@ -0,0 +1,285 @@
+// Past states of a run, rebuilt by replaying from the closest keyframe.
+use std::fmt;
+
+use itertools::Itertools;
+
+use super::{
+    bf5d::{Engine, TimelineSelector},
+    error::RuntimeError,
+    types::{Timeline, ID},
+};
+
+/// Everything visible about an engine at one step.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub step: usize,
+    pub output: String,
+    pub timelines: Vec<Timeline>,
+}
+
+impl Snapshot {
+    pub fn of(engine: &Engine) -> Self {
+        Snapshot {
+            step: engine.context.steps,
+            output: engine.context.program_output.clone(),
+            timelines: engine.timelines.clone(),
+        }
+    }
+
+    pub fn timeline(&self, selector: TimelineSelector) -> Option<&Timeline> {
+        match selector {
+            TimelineSelector::Id(id) => self.timelines.iter().find(|t| t.id == id),
+            TimelineSelector::Position(position) => self.timelines.get(position),
+            TimelineSelector::Root => self.timelines.first(),
+            TimelineSelector::Bottom => self.timelines.last(),
+        }
+    }
+
+    /// What changed going from `self` to `other`, normally a later snapshot of the same run.
+    pub fn diff(&self, other: &Snapshot) -> StateDiff {
+        let spawned = other
+            .timelines
+            .iter()
+            .filter(|t| self.timeline(TimelineSelector::Id(t.id)).is_none())
+            .map(|t| t.id)
+            .collect();
+        let killed = self
+            .timelines
+            .iter()
+            .filter(|t| other.timeline(TimelineSelector::Id(t.id)).is_none())
+            .map(|t| t.id)
+            .collect();
+
+        let timelines = self
+            .timelines
+            .iter()
+            .filter_map(|before| {
+                let after = other.timeline(TimelineSelector::Id(before.id))?;
+                let diff = TimelineDiff::between(before, after);
+                if diff.is_empty() {
+                    None
+                } else {
+                    Some(diff)
+                }
+            })
+            .collect();
+
+        // the output only ever grows within a run, anything else is shown in full
+        let output = match other.output.strip_prefix(self.output.as_str()) {
+            Some(added) => added.to_string(),
+            None => other.output.clone(),
+        };
+
+        StateDiff {
+            from_step: self.step,
+            to_step: other.step,
+            spawned,
+            killed,
+            timelines,
+            output,
+        }
+    }
+}
+
+/// Displayed one line per timeline, e.g. `timeline 0: ip 3 -> 4, cell 0: 1 -> 2`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateDiff {
+    pub from_step: usize,
+    pub to_step: usize,
+    pub spawned: Vec<ID>,
+    pub killed: Vec<ID>,
+    // only timelines in both snapshots that changed
+    pub timelines: Vec<TimelineDiff>,
+    // written in between
+    pub output: String,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.spawned.is_empty()
+            && self.killed.is_empty()
+            && self.timelines.is_empty()
+            && self.output.is_empty()
+    }
+}
+
+/// Everything here is a (before, after) pair.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineDiff {
+    pub id: ID,
+    pub instruction_pointer: Option<(usize, usize)>,
+    pub pointers: Option<(Vec<isize>, Vec<isize>)>,
+    // (index, before, after)
+    pub cells: Vec<(isize, u8, u8)>,
+}
+
+impl TimelineDiff {
+    fn between(before: &Timeline, after: &Timeline) -> Self {
+        let low = -(before.data_backwards.len().max(after.data_backwards.len()) as isize);
+        let high = before.data.len().max(after.data.len()) as isize;
+
+        TimelineDiff {
+            id: before.id,
+            instruction_pointer: Some((before.instruction_pointer, after.instruction_pointer))
+                .filter(|(before, after)| before != after),
+            pointers: Some((before.pointers.clone(), after.pointers.clone()))
+                .filter(|(before, after)| before != after),
+            cells: (low..high)
+                .map(|i| (i, before.cell(i), after.cell(i)))
+                .filter(|(_, before, after)| before != after)
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instruction_pointer.is_none() && self.pointers.is_none() && self.cells.is_empty()
+    }
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {} -> {}", self.from_step, self.to_step)?;
+        for id in self.spawned.iter() {
+            write!(f, "\nspawned timeline {}", id)?;
+        }
+        for id in self.killed.iter() {
+            write!(f, "\nkilled timeline {}", id)?;
+        }
+        for diff in self.timelines.iter() {
+            write!(f, "\n{}", diff)?;
+        }
+        if !self.output.is_empty() {
+            write!(f, "\noutput {:?}", self.output)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TimelineDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut changes = vec![];
+        if let Some((before, after)) = self.instruction_pointer {
+            changes.push(format!("ip {} -> {}", before, after));
+        }
+        if let Some((before, after)) = self.pointers.as_ref() {
+            changes.push(format!("pointers {:?} -> {:?}", before, after));
+        }
+        for (i, before, after) in self.cells.iter() {
+            changes.push(format!("cell {}: {} -> {}", i, before, after));
+        }
+        write!(f, "timeline {}: {}", self.id, changes.iter().join(", "))
+    }
+}
+
+/// Keyframes of a run taken every `interval` steps.
+///
+/// Going back to step N means cloning the last keyframe before it and stepping forward, so the
+/// run has to replay exactly: the first keyframe switches the engine to its own timeline ids.
+#[derive(Debug, Clone)]
+pub struct History {
+    interval: usize,
+    keyframes: Vec<Engine>,
+    last_step: usize,
+}
+
+impl History {
+    pub fn new(interval: usize) -> Self {
+        History {
+            interval: interval.max(1),
+            keyframes: vec![],
+            last_step: 0,
+        }
+    }
+
+    /// Runs `engine` like `Engine::run`, recording as it goes.
+    pub fn run(
+        engine: &mut Engine,
+        max_steps: usize,
+        interval: usize,
+    ) -> Result<Self, RuntimeError> {
+        let mut history = History::new(interval);
+        history.record(engine);
+        for _ in 0..max_steps {
+            if engine.is_halted() {
+                break;
+            }
+            engine.step()?;
+            history.record(engine);
+        }
+        Ok(history)
+    }
+
+    /// Call once before the first step and after every step.
+    pub fn record(&mut self, engine: &mut Engine) {
+        if engine.context.id_gen.is_none() {
+            // replays would hand out fresh process wide ids otherwise
+            engine.context.id_gen = Some(engine.context.new_id());
+        }
+
+        let step = engine.context.steps;
+        let due = match self.keyframes.last() {
+            Some(last) => step >= last.context.steps + self.interval,
+            None => true,
+        };
+        if due {
+            self.keyframes.push(engine.clone());
+        }
+        self.last_step = step;
+    }
+
+    /// The state right before step `step` ran, `None` outside the recorded run.
+    pub fn state_at(&self, step: usize) -> Option<Snapshot> {
+        if step > self.last_step {
+            return None;
+        }
+
+        let keyframe = self
+            .keyframes
+            .iter()
+            .rev()
+            .find(|engine| engine.context.steps <= step)?;
+        let mut engine = keyframe.clone();
+        while engine.context.steps < step {
+            engine.step().ok()?;
+        }
+        Some(Snapshot::of(&engine))
+    }
+
+    pub fn cell_at(&self, step: usize, selector: TimelineSelector, index: isize) -> Option<u8> {
+        self.state_at(step)?
+            .timeline(selector)
+            .map(|t| t.cell(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::types::Extensions;
+
+    #[test]
+    fn replays_match_the_live_run() {
+        let source = "+++(?>+.(-:))[->++<]>:@.";
+        let mut engine = Engine::from_source_with(source, "", Extensions::all()).unwrap();
+        let mut history = History::new(4);
+        history.record(&mut engine);
+        let mut live = vec![Snapshot::of(&engine)];
+        while !engine.is_halted() {
+            engine.step().unwrap();
+            history.record(&mut engine);
+            live.push(Snapshot::of(&engine));
+        }
+        assert!(live.len() > 10);
+        assert!(live.iter().any(|snapshot| snapshot.timelines.len() > 2));
+
+        for (step, snapshot) in live.iter().enumerate() {
+            assert_eq!(
+                history.state_at(step).as_ref(),
+                Some(snapshot),
+                "step {}",
+                step
+            );
+        }
+        assert_eq!(history.state_at(live.len()), None);
+    }
+}
@@ -1,2 +1,13 @@
+pub mod actor;
 pub mod bf5d;
+pub mod error;
+pub mod explain;
+pub mod hash;
+pub mod history;
+pub mod outcome;
+pub mod progress;
+pub mod reload;
+pub mod rng;
+pub mod sandbox;
+pub mod scheduling;
 pub mod types;
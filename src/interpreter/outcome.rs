@@ -0,0 +1,117 @@
+// What `Engine::run` hands back, everything an embedder looks at once a run is over in one place
+// instead of spread over the context.
+use super::{
+    bf5d::Engine,
+    error::{RuntimeError, RuntimeErrorKind},
+    history::Snapshot,
+    sandbox::Limit,
+};
+
+/// Why a run stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExitReason {
+    Halted,
+    /// `Limit::Steps` for the steps `run` was given.
+    Limit(Limit),
+    /// A callback set with `Engine::on_every` said to stop.
+    Interrupted,
+    /// Every timeline left was waiting on another, the error says which.
+    Deadlock(RuntimeError),
+    Error(RuntimeError),
+}
+
+/// Numbers about the run, the same as the playground's stats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metrics {
+    // rounds stepped
+    pub steps: usize,
+    pub instructions: usize,
+    // the timelines alive at the end and those spawned or forked on the way
+    pub timelines: usize,
+    pub spawned: usize,
+    // characters of the input
+    pub input_read: usize,
+    pub warnings: usize,
+    // bytes, see `Engine::memory_usage`
+    pub memory: usize,
+}
+
+impl Metrics {
+    pub fn of(engine: &Engine) -> Self {
+        let context = &engine.context;
+        Metrics {
+            steps: context.steps,
+            instructions: context.instructions,
+            timelines: engine.timelines.len(),
+            spawned: context.births.len(),
+            input_read: context.input_consumed,
+            warnings: context.warning_count(),
+            memory: engine.memory_usage(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunOutcome {
+    /// Everything written, a character per byte.
+    pub output: String,
+    pub bytes: Vec<u8>,
+    pub exit: ExitReason,
+    pub metrics: Metrics,
+    /// The state the run ended in, see `with_snapshot`.
+    pub snapshot: Option<Snapshot>,
+}
+
+impl RunOutcome {
+    /// `result` being what `Engine::run_while` returned for `engine`, `interrupted` whether an
+    /// `on_every` callback stopped it.
+    pub fn of(engine: &Engine, result: Result<bool, RuntimeError>, interrupted: bool) -> Self {
+        let exit = match result {
+            Ok(true) => ExitReason::Halted,
+            Ok(false) if interrupted => ExitReason::Interrupted,
+            Ok(false) => ExitReason::Limit(Limit::Steps),
+            Err(RuntimeError {
+                kind: RuntimeErrorKind::LimitExceeded(limit),
+                ..
+            }) => ExitReason::Limit(limit),
+            Err(
+                error @ RuntimeError {
+                    kind: RuntimeErrorKind::Deadlock(_),
+                    ..
+                },
+            ) => ExitReason::Deadlock(error),
+            Err(error) => ExitReason::Error(error),
+        };
+        RunOutcome {
+            output: engine.context.program_output.clone(),
+            bytes: engine
+                .output_log()
+                .iter()
+                .flat_map(|chunk| chunk.bytes.iter().copied())
+                .collect(),
+            exit,
+            metrics: Metrics::of(engine),
+            snapshot: None,
+        }
+    }
+
+    /// With the state `engine` ended in, `engine.run(max_steps).with_snapshot(&engine)`.
+    pub fn with_snapshot(self, engine: &Engine) -> Self {
+        RunOutcome {
+            snapshot: Some(Snapshot::of(engine)),
+            ..self
+        }
+    }
+
+    pub fn halted(&self) -> bool {
+        self.exit == ExitReason::Halted
+    }
+
+    /// What stopped it, for deadlocks too, `None` if nothing went wrong.
+    pub fn error(&self) -> Option<&RuntimeError> {
+        match &self.exit {
+            ExitReason::Deadlock(error) | ExitReason::Error(error) => Some(error),
+            _ => None,
+        }
+    }
+}
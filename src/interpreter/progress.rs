@@ -0,0 +1,81 @@
+// Telling whoever waits on a long run that it's still getting somewhere.
+use std::fmt;
+
+use super::bf5d::Engine;
+
+/// Where a run is at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    // rounds stepped so far
+    pub steps: usize,
+    pub timelines: usize,
+    // bytes, as counted by `Timeline::memory_usage`
+    pub memory: usize,
+}
+
+impl Progress {
+    pub fn of(engine: &Engine) -> Self {
+        Progress {
+            steps: engine.context.steps,
+            timelines: engine.timelines.len(),
+            memory: engine.memory_usage(),
+        }
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} steps, {} timelines, {} of memory",
+            self.steps,
+            self.timelines,
+            bytes(self.memory)
+        )
+    }
+}
+
+/// Hands out a `Progress` every `every` steps, for hosts that drive the engine themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reporter {
+    pub every: usize,
+    // the step the last report was for
+    last: usize,
+}
+
+impl Reporter {
+    /// `every` is taken as 1 if it's 0.
+    pub fn new(every: usize) -> Self {
+        Reporter {
+            every: every.max(1),
+            last: 0,
+        }
+    }
+
+    /// The progress to report if `every` steps went by since the last report, the first one
+    /// comes once `every` steps went by at all.
+    pub fn poll(&mut self, engine: &Engine) -> Option<Progress> {
+        let steps = engine.context.steps;
+        if steps.saturating_sub(self.last) < self.every {
+            return None;
+        }
+        self.last = steps;
+        Some(Progress::of(engine))
+    }
+}
+
+// `bytes` in the biggest unit there's at least one of
+fn bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
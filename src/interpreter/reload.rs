@@ -0,0 +1,164 @@
+// Swapping the program of a running engine for an edited one, for hosts that watch the source.
+//
+// The timelines keep their cells and go on from the instruction theirs was at, wherever the edit
+// moved it to. Instructions are lined up the way a diff would line up lines: what the old and new
+// program start and end with is the same, the changed part in between is matched by its longest
+// common subsequence.
+use super::types::ID;
+use crate::parser::types::Token;
+
+// beyond this many pairs of instructions the changed parts aren't lined up, only the ends are
+const MAX_PAIRS: usize = 1 << 22;
+
+/// What `Engine::reload` did.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reload {
+    /// Every timeline goes on in the new program.
+    Mapped,
+    /// `timeline` is at or returns to `instruction`, which the edit took out, the engine was left
+    /// as it was.
+    Lost { timeline: ID, instruction: usize },
+}
+
+/// Where each instruction of `old` is in `new`, `None` for those the edit took out, one more for
+/// the end of the program.
+pub fn map_instructions(old: &[Token], new: &[Token]) -> Vec<Option<usize>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| same(a, b)).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| same(a, b))
+        .count();
+
+    let mut map = vec![None; old.len() + 1];
+    for (i, to) in map.iter_mut().enumerate().take(prefix) {
+        *to = Some(i);
+    }
+    for i in 1..=suffix {
+        map[old.len() - i] = Some(new.len() - i);
+    }
+    map[old.len()] = Some(new.len());
+
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+    if a.is_empty() || b.is_empty() || a.len().saturating_mul(b.len()) > MAX_PAIRS {
+        return map;
+    }
+    // `longest[i * width + j]`, how long the longest common subsequence of `a[i..]` and `b[j..]` is
+    let width = b.len() + 1;
+    let mut longest = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            longest[i * width + j] = if same(&a[i], &b[j]) {
+                longest[(i + 1) * width + j + 1] + 1
+            } else {
+                longest[(i + 1) * width + j].max(longest[i * width + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if same(&a[i], &b[j]) {
+            map[prefix + i] = Some(prefix + j);
+            i += 1;
+            j += 1;
+        } else if longest[(i + 1) * width + j] >= longest[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    map
+}
+
+// the same instruction, wherever it jumps to
+fn same(a: &Token, b: &Token) -> bool {
+    untargeted(a) == untargeted(b)
+}
+
+fn untargeted(token: &Token) -> Token {
+    let mut token = *token;
+    match &mut token {
+        Token::Jump { index, .. }
+        | Token::Spawn { index }
+        | Token::Fork { index }
+        | Token::SpawnIf { index }
+        | Token::Goto { index }
+        | Token::Call { index } => *index = 0,
+        _ => (),
+    }
+    token
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interpreter::bf5d::Engine, parser::bf5d::parse};
+
+    fn map(old: &str, new: &str) -> Vec<Option<usize>> {
+        map_instructions(&parse(old).unwrap(), &parse(new).unwrap())
+    }
+
+    fn instructions(engine: &Engine) -> Vec<usize> {
+        engine
+            .timelines
+            .iter()
+            .map(|t| t.instruction_pointer)
+            .collect()
+    }
+
+    #[test]
+    fn lines_up_what_the_edit_kept() {
+        assert_eq!(
+            map("+>-.", "+>>-."),
+            vec![Some(0), Some(1), Some(3), Some(4), Some(5)]
+        );
+        assert_eq!(
+            map("+>-.", "+."),
+            vec![Some(0), None, None, Some(1), Some(2)]
+        );
+        // jumps line up whatever they jump to
+        assert_eq!(
+            map("[-]+", ">[-]"),
+            vec![Some(1), Some(2), Some(3), None, Some(4)]
+        );
+    }
+
+    #[test]
+    fn every_timeline_goes_on_where_its_instruction_went() {
+        let mut engine = Engine::from_source("(++++)++++.", "").unwrap();
+        while engine.timelines.len() < 2 {
+            engine.step().unwrap();
+        }
+        engine.step().unwrap();
+        let before = instructions(&engine);
+
+        assert_eq!(engine.reload(">(++++)++++."), Ok(Reload::Mapped));
+        let after = instructions(&engine);
+        assert_eq!(after, before.iter().map(|i| i + 1).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn leaves_the_engine_alone_if_an_instruction_is_lost() {
+        let mut engine = Engine::from_source("(++++)+++.", "").unwrap();
+        while engine.timelines.len() < 2 {
+            engine.step().unwrap();
+        }
+        engine.step().unwrap();
+        let child = engine.timelines[1].id;
+        let before = instructions(&engine);
+        // the child is in the spawn body
+        assert!(before[1] > 0 && before[1] < 5);
+
+        assert_eq!(
+            engine.reload("()+++."),
+            Ok(Reload::Lost {
+                timeline: child,
+                instruction: before[1],
+            })
+        );
+        assert_eq!(instructions(&engine), before);
+        assert_eq!(engine.context.raw_program, "(++++)+++.");
+    }
+}
@@ -0,0 +1,197 @@
+// Running programs nobody vetted, for judges and bots.
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use super::{
+    bf5d::Engine,
+    error::{RuntimeError, RuntimeErrorKind},
+    types::ID,
+};
+
+/// `None` means unlimited.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Limits {
+    pub steps: Option<usize>,
+    // wall clock time, measuring it panics on wasm32-unknown-unknown so leave it off there
+    pub time: Option<Duration>,
+    pub timelines: Option<usize>,
+    // bytes, as counted by `Timeline::memory_usage`
+    pub memory: Option<usize>,
+    // bytes of `program_output`
+    pub output: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Steps,
+    Time,
+    Timelines,
+    Memory,
+    Output,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Limit::Steps => "step",
+            Limit::Time => "time",
+            Limit::Timelines => "timeline",
+            Limit::Memory => "memory",
+            Limit::Output => "output",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// An engine that runs until it halts or trips one of `limits`.
+#[derive(Debug, Clone)]
+pub struct SandboxedRun {
+    pub engine: Engine,
+    pub limits: Limits,
+}
+
+impl SandboxedRun {
+    pub fn new(engine: Engine, limits: Limits) -> Self {
+        SandboxedRun { engine, limits }
+    }
+
+    /// `Ok` once the program halted within the limits, a `LimitExceeded` error names the one it
+    /// tripped first, an `Interrupted` error means a callback set with `Engine::on_every` stopped
+    /// it before either.
+    ///
+    /// The limits are checked between steps, so a single step can go a little over.
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        self.run_watched(|_| ())
+    }
+
+    /// Like `run`, `watch` sees the engine before every step, for hosts showing the run live.
+    pub fn run_watched(&mut self, mut watch: impl FnMut(&Engine)) -> Result<(), RuntimeError> {
+        let limits = &self.limits;
+        let started = limits.time.map(|_| Instant::now());
+        let mut steps = 0;
+        let mut tripped = None;
+
+        let halted = self.engine.run_while(usize::MAX, |engine| {
+            watch(engine);
+            tripped = if limits.steps.is_some_and(|max| steps >= max) {
+                Some(Limit::Steps)
+            } else if limits
+                .time
+                .zip(started)
+                .is_some_and(|(max, started)| started.elapsed() >= max)
+            {
+                Some(Limit::Time)
+            } else {
+                exceeded(limits, engine)
+            };
+            steps += 1;
+            tripped.is_none()
+        })?;
+
+        // what the last step did only gets looked at here if it was the last one
+        if halted {
+            tripped = exceeded(limits, &self.engine);
+        }
+        match tripped {
+            Some(limit) => Err(self.engine.context.error(
+                RuntimeErrorKind::LimitExceeded(limit),
+                self.blame(limit),
+                None,
+            )),
+            None if !halted => Err(self.engine.context.error(
+                RuntimeErrorKind::Interrupted,
+                self.engine.timelines.first().map_or(0, |t| t.id),
+                None,
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // the timeline that pushed the engine over, the root for the engine wide limits
+    fn blame(&self, limit: Limit) -> ID {
+        let engine = &self.engine;
+        let root = engine.timelines.first().map_or(0, |t| t.id);
+        match limit {
+            Limit::Steps | Limit::Time => None,
+            Limit::Timelines => engine.context.births.last().map(|birth| birth.parent_id),
+            Limit::Memory => engine
+                .timelines
+                .iter()
+                .max_by_key(|t| t.memory_usage())
+                .map(|t| t.id),
+            Limit::Output => engine.context.output_log.last().map(|chunk| chunk.timeline),
+        }
+        .unwrap_or(root)
+    }
+}
+
+// the limits on what the engine holds, as opposed to how long it ran
+pub(crate) fn exceeded(limits: &Limits, engine: &Engine) -> Option<Limit> {
+    if limits
+        .timelines
+        .is_some_and(|max| engine.timelines.len() > max)
+    {
+        Some(Limit::Timelines)
+    } else if limits.memory.is_some_and(|max| engine.memory_usage() > max) {
+        Some(Limit::Memory)
+    } else if limits
+        .output
+        .is_some_and(|max| engine.context.program_output.len() > max)
+    {
+        Some(Limit::Output)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::types::Extensions;
+
+    fn sandbox(source: &str, limits: Limits) -> SandboxedRun {
+        SandboxedRun::new(Engine::from_source(source, "").unwrap(), limits)
+    }
+
+    #[test]
+    fn reports_the_limit_tripped() {
+        let limits = Limits {
+            steps: Some(100),
+            ..Limits::default()
+        };
+        assert_eq!(sandbox("+.", limits.clone()).run(), Ok(()));
+        let error = sandbox("+[]", limits).run().unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::LimitExceeded(Limit::Steps));
+    }
+
+    #[test]
+    fn memory_follows_the_history() {
+        // the `\` makes the engine record the 255 changes to cell 0 before it wraps around
+        let engine = |source| Engine::from_source_with(source, "", Extensions::all()).unwrap();
+        let limits = Limits {
+            memory: Some(1000),
+            ..Limits::default()
+        };
+        let error = SandboxedRun::new(engine("+[+]\\"), limits)
+            .run()
+            .unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::LimitExceeded(Limit::Memory));
+
+        // and rewinding them gives the memory back
+        let mut run = SandboxedRun::new(engine("`+[+]\\"), Limits::default());
+        let before = run.engine.memory_usage();
+        assert_eq!(run.run(), Ok(()));
+        assert_eq!(run.engine.memory_usage(), before);
+    }
+
+    #[test]
+    fn reports_interruptions() {
+        let mut run = sandbox("+[]", Limits::default());
+        run.engine.on_every(10, |_| false);
+        let error = run.run().unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::Interrupted);
+        assert!(!run.engine.is_halted());
+    }
+}
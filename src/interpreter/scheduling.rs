@@ -0,0 +1,104 @@
+use std::cmp::Reverse;
+
+use super::{rng::Rng, types::Timeline};
+
+/// The order timelines take their turns in within a round.
+///
+/// Every timeline sees the round as it started, `@` and `|` included, so the order only decides
+/// whose output and input come first and which commands are applied first.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SchedulingPolicy {
+    /// From the root down, the order timelines are laid out in.
+    #[default]
+    TopDown,
+    BottomUp,
+    /// A new shuffle every round, the same shuffles for the same seed.
+    Random {
+        seed: u64,
+    },
+    /// The most recently spawned timeline goes first.
+    NewestFirst,
+    /// Highest `priority` first, top-down among equals.
+    Priority,
+}
+
+impl SchedulingPolicy {
+    /// Indices into `timelines` in the order they run in `round`.
+    pub fn order(&self, timelines: &[Timeline], round: usize) -> Vec<usize> {
+        let mut order = (0..timelines.len()).collect::<Vec<_>>();
+        match self {
+            SchedulingPolicy::TopDown => (),
+            SchedulingPolicy::BottomUp => order.reverse(),
+            SchedulingPolicy::Random { seed } => {
+                // seeding per round keeps the policy itself stateless
+                let mut rng = Rng::new(seed.wrapping_add(round as u64));
+                for i in (1..order.len()).rev() {
+                    order.swap(i, (rng.next_u64() % (i as u64 + 1)) as usize);
+                }
+            }
+            // ids are handed out in increasing order
+            SchedulingPolicy::NewestFirst => order.sort_by_key(|&i| Reverse(timelines[i].id)),
+            // `sort_by_key` is stable
+            SchedulingPolicy::Priority => order.sort_by_key(|&i| Reverse(timelines[i].priority)),
+        }
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interpreter::bf5d::Engine, parser::types::Extensions};
+
+    const POLICIES: [SchedulingPolicy; 4] = [
+        SchedulingPolicy::TopDown,
+        SchedulingPolicy::BottomUp,
+        SchedulingPolicy::NewestFirst,
+        SchedulingPolicy::Priority,
+    ];
+
+    fn run(source: &str, policy: SchedulingPolicy) -> Engine {
+        let mut engine = Engine::from_source_with(source, "", Extensions::all()).unwrap();
+        engine.context.scheduling = policy;
+        assert!(engine.run(100).halted(), "{} {:?}", source, policy);
+        engine
+    }
+
+    #[test]
+    fn the_order_decides_whose_output_comes_first() {
+        // the root and its child write in the same round
+        let outputs = POLICIES.map(|policy| run("++(--:)+:", policy).context.program_output);
+        assert_eq!(outputs, ["3 0 ", "0 3 ", "0 3 ", "3 0 "]);
+    }
+
+    #[test]
+    fn the_order_decides_which_commands_go_first() {
+        // the root and its child both spawn in the second round
+        let spawned_by_the_root = |policy| {
+            let engine = run("(+(-))(-)", policy);
+            let root = engine.timelines[0].id;
+            let births = engine.context.births.iter();
+            births
+                .map(|birth| birth.parent_id == root)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            spawned_by_the_root(SchedulingPolicy::TopDown),
+            [true, true, false]
+        );
+        assert_eq!(
+            spawned_by_the_root(SchedulingPolicy::BottomUp),
+            [true, false, true]
+        );
+    }
+
+    #[test]
+    fn await_resolves_in_the_same_round_whatever_the_order() {
+        let rounds = POLICIES.map(|policy| run("+(+++[-])@:", policy).context.steps);
+        assert!(
+            rounds.iter().all(|&steps| steps == rounds[0]),
+            "{:?}",
+            rounds
+        );
+    }
+}
@@ -1,17 +1,27 @@
 use itertools::Itertools;
 use std::{
-    cell::{Cell, RefCell},
+    fmt, mem,
     num::Wrapping,
-    rc::Rc,
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
     vec,
 };
 
-use crate::parser::types::{JumpType, MoveDirection, Token, UpdateType};
+use super::{
+    error::{RuntimeError, RuntimeErrorKind, Warning, WarningKind},
+    rng::Rng,
+    scheduling::SchedulingPolicy,
+};
+use crate::parser::types::{Extensions, JumpType, MoveDirection, Token, UpdateType};
+
+pub type ID = usize;
 
-type ID = usize;
+// shared between threads so engines moved onto a worker thread keep handing out unique ids
+static ID_GEN: AtomicUsize = AtomicUsize::new(0);
 
-// https://stackoverflow.com/a/32936064/14835397
-thread_local!(static ID_GEN: Cell<ID> = Cell::new(0));
+fn next_id() -> ID {
+    ID_GEN.fetch_add(1, Ordering::Relaxed)
+}
 
 enum Pointer {
     Here(isize),
@@ -25,8 +35,34 @@ pub struct Timeline {
     pub data_backwards: Vec<Wrapping<u8>>,
     pub pointers: Vec<isize>,
     pub tape: Vec<Vec<(isize, Wrapping<u8>)>>,
+    // the changes in `tape` together, kept as it grows and shrinks so `memory_usage` doesn't have
+    // to add them up every round
+    history_len: usize,
     pub instruction_pointer: usize,
     pub alive: bool,
+    // return addresses pushed by `{label}`, spawned timelines get a copy
+    pub call_stack: Vec<usize>,
+    // below zero the timeline only runs every `1 - priority` rounds, above zero it only goes
+    // earlier in the round, and only under `SchedulingPolicy::Priority`
+    pub priority: isize,
+    // rounds left to skip after `%`
+    pub sleeping: usize,
+    // the fuel turns cut short left over, see `FuelPolicy::FairShare`
+    pub credit: usize,
+    // history depths saved by '`'
+    pub markers: Vec<usize>,
+    // held back by the host, unlike `alive` the program has no say in it
+    pub suspended: bool,
+    // the timeline that spawned or forked this one, and the round it happened in
+    pub parent_id: Option<ID>,
+    pub spawned_at_step: Option<usize>,
+    // instructions run so far and the last round it ran one in
+    pub steps_executed: usize,
+    pub last_active_step: Option<usize>,
+    // the round it was killed or ran off the end in, for those `BF5DContext::keep_dead` kept
+    pub died_at_step: Option<usize>,
+    // the `)` its own spawn just sent it to, going past that one is no attempt to kill the root
+    pub spawn_exit: Option<usize>,
 }
 
 pub enum Command {
@@ -34,40 +70,82 @@ pub enum Command {
     MovePointer { id: ID, direction: MoveDirection },
     SpawnAt { id: ID, instruction_start: usize },
     RemoveAt(ID),
+    CopyCells { id: ID, direction: CopyDirection },
+    ForkAt { id: ID, instruction_start: usize },
     // MutateAt(Vec<(Pointer, ID)>),
 }
 
+impl Command {
+    /// The timeline that issued it, `None` for `Command::None`.
+    pub fn timeline(&self) -> Option<ID> {
+        match self {
+            Command::None => None,
+            Command::MovePointer { id, .. }
+            | Command::SpawnAt { id, .. }
+            | Command::RemoveAt(id)
+            | Command::CopyCells { id, .. }
+            | Command::ForkAt { id, .. } => Some(*id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CopyDirection {
+    ToBelow,
+    FromBelow,
+}
+
 impl Timeline {
     pub fn new() -> Self {
-        ID_GEN.with(|thread_id| {
-            let id = thread_id.get();
-            thread_id.set(id + 1);
-            Timeline {
-                id,
-                data: vec![Wrapping(0)],
-                data_backwards: vec![],
-                pointers: vec![0],
-                tape: vec![],
-                instruction_pointer: 0,
-                alive: true,
-            }
-        })
+        Timeline {
+            id: next_id(),
+            data: vec![Wrapping(0)],
+            data_backwards: vec![],
+            pointers: vec![0],
+            tape: vec![],
+            history_len: 0,
+            instruction_pointer: 0,
+            alive: true,
+            call_stack: vec![],
+            priority: 0,
+            sleeping: 0,
+            credit: 0,
+            markers: vec![],
+            suspended: false,
+            parent_id: None,
+            spawned_at_step: None,
+            steps_executed: 0,
+            last_active_step: None,
+            died_at_step: None,
+            spawn_exit: None,
+        }
     }
 
     pub fn clone_new_id(&self) -> Self {
-        ID_GEN.with(|thread_id| {
-            let id = thread_id.get();
-            thread_id.set(id + 1);
-            Self { id, ..self.clone() }
-        })
+        self.clone_with_id(next_id())
+    }
+
+    pub fn clone_with_id(&self, id: ID) -> Self {
+        Self { id, ..self.clone() }
     }
 
-    pub fn update(self: &mut Self, context: &mut BF5DContext) -> (&Self, Command) {
+    pub fn update(&mut self, context: &mut BF5DContext) -> Result<(&Self, Command), RuntimeError> {
         use JumpType::*;
         use Token::*;
         use UpdateType::*;
 
-        let action = context.tokens.get(self.instruction_pointer);
+        // a copy, so the context can be borrowed again while it runs
+        let token = context.tokens.get(self.instruction_pointer).copied();
+        let action = token.as_ref();
+        let spawn_exit = self.spawn_exit.take();
+
+        // tokens built without the parser can still name a disabled extension, skip those
+        if let Some(extension) = action.and_then(Token::extension) {
+            if !context.extensions.contains(extension) {
+                self.instruction_pointer += 1;
+                return Ok((self, Command::None));
+            }
+        }
 
         if let Some(action) = action {
             // handle actions that don't dispatch commands
@@ -75,19 +153,19 @@ impl Timeline {
                 Move(dir) => match dir {
                     MoveDirection::Left => {
                         for i in 0..self.pointers.len() {
-                            let ptr = self.pointers.get_mut(i).unwrap();
-                            *ptr -= 1;
-                            // drop mutable borrow
-                            let ptr = *ptr;
+                            let ptr = self.pointers[i]
+                                .checked_sub(1)
+                                .ok_or_else(|| self.tape_overflow(context))?;
+                            self.pointers[i] = ptr;
                             self.extend_data(ptr);
                         }
                     }
                     MoveDirection::Right => {
                         for i in 0..self.pointers.len() {
-                            let ptr = self.pointers.get_mut(i).unwrap();
-                            *ptr += 1;
-                            // drop mutable borrow
-                            let ptr = *ptr;
+                            let ptr = self.pointers[i]
+                                .checked_add(1)
+                                .ok_or_else(|| self.tape_overflow(context))?;
+                            self.pointers[i] = ptr;
                             self.extend_data(ptr);
                         }
                     }
@@ -98,12 +176,17 @@ impl Timeline {
                         Increment => {
                             let mut slice_of_time = vec![];
                             for ptr in self.pointers.clone() {
+                                if context.overflow == OverflowPolicy::Trap
+                                    && self.cell(ptr) == u8::MAX
+                                {
+                                    return Err(self.cell_overflow(context, ptr, Increment));
+                                }
                                 let data = self.data_at_mut(ptr);
                                 slice_of_time.push((ptr, data.clone()));
                                 *data += Wrapping(1);
                             }
-                            if context.need_history {
-                                self.tape.push(slice_of_time)
+                            if context.records_history() {
+                                self.record(slice_of_time)
                             }
                             // why is this an error ⬇️
                             // self.pointers.clone().iter().map(|index| self.get_data_at(*index));
@@ -111,92 +194,208 @@ impl Timeline {
                         Decrement => {
                             let mut slice_of_time = vec![];
                             for ptr in self.pointers.clone() {
+                                if context.overflow == OverflowPolicy::Trap && self.cell(ptr) == 0 {
+                                    return Err(self.cell_overflow(context, ptr, Decrement));
+                                }
                                 let data = self.data_at_mut(ptr);
                                 slice_of_time.push((ptr, data.clone()));
                                 *data -= Wrapping(1);
                             }
-                            if context.need_history {
-                                self.tape.push(slice_of_time)
+                            if context.records_history() {
+                                self.record(slice_of_time)
                             }
                         }
                     }
                 }
                 Write => {
-                    context.program_output.push_str(
-                        self.pointers
-                            .iter()
-                            .map(|ptr| self.data_at(*ptr).unwrap().0 as char)
-                            .collect::<String>()
-                            .as_str(),
-                    );
+                    let bytes = self
+                        .pointers
+                        .iter()
+                        .map(|ptr| self.cell(*ptr))
+                        .collect::<Vec<_>>();
+                    context
+                        .program_output
+                        .extend(bytes.iter().map(|x| *x as char));
+                    context.output_log.push(OutputChunk {
+                        timeline: self.id,
+                        step: context.steps,
+                        bytes,
+                    });
+                }
+                WriteNumber => {
+                    let mut text = String::new();
+                    for ptr in self.pointers.iter() {
+                        let value = self.cell(*ptr);
+                        text.push_str(value.to_string().as_str());
+                        text.push(NUMBER_SEPARATOR);
+                    }
+                    context.program_output.push_str(text.as_str());
+                    context.output_log.push(OutputChunk {
+                        timeline: self.id,
+                        step: context.steps,
+                        bytes: text.into_bytes(),
+                    });
                 }
                 Read => {
                     let mut slice_of_time = vec![];
                     for ptr in self.pointers.clone() {
-                        let c = if context.program_input.len() == 0 {
+                        let exhausted = context.program_input.is_empty();
+                        let c = if exhausted {
                             '\0'
                         } else {
                             context.program_input.remove(0)
                         };
+                        context.input_log.push(InputRead {
+                            step: context.steps,
+                            timeline: self.id,
+                            instruction: self.instruction_pointer,
+                            offset: Some(context.input_consumed).filter(|_| !exhausted),
+                            value: c as u8,
+                        });
+                        if !exhausted {
+                            context.input_consumed += 1;
+                        }
                         let data = self.data_at_mut(ptr);
                         slice_of_time.push((ptr, data.clone()));
                         *data = Wrapping(c as u8);
                     }
-                    if context.need_history {
-                        self.tape.push(slice_of_time)
+                    if context.records_history() {
+                        self.record(slice_of_time)
                     }
                 }
                 Rewind => {
-                    if let Some(slice_of_time) = self.tape.pop() {
+                    if let Some(slice_of_time) = self.undo() {
                         for (i, history) in slice_of_time {
                             let data = self.data_at_mut(i);
                             *data = history;
                         }
+                    } else {
+                        context.warn(
+                            WarningKind::EmptyHistory,
+                            self.id,
+                            Some(self.instruction_pointer),
+                        );
+                    }
+                }
+                SetMarker => {
+                    self.markers.push(self.tape.len());
+                }
+                // without a marker there is nothing to go back to
+                RewindToMarker => {
+                    if self.markers.is_empty() {
+                        context.warn(
+                            WarningKind::NoMarker,
+                            self.id,
+                            Some(self.instruction_pointer),
+                        );
+                    }
+                    let depth = self.markers.pop().unwrap_or(self.tape.len());
+                    while self.tape.len() > depth {
+                        for (i, history) in self.undo().unwrap() {
+                            let data = self.data_at_mut(i);
+                            *data = history;
+                        }
+                    }
+                }
+                // one slice of every nonzero cell, so a single `~` undoes it
+                ResetTape => {
+                    let slice_of_time = self
+                        .iter_nonzero_cells()
+                        .map(|(i, x)| (i, Wrapping(x)))
+                        .collect::<Vec<_>>();
+
+                    for x in self.data.iter_mut().chain(self.data_backwards.iter_mut()) {
+                        *x = Wrapping(0);
+                    }
+                    if context.records_history() {
+                        self.record(slice_of_time)
+                    }
+                }
+                Random => {
+                    let mut slice_of_time = vec![];
+                    for ptr in self.pointers.clone() {
+                        let data = self.data_at_mut(ptr);
+                        slice_of_time.push((ptr, *data));
+                        *data = Wrapping(context.rng.next_u8());
+                    }
+                    if context.records_history() {
+                        self.record(slice_of_time)
+                    }
+                }
+                RaisePriority => {
+                    self.priority = self.priority.saturating_add(1);
+                }
+                LowerPriority => {
+                    self.priority = self.priority.saturating_sub(1);
+                }
+                // sleeps for as many rounds as the first pointer's cell says
+                Sleep => {
+                    self.sleeping = self
+                        .pointers
+                        .first()
+                        .and_then(|ptr| self.data_at(*ptr))
+                        .map_or(0, |x| x.0 as usize);
+                }
+                // the new pointer starts on the first one's cell, `^` and `v` can part them later
+                SplitPointer => {
+                    if let Some(&first) = self.pointers.first() {
+                        self.pointers.push(first);
                     }
                 }
+                MergePointers => {
+                    self.pointers.truncate(1);
+                }
+                DebugDump => {
+                    context.dumps.push(self.debug_dump());
+                }
+                Kill if context.metadata.first().map(|meta| meta.id) == Some(self.id)
+                    && spawn_exit != Some(self.instruction_pointer) =>
+                {
+                    context.warn(
+                        WarningKind::RootKillIgnored,
+                        self.id,
+                        Some(self.instruction_pointer),
+                    );
+                }
                 _ => (),
             }
 
             // handle instruction pointer related actions
             match action {
                 Jump { type_, index } => match type_ {
-                    IfZero
-                        if self
-                            .pointers
-                            .iter()
-                            .cloned()
-                            .map(|index| self.data_at(index))
-                            .all(|x| *x.unwrap() == Wrapping(0)) =>
-                    {
+                    IfZero if !self.any_nonzero() => {
                         self.instruction_pointer = *index;
                     }
-                    IfNotZero
-                        if self
-                            .pointers
-                            .iter()
-                            .cloned()
-                            .map(|index| self.data_at(index))
-                            .any(|x| *x.unwrap() != Wrapping(0)) =>
-                    {
+                    IfNotZero if self.any_nonzero() => {
                         self.instruction_pointer = *index;
                     }
                     _ => {
                         self.instruction_pointer += 1;
                     }
                 },
+                Goto { index } => {
+                    self.instruction_pointer = *index;
+                }
+                // skips the body like the parent of a spawn would, without the child
+                SpawnIf { index } if !self.spawns_if(context.spawn_if) => {
+                    self.instruction_pointer = *index;
+                    self.spawn_exit = Some(*index);
+                }
+                Call { index } => {
+                    self.call_stack.push(self.instruction_pointer + 1);
+                    self.instruction_pointer = *index;
+                }
+                // returning from the outermost level just carries on
+                Return => {
+                    self.instruction_pointer = match self.call_stack.pop() {
+                        Some(return_address) => return_address,
+                        None => self.instruction_pointer + 1,
+                    };
+                }
+                // waits for `release_barrier` to move it along
+                Barrier => (),
                 Await => {
-                    let (timeline_index, _) = context
-                        .metadata
-                        .iter()
-                        .find_position(|meta| meta.id == self.id)
-                        .unwrap();
-                    if let Some(meta) = context.metadata.get(timeline_index + 1) {
-                        // if timeline below has no pointers
-                        if meta.pointers_count == 0 {
-                            self.instruction_pointer += 1;
-                        }
-                    } else {
-                        // or their is no timeline below this one
+                    if !context.awaiting(self.id) {
                         self.instruction_pointer += 1;
                     }
                 }
@@ -206,7 +405,7 @@ impl Timeline {
             }
 
             // handle command dispatching actions
-            match action {
+            Ok(match action {
                 Kill => (self, Command::RemoveAt(self.id)),
                 Move(dir) => match dir {
                     MoveDirection::Up | MoveDirection::Down => (
@@ -225,10 +424,96 @@ impl Timeline {
                         instruction_start: *index,
                     },
                 ),
+                SpawnIf { index } if self.spawns_if(context.spawn_if) => (
+                    self,
+                    Command::SpawnAt {
+                        id: self.id,
+                        instruction_start: *index,
+                    },
+                ),
+                Fork { index } => (
+                    self,
+                    Command::ForkAt {
+                        id: self.id,
+                        instruction_start: *index,
+                    },
+                ),
+                CopyToBelow => (
+                    self,
+                    Command::CopyCells {
+                        id: self.id,
+                        direction: CopyDirection::ToBelow,
+                    },
+                ),
+                CopyFromBelow => (
+                    self,
+                    Command::CopyCells {
+                        id: self.id,
+                        direction: CopyDirection::FromBelow,
+                    },
+                ),
                 _ => (self, Command::None),
-            }
+            })
         } else {
-            (self, Command::RemoveAt(self.id))
+            Ok((self, Command::RemoveAt(self.id)))
+        }
+    }
+
+    fn tape_overflow(&self, context: &BF5DContext) -> RuntimeError {
+        context.error(
+            RuntimeErrorKind::TapeOverflow,
+            self.id,
+            Some(self.instruction_pointer),
+        )
+    }
+
+    // the error carries what the cell held before, so it shows how the value got there
+    fn cell_overflow(
+        &self,
+        context: &BF5DContext,
+        index: isize,
+        update: UpdateType,
+    ) -> RuntimeError {
+        let mut history = self
+            .tape
+            .iter()
+            .rev()
+            .flat_map(|slice_of_time| slice_of_time.iter().rev())
+            .filter(|(i, _)| *i == index)
+            .map(|(_, x)| x.0)
+            .take(OVERFLOW_HISTORY)
+            .collect::<Vec<_>>();
+        history.reverse();
+
+        context.error(
+            RuntimeErrorKind::CellOverflow {
+                cell: index,
+                value: self.cell(index),
+                update,
+                history,
+            },
+            self.id,
+            Some(self.instruction_pointer),
+        )
+    }
+
+    /// Whether the scheduler lets this timeline execute in the given round.
+    pub fn runs_in(&self, round: usize) -> bool {
+        self.priority >= 0 || round.is_multiple_of(self.priority.unsigned_abs() + 1)
+    }
+
+    fn any_nonzero(&self) -> bool {
+        self.pointers
+            .iter()
+            .any(|ptr| self.data_at(*ptr).is_some_and(|x| x.0 != 0))
+    }
+
+    /// Whether `I` spawns from here under `policy`, never without a pointer.
+    pub fn spawns_if(&self, policy: SpawnIfPolicy) -> bool {
+        let nonzero = |ptr: &isize| self.data_at(*ptr).is_some_and(|x| x.0 != 0);
+        match policy {
+            SpawnIfPolicy::Any => self.pointers.iter().any(nonzero),
+            SpawnIfPolicy::All => !self.pointers.is_empty() && self.pointers.iter().all(nonzero),
         }
     }
 
@@ -282,12 +567,237 @@ impl Timeline {
 
         (*data).get(index)
     }
+
+    /// The value at `index` on either side of the tape, cells never touched read as zero.
+    pub fn cell(&self, index: isize) -> u8 {
+        self.data_at(index).map_or(0, |x| x.0)
+    }
+
+    /// Every allocated cell as `(index, value)`, from the lowest index up.
+    pub fn iter_cells(&self) -> impl Iterator<Item = (isize, u8)> + '_ {
+        let backwards = self
+            .data_backwards
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, x)| (-(i as isize) - 1, x.0));
+        let forwards = self.data.iter().enumerate().map(|(i, x)| (i as isize, x.0));
+        backwards.chain(forwards)
+    }
+
+    pub fn iter_nonzero_cells(&self) -> impl Iterator<Item = (isize, u8)> + '_ {
+        self.iter_cells().filter(|(_, x)| *x != 0)
+    }
+
+    /// Forgets the history, `~` and `\` can't go back past here: a marker set before now
+    /// rewinds to here.
+    pub fn commit(&mut self) {
+        self.tape.clear();
+        self.history_len = 0;
+        for marker in self.markers.iter_mut() {
+            *marker = 0;
+        }
+    }
+
+    fn record(&mut self, slice_of_time: Vec<(isize, Wrapping<u8>)>) {
+        self.history_len += slice_of_time.len();
+        self.tape.push(slice_of_time);
+    }
+
+    fn undo(&mut self) -> Option<Vec<(isize, Wrapping<u8>)>> {
+        let slice_of_time = self.tape.pop()?;
+        self.history_len -= slice_of_time.len();
+        Some(slice_of_time)
+    }
+
+    /// Roughly the bytes the tape, its history and the pointers take up.
+    pub fn memory_usage(&self) -> usize {
+        let addresses = self.pointers.len() + self.call_stack.len() + self.markers.len();
+        self.data.len()
+            + self.data_backwards.len()
+            + self.history_len * mem::size_of::<(isize, Wrapping<u8>)>()
+            + addresses * mem::size_of::<usize>()
+    }
+
+    // the cells worth showing, within `DUMP_RADIUS` of a pointer
+    fn window(&self) -> (isize, isize) {
+        match (self.pointers.iter().min(), self.pointers.iter().max()) {
+            (Some(low), Some(high)) => (low - DUMP_RADIUS, high + DUMP_RADIUS),
+            _ => (-DUMP_RADIUS, DUMP_RADIUS),
+        }
+    }
+
+    /// The cells around the pointers, e.g. `timeline 0 ip 3 pointers [0] | -1:0 *0:72 1:0`.
+    pub fn debug_dump(&self) -> String {
+        let (low, high) = self.window();
+
+        let cells = (low..=high)
+            .map(|i| {
+                let marker = if self.pointers.contains(&i) { "*" } else { "" };
+                format!("{}{}:{}", marker, i, self.cell(i))
+            })
+            .join(" ");
+
+        format!(
+            "timeline {} ip {} pointers {:?} | {}",
+            self.id, self.instruction_pointer, self.pointers, cells
+        )
+    }
+}
+
+impl fmt::Display for Timeline {
+    /// The same window as `debug_dump` as a table, pointers marked underneath:
+    ///
+    /// ```text
+    /// timeline 0 ip 3 history 1
+    ///   -3  -2  -1   0   1   2   3
+    ///    0   0   0  72   0   0   0
+    ///                ^
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (low, high) = self.window();
+        let column = |text: String| format!("{:>4}", text);
+
+        let indices = (low..=high).map(|i| column(i.to_string())).join("");
+        let values = (low..=high)
+            .map(|i| column(self.cell(i).to_string()))
+            .join("");
+        let markers = (low..=high)
+            .map(|i| column(if self.pointers.contains(&i) { "^" } else { "" }.to_string()))
+            .join("");
+
+        writeln!(
+            f,
+            "timeline {} ip {} history {}",
+            self.id,
+            self.instruction_pointer,
+            self.tape.len()
+        )?;
+        writeln!(f, "{}", indices)?;
+        writeln!(f, "{}", values)?;
+        write!(f, "{}", markers.trim_end())
+    }
 }
 
+// cells shown on either side of the pointers by `#`
+const DUMP_RADIUS: isize = 3;
+// written after every number printed by `:`
+const NUMBER_SEPARATOR: char = ' ';
+// earlier values of the cell an overflow trap reports
+const OVERFLOW_HISTORY: usize = 8;
+
+/// Warnings a context keeps, a program repeating one in a loop would otherwise fill the memory.
+pub const WARNINGS_KEPT: usize = 1000;
+
 #[derive(Debug, Clone)]
 pub struct TimelineMeta {
-    id: usize,
-    pointers_count: usize,
+    pub id: usize,
+    pub pointers_count: usize,
+    pub parent_id: Option<ID>,
+    pub spawned_at_step: Option<usize>,
+    pub steps_executed: usize,
+    pub last_active_step: Option<usize>,
+}
+
+/// Every timeline still running waits on `@` or `|`, so none of them will ever move again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deadlock {
+    // (timeline id, instruction it waits at)
+    pub blocked: Vec<(ID, usize)>,
+}
+
+impl fmt::Display for Deadlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadlock:")?;
+        for (i, (id, instruction)) in self.blocked.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(
+                f,
+                "{}timeline {} waits at instruction {}",
+                separator, id, instruction
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// What one `.` or `:` wrote, `bytes` are the cell values for `.` and the digits for `:`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputChunk {
+    pub timeline: ID,
+    pub step: usize,
+    pub bytes: Vec<u8>,
+}
+
+/// Where a character of the input went.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputRead {
+    pub step: usize,
+    pub timeline: ID,
+    pub instruction: usize,
+    // position in the input, `None` once it ran out and `value` is a zero
+    pub offset: Option<usize>,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Birth {
+    pub id: ID,
+    pub parent_id: ID,
+    pub step: usize,
+}
+
+/// What `@` waits for.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AwaitPolicy {
+    /// Until there is no timeline directly below.
+    BelowDead,
+    /// Until the timeline directly below has no pointers left, or there is none.
+    #[default]
+    BelowPointerless,
+    /// Until every timeline this one spawned or forked has been killed.
+    AllChildren,
+}
+
+/// What `+` on 255 and `-` on 0 do.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OverflowPolicy {
+    #[default]
+    Wrap,
+    /// Stops with a `CellOverflow` error, recording history so the error can show it.
+    Trap,
+}
+
+/// How `fuel` is handed out.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FuelPolicy {
+    /// `fuel` instructions every round, what a turn cut short doesn't use is gone.
+    #[default]
+    Quantum,
+    /// What a turn cut short by a command leaves carries over, so a timeline that keeps stopping
+    /// for them gets as many instructions as the busy ones do. A timeline waiting or asleep has
+    /// nothing to catch up on, those turns leave no fuel over.
+    FairShare,
+}
+
+/// Which of the cells under the pointers `I` needs to be nonzero to spawn.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SpawnIfPolicy {
+    #[default]
+    Any,
+    All,
+}
+
+/// When the timelines commit without the host asking, see `Timeline::commit`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CommitPolicy {
+    #[default]
+    Never,
+    /// All of them once every `n` rounds.
+    Every(usize),
+    /// A timeline as soon as it wrote or read, a rewind doesn't take back the output or give back
+    /// the input anyway.
+    OnIo,
 }
 
 fn backwards_index(index: isize) -> usize {
@@ -302,11 +812,53 @@ fn backwards_index(index: isize) -> usize {
 pub struct BF5DContext {
     pub raw_program: String,
     pub tokens: Vec<Token>,
+    // where each token came from in `raw_program`, empty for tokens built by hand
+    pub spans: Vec<Range<usize>>,
     pub program_input: String,
     pub program_output: String,
     pub total_timelines: usize,
     pub metadata: Vec<TimelineMeta>,
     pub need_history: bool,
+    pub extensions: Extensions,
+    // what `#` dumped, for the host to show
+    pub dumps: Vec<String>,
+    // seed it for reproducible `?`
+    pub rng: Rng,
+    // rounds stepped so far
+    pub steps: usize,
+    // instructions run so far, by all the timelines together
+    pub instructions: usize,
+    // whether the last round let the timelines through a `|`
+    pub barrier_released: bool,
+    pub scheduling: SchedulingPolicy,
+    // instructions each timeline may run per round
+    pub fuel: usize,
+    pub fuel_policy: FuelPolicy,
+    // the next timeline id once `make_deterministic` was called, ids are process wide before that
+    pub id_gen: Option<ID>,
+    pub await_policy: AwaitPolicy,
+    pub overflow: OverflowPolicy,
+    pub spawn_if: SpawnIfPolicy,
+    // when the timelines drop the history behind them, bounding what a long run keeps of it
+    pub commit: CommitPolicy,
+    // set instead of stepping once nothing can make progress
+    pub deadlock: Option<Deadlock>,
+    // every spawn and fork so far, killed timelines included
+    pub births: Vec<Birth>,
+    // `program_output` split up by who wrote it when
+    pub output_log: Vec<OutputChunk>,
+    // one entry per cell `,` filled
+    pub input_log: Vec<InputRead>,
+    // characters taken off the front of `program_input` so far
+    pub input_consumed: usize,
+    // oddities the engine carried on past, oldest first, the first `WARNINGS_KEPT` of them
+    pub warnings: Vec<Warning>,
+    // those past `WARNINGS_KEPT`, only counted
+    pub warnings_dropped: usize,
+    // whether timelines that are removed go to `dead`, for looking the whole run over after
+    pub keep_dead: bool,
+    // the removed timelines with `alive` unset, in the order they went
+    pub dead: Vec<Timeline>,
 }
 
 impl BF5DContext {
@@ -314,14 +866,91 @@ impl BF5DContext {
         BF5DContext {
             raw_program: "".to_string(),
             tokens: vec![],
+            spans: vec![],
             program_input: "".to_string(),
             program_output: "".to_string(),
             total_timelines: 0,
             metadata: vec![],
             need_history: true,
+            extensions: Extensions::default(),
+            dumps: vec![],
+            rng: Rng::new(0),
+            steps: 0,
+            instructions: 0,
+            barrier_released: false,
+            scheduling: SchedulingPolicy::default(),
+            fuel: 1,
+            fuel_policy: FuelPolicy::default(),
+            id_gen: None,
+            await_policy: AwaitPolicy::default(),
+            overflow: OverflowPolicy::default(),
+            spawn_if: SpawnIfPolicy::default(),
+            commit: CommitPolicy::default(),
+            deadlock: None,
+            births: vec![],
+            output_log: vec![],
+            input_log: vec![],
+            input_consumed: 0,
+            warnings: vec![],
+            warnings_dropped: 0,
+            keep_dead: false,
+            dead: vec![],
+        }
+    }
+
+    pub fn new_id(&mut self) -> ID {
+        match self.id_gen.as_mut() {
+            Some(next) => {
+                *next += 1;
+                *next - 1
+            }
+            None => next_id(),
         }
     }
 
+    /// Takes everything that could differ between two runs out of the picture: timeline ids count
+    /// up from zero, `?` and random scheduling are seeded with `seed`.
+    pub fn make_deterministic(&mut self, seed: u64) {
+        self.id_gen = Some(0);
+        self.rng = Rng::new(seed);
+        if let SchedulingPolicy::Random { .. } = self.scheduling {
+            self.scheduling = SchedulingPolicy::Random { seed };
+        }
+    }
+
+    pub fn set_tokens(&mut self, tokens: Vec<Token>) {
+        // history is only worth recording when something can rewind it
+        self.need_history =
+            tokens.contains(&Token::Rewind) || tokens.contains(&Token::RewindToMarker);
+        self.tokens = tokens;
+        self.spans.clear();
+    }
+
+    /// `need_history`, or an overflow trap that wants to show how a cell got to where it is.
+    pub fn records_history(&self) -> bool {
+        self.need_history || self.overflow == OverflowPolicy::Trap
+    }
+
+    /// Fills in the span of `instruction` and the current step.
+    pub fn error(
+        &self,
+        kind: RuntimeErrorKind,
+        timeline: ID,
+        instruction: Option<usize>,
+    ) -> RuntimeError {
+        RuntimeError {
+            kind,
+            timeline,
+            instruction,
+            span: self.span_of(instruction),
+            step: self.steps,
+        }
+    }
+
+    fn span_of(&self, instruction: Option<usize>) -> Option<Range<usize>> {
+        instruction.and_then(|i| self.spans.get(i).cloned())
+    }
+
     pub fn collect_timeline_metadata(self: &mut Self, timelines: &Vec<Timeline>) {
         self.total_timelines = timelines.len();
         self.metadata = timelines
@@ -329,16 +958,149 @@ impl BF5DContext {
             .map(|t| TimelineMeta {
                 id: t.id,
                 pointers_count: t.pointers.len(),
+                parent_id: t.parent_id,
+                spawned_at_step: t.spawned_at_step,
+                steps_executed: t.steps_executed,
+                last_active_step: t.last_active_step,
             })
             .collect();
     }
 
-    pub fn execute_command(self: &Self, command: Command, timelines: &mut Vec<Timeline>) {
+    /// The position and metadata of timeline `id` as of the last `collect_timeline_metadata`,
+    /// `None` for timelines spawned since.
+    pub fn meta(&self, id: ID) -> Option<(usize, &TimelineMeta)> {
+        self.metadata.iter().find_position(|meta| meta.id == id)
+    }
+
+    /// Whether `@` holds the timeline back, going by the metadata collected this round.
+    ///
+    /// A timeline the metadata doesn't know about yet keeps waiting until the next round.
+    pub fn awaiting(&self, id: ID) -> bool {
+        let index = match self.meta(id) {
+            Some((index, _)) => index,
+            None => return true,
+        };
+        let below = self.metadata.get(index + 1);
+
+        match self.await_policy {
+            AwaitPolicy::BelowDead => below.is_some(),
+            AwaitPolicy::BelowPointerless => below.is_some_and(|meta| meta.pointers_count != 0),
+            AwaitPolicy::AllChildren => self.metadata.iter().any(|meta| meta.parent_id == Some(id)),
+        }
+    }
+
+    /// Moves every timeline past its `|` once no timeline that could still arrive is running.
+    ///
+    /// Finished timelines and timelines blocked on `@` don't hold the others up.
+    pub fn release_barrier(&mut self, timelines: &mut [Timeline]) {
+        let tokens = &self.tokens;
+        let at_barrier = |t: &Timeline| tokens.get(t.instruction_pointer) == Some(&Token::Barrier);
+        let arrived = timelines
+            .iter()
+            .all(|t| match tokens.get(t.instruction_pointer) {
+                Some(Token::Barrier) | None => true,
+                Some(Token::Await) => self.awaiting(t.id),
+                _ => false,
+            });
+
+        let released = self.extensions.contains(Extensions::BARRIER)
+            && arrived
+            && timelines.iter().any(at_barrier);
+        if released {
+            for timeline in timelines.iter_mut().filter(|t| at_barrier(t)) {
+                timeline.instruction_pointer += 1;
+            }
+        }
+        self.barrier_released = released;
+    }
+
+    /// Meant to run right after `release_barrier`, any timeline still at a `|` by then is stuck,
+    /// unless the barrier was just released, those it let through can be at the next `|` already.
+    ///
+    /// A suspended timeline could still be resumed by the host, so there's no deadlock while one
+    /// has work left.
+    pub fn find_deadlock(&self, timelines: &[Timeline]) -> Option<Deadlock> {
+        if self.barrier_released {
+            return None;
+        }
+        let mut blocked = vec![];
+        for timeline in timelines {
+            let waits = match self.tokens.get(timeline.instruction_pointer) {
+                None => continue,
+                Some(_) if timeline.suspended => return None,
+                Some(Token::Await) => self.awaiting(timeline.id),
+                Some(Token::Barrier) => self.extensions.contains(Extensions::BARRIER),
+                Some(_) => false,
+            };
+            if !waits {
+                return None;
+            }
+            blocked.push((timeline.id, timeline.instruction_pointer));
+        }
+
+        if blocked.is_empty() {
+            None
+        } else {
+            Some(Deadlock { blocked })
+        }
+    }
+
+    fn record_birth(&mut self, timeline: &Timeline) {
+        if let (Some(parent_id), Some(step)) = (timeline.parent_id, timeline.spawned_at_step) {
+            self.births.push(Birth {
+                id: timeline.id,
+                parent_id,
+                step,
+            });
+        }
+    }
+
+    /// Like `error`, for `warnings`.
+    pub fn warning(&self, kind: WarningKind, timeline: ID, instruction: Option<usize>) -> Warning {
+        Warning {
+            kind,
+            timeline,
+            instruction,
+            span: self.span_of(instruction),
+            step: self.steps,
+        }
+    }
+
+    /// Records a warning, or only counts it once there are `WARNINGS_KEPT`.
+    pub fn warn(&mut self, kind: WarningKind, timeline: ID, instruction: Option<usize>) {
+        if self.warnings.len() < WARNINGS_KEPT {
+            let warning = self.warning(kind, timeline, instruction);
+            self.warnings.push(warning);
+        } else {
+            self.warnings_dropped += 1;
+        }
+    }
+
+    /// Every warning so far, the dropped ones too.
+    pub fn warning_count(&self) -> usize {
+        self.warnings.len() + self.warnings_dropped
+    }
+
+    /// Applies a command from the round that just ran.
+    ///
+    /// A command for a timeline that's gone is skipped. A timeline only ever removes itself, with
+    /// its last command of the round, so that only happens to a host applying commands itself.
+    pub fn execute_command(
+        &mut self,
+        command: Command,
+        timelines: &mut Vec<Timeline>,
+    ) -> Result<(), RuntimeError> {
+        let index = match command
+            .timeline()
+            .and_then(|id| timelines.iter().position(|t| t.id == id))
+        {
+            Some(index) => index,
+            None => return Ok(()),
+        };
         match command {
             Command::MovePointer { id, direction } => match direction {
                 MoveDirection::Up => {
-                    let (index, timeline) =
-                        timelines.iter_mut().find_position(|t| t.id == id).unwrap();
+                    let timeline = &mut timelines[index];
 
                     if index != 0 {
                         let pointers = timeline.pointers.clone();
@@ -348,41 +1110,123 @@ impl BF5DContext {
                         for ptr in pointers {
                             target.extend_data(ptr);
                         }
-                    } else {
+                    } else if !timeline.pointers.is_empty() {
                         timeline.pointers.clear();
+                        self.warn(WarningKind::PointersDroppedAbove, id, None);
                     }
                 }
                 MoveDirection::Down => {
-                    let (index, timeline) =
-                        timelines.iter_mut().find_position(|t| t.id == id).unwrap();
+                    if index + 1 == timelines.len() && index != 0 {
+                        self.warn(WarningKind::NoTimelineBelow, id, None);
+                        return Ok(());
+                    }
+                    let timeline = &mut timelines[index];
 
                     if index != 0 {
                         let pointers = timeline.pointers.clone();
                         timeline.pointers.clear();
-                        let target = timelines.get_mut(index + 1).unwrap();
+                        let target = &mut timelines[index + 1];
                         target.pointers.extend(pointers.clone());
-                    } else {
+                        for ptr in pointers {
+                            target.extend_data(ptr);
+                        }
+                    } else if !timeline.pointers.is_empty() {
                         timeline.pointers.clear();
+                        self.warn(WarningKind::PointersDroppedBelow, id, None);
                     }
                 }
-                _ => panic!("undefined command direction"),
+                _ => {
+                    return Err(self.error(
+                        RuntimeErrorKind::InvalidCommand(format!(
+                            "pointers can't move {:?} between timelines",
+                            direction
+                        )),
+                        id,
+                        None,
+                    ))
+                }
             },
             Command::SpawnAt {
                 id,
                 instruction_start,
             } => {
-                let (index, timeline) = timelines.iter_mut().find_position(|t| t.id == id).unwrap();
-                let new_timeline = timeline.clone_new_id();
+                let timeline = &mut timelines[index];
+                let new_timeline = Timeline {
+                    parent_id: Some(id),
+                    spawned_at_step: Some(self.steps),
+                    steps_executed: 0,
+                    last_active_step: None,
+                    credit: 0,
+                    ..timeline.clone_with_id(self.new_id())
+                };
                 timeline.instruction_pointer = instruction_start;
+                timeline.spawn_exit = Some(instruction_start);
+                self.record_birth(&new_timeline);
                 timelines.insert(index + 1, new_timeline);
             }
-            Command::RemoveAt(id) => {
-                let (index, _) = timelines.iter().find_position(|t| t.id == id).unwrap();
+            Command::RemoveAt(_) => {
                 if index != 0 {
-                    timelines.remove(index);
+                    let mut timeline = timelines.remove(index);
+                    if self.keep_dead {
+                        timeline.alive = false;
+                        timeline.died_at_step = Some(self.steps);
+                        self.dead.push(timeline);
+                    }
+                }
+            }
+            // like `SpawnAt`, with one child per pointer in pointer order
+            Command::ForkAt {
+                id,
+                instruction_start,
+            } => {
+                let timeline = &mut timelines[index];
+                let children = timeline
+                    .pointers
+                    .iter()
+                    .map(|ptr| Timeline {
+                        pointers: vec![*ptr],
+                        parent_id: Some(id),
+                        spawned_at_step: Some(self.steps),
+                        steps_executed: 0,
+                        last_active_step: None,
+                        credit: 0,
+                        ..timeline.clone_with_id(self.new_id())
+                    })
+                    .collect::<Vec<_>>();
+                timeline.instruction_pointer = instruction_start;
+                timeline.spawn_exit = Some(instruction_start);
+                for (offset, child) in children.into_iter().enumerate() {
+                    self.record_birth(&child);
+                    timelines.insert(index + 1 + offset, child);
+                }
+            }
+            // the timeline below is addressed through the copying timeline's pointers
+            Command::CopyCells { direction, .. } => {
+                if index + 1 < timelines.len() {
+                    let (from, to) = match direction {
+                        CopyDirection::ToBelow => (index, index + 1),
+                        CopyDirection::FromBelow => (index + 1, index),
+                    };
+                    let pointers = timelines[index].pointers.clone();
+                    let values = pointers
+                        .iter()
+                        .map(|ptr| timelines[from].data_at(*ptr).map_or(Wrapping(0), |x| *x))
+                        .collect::<Vec<_>>();
+
+                    let target = &mut timelines[to];
+                    let mut slice_of_time = vec![];
+                    for (ptr, value) in pointers.into_iter().zip(values) {
+                        let data = target.data_at_mut(ptr);
+                        slice_of_time.push((ptr, *data));
+                        *data = value;
+                    }
+                    if self.records_history() {
+                        target.record(slice_of_time)
+                    }
                 }
             }
             Command::None => (),
         }
+        Ok(())
     }
 }
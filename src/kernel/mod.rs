@@ -0,0 +1,492 @@
+// A Jupyter kernel, `bf5d-cli kernel CONNECTION_FILE` with the `kernel` feature.
+//
+// Spoken over ZeroMQ: shell and control are ROUTER sockets polled together, iopub a PUB and the
+// heartbeat a REP echoing on a thread of its own. Requests are answered one at a time, like a
+// notebook runs its cells.
+use std::{
+    io, thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    interpreter::rng::Rng,
+    parser::{bf5d::parse_with, types::Extensions},
+};
+
+pub mod session;
+pub mod signature;
+
+use session::Session;
+
+const PROTOCOL_VERSION: &str = "5.3";
+// between the routing ids and the signed parts of every message
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+/// The connection file Jupyter starts a kernel with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionInfo {
+    pub ip: String,
+    pub shell_port: usize,
+    pub iopub_port: usize,
+    pub stdin_port: usize,
+    pub control_port: usize,
+    pub hb_port: usize,
+    pub key: String,
+}
+
+impl ConnectionInfo {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let json = serde_json::from_str::<Value>(text)
+            .map_err(|_| "the connection file isn't JSON".to_string())?;
+        match json["transport"].as_str() {
+            None | Some("tcp") => (),
+            Some(transport) => return Err(format!("unsupported transport {:?}", transport)),
+        }
+        match json["signature_scheme"].as_str() {
+            None | Some("hmac-sha256") => (),
+            Some(scheme) => return Err(format!("unsupported signature scheme {:?}", scheme)),
+        }
+        let port = |name: &str| {
+            json[name]
+                .as_u64()
+                .map(|port| port as usize)
+                .ok_or_else(|| format!("the connection file has no {}", name))
+        };
+        Ok(ConnectionInfo {
+            ip: json["ip"].as_str().unwrap_or("127.0.0.1").to_string(),
+            shell_port: port("shell_port")?,
+            iopub_port: port("iopub_port")?,
+            stdin_port: port("stdin_port")?,
+            control_port: port("control_port")?,
+            hb_port: port("hb_port")?,
+            key: json["key"].as_str().unwrap_or("").to_string(),
+        })
+    }
+
+    fn endpoint(&self, port: usize) -> String {
+        format!("tcp://{}:{}", self.ip, port)
+    }
+}
+
+/// Binds every socket and answers requests until a `shutdown_request`.
+pub fn serve(connection: &ConnectionInfo, extensions: Extensions) -> io::Result<()> {
+    let context = zmq::Context::new();
+    let bind = |socket_type, port| -> io::Result<zmq::Socket> {
+        let socket = context.socket(socket_type)?;
+        socket.bind(&connection.endpoint(port))?;
+        Ok(socket)
+    };
+    let shell = bind(zmq::ROUTER, connection.shell_port)?;
+    let control = bind(zmq::ROUTER, connection.control_port)?;
+    // the kernel never asks for input, `%input` takes care of `,`
+    let _stdin = bind(zmq::ROUTER, connection.stdin_port)?;
+    let heartbeat = bind(zmq::REP, connection.hb_port)?;
+    thread::spawn(move || -> zmq::Result<()> {
+        loop {
+            let ping = heartbeat.recv_bytes(0)?;
+            heartbeat.send(ping, 0)?;
+        }
+    });
+
+    let mut kernel = Kernel {
+        session: Session::new(extensions),
+        key: connection.key.clone().into_bytes(),
+        id: random_id(),
+        sent: 0,
+        execution_count: 0,
+        iopub: bind(zmq::PUB, connection.iopub_port)?,
+    };
+    loop {
+        let mut ready = [
+            control.as_poll_item(zmq::POLLIN),
+            shell.as_poll_item(zmq::POLLIN),
+        ];
+        zmq::poll(&mut ready, -1)?;
+        // control first, an interrupt or a shutdown doesn't wait behind the cells
+        let socket = if ready[0].is_readable() {
+            &control
+        } else {
+            &shell
+        };
+        let frames = socket.recv_multipart(0)?;
+        if !kernel.handle(socket, frames) {
+            return Ok(());
+        }
+    }
+}
+
+struct Kernel {
+    session: Session,
+    key: Vec<u8>,
+    // the session id of the kernel's own messages
+    id: String,
+    sent: usize,
+    execution_count: usize,
+    iopub: zmq::Socket,
+}
+
+// a request taken apart, the JSON parts parsed
+struct Message {
+    identities: Vec<Vec<u8>>,
+    header: Value,
+    content: Value,
+}
+
+impl Kernel {
+    // `false` once the kernel should stop
+    fn handle(&mut self, socket: &zmq::Socket, frames: Vec<Vec<u8>>) -> bool {
+        let message = match open(&self.key, frames) {
+            Some(message) => message,
+            // unsigned, or not a Jupyter message at all
+            None => return true,
+        };
+        let msg_type = message.header["msg_type"].as_str().unwrap_or("");
+        let reply_type = msg_type.replace("_request", "_reply");
+
+        self.publish("status", &message.header, status("busy"));
+        let mut keep_going = true;
+        let reply = match msg_type {
+            "kernel_info_request" => Some(kernel_info()),
+            "execute_request" => Some(self.execute(&message)),
+            "is_complete_request" => {
+                let code = message.content["code"].as_str().unwrap_or("");
+                Some(is_complete(code, &self.session))
+            }
+            "comm_info_request" => Some(json!({ "status": "ok", "comms": {} })),
+            // a cell can't be stopped halfway, `%steps` is what keeps them short
+            "interrupt_request" => Some(json!({ "status": "ok" })),
+            "shutdown_request" => {
+                keep_going = false;
+                Some(json!({ "status": "ok", "restart": message.content["restart"] }))
+            }
+            _ => None,
+        };
+        if let Some(content) = reply {
+            let frames = self.sign(&message.identities, &reply_type, &message.header, content);
+            // a ROUTER drops what it can't route, only a closed context is left to fail
+            let _ = socket.send_multipart(frames, 0);
+        }
+        self.publish("status", &message.header, status("idle"));
+        keep_going
+    }
+
+    fn execute(&mut self, message: &Message) -> Value {
+        let code = message.content["code"].as_str().unwrap_or("");
+        let silent = message.content["silent"] == true;
+        if !silent {
+            self.execution_count += 1;
+        }
+        let count = self.execution_count;
+        let parent = &message.header;
+        self.publish(
+            "execute_input",
+            parent,
+            json!({ "code": code, "execution_count": count }),
+        );
+
+        let outcome = self.session.execute(code);
+        for display in outcome.displays {
+            self.publish(
+                "display_data",
+                parent,
+                json!({ "data": { "text/plain": display }, "metadata": {} }),
+            );
+        }
+        if !outcome.output.is_empty() && !silent {
+            self.publish(
+                "stream",
+                parent,
+                json!({ "name": "stdout", "text": outcome.output }),
+            );
+        }
+
+        match outcome.error {
+            None => json!({
+                "status": "ok",
+                "execution_count": count,
+                "payload": [],
+                "user_expressions": {},
+            }),
+            Some((name, value)) => {
+                let traceback = format!("{}: {}", name, value);
+                self.publish(
+                    "error",
+                    parent,
+                    json!({ "ename": name, "evalue": value, "traceback": [traceback] }),
+                );
+                json!({
+                    "status": "error",
+                    "execution_count": count,
+                    "ename": name,
+                    "evalue": value,
+                    "traceback": [traceback],
+                })
+            }
+        }
+    }
+
+    // the frames of a message from the kernel, in reply to `parent`
+    fn sign(
+        &mut self,
+        identities: &[Vec<u8>],
+        msg_type: &str,
+        parent: &Value,
+        content: Value,
+    ) -> Vec<Vec<u8>> {
+        self.sent += 1;
+        let header = json!({
+            "msg_id": format!("{}-{}", self.id, self.sent),
+            "session": self.id,
+            "username": "bf5d",
+            "date": now(),
+            "msg_type": msg_type,
+            "version": PROTOCOL_VERSION,
+        });
+        let parts = [
+            header.to_string().into_bytes(),
+            parent.to_string().into_bytes(),
+            b"{}".to_vec(),
+            content.to_string().into_bytes(),
+        ];
+        let signature = if self.key.is_empty() {
+            String::new()
+        } else {
+            let signed = parts.iter().map(Vec::as_slice).collect::<Vec<_>>();
+            signature::sign(&self.key, &signed)
+        };
+
+        let mut frames = identities.to_vec();
+        frames.push(DELIMITER.to_vec());
+        frames.push(signature.into_bytes());
+        frames.extend(parts.iter().cloned());
+        frames
+    }
+
+    // to every subscriber on iopub, a PUB without any drops it
+    fn publish(&mut self, msg_type: &str, parent: &Value, content: Value) {
+        let frames = self.sign(&[msg_type.as_bytes().to_vec()], msg_type, parent, content);
+        let _ = self.iopub.send_multipart(frames, 0);
+    }
+}
+
+// checks the signature and parses the parts of a message
+fn open(key: &[u8], mut frames: Vec<Vec<u8>>) -> Option<Message> {
+    let delimiter = frames.iter().position(|frame| frame == DELIMITER)?;
+    let parts = frames.split_off(delimiter);
+    if parts.len() < 6 {
+        return None;
+    }
+    let signed = parts[2..6].iter().map(Vec::as_slice).collect::<Vec<_>>();
+    if !key.is_empty() && !signature::verify(key, &signed, &parts[1]) {
+        return None;
+    }
+
+    let json = |part: &[u8]| serde_json::from_slice::<Value>(part).ok();
+    Some(Message {
+        identities: frames,
+        header: json(&parts[2])?,
+        content: json(&parts[5])?,
+    })
+}
+
+fn status(state: &str) -> Value {
+    json!({ "execution_state": state })
+}
+
+fn kernel_info() -> Value {
+    json!({
+        "status": "ok",
+        "protocol_version": PROTOCOL_VERSION,
+        "implementation": "bf5d",
+        "implementation_version": env!("CARGO_PKG_VERSION"),
+        "language_info": {
+            "name": "bf5d",
+            "version": env!("CARGO_PKG_VERSION"),
+            "mimetype": "text/x-bf5d",
+            "file_extension": ".bf5d",
+        },
+        "banner": "BF5D, the timelines carry over from cell to cell, %state shows them",
+    })
+}
+
+// a cell with a bracket left open asks the console for another line
+fn is_complete(code: &str, session: &Session) -> Value {
+    let program = code
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('%'))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let status = match parse_with(&program, session.extensions) {
+        Ok(_) => "complete",
+        // only an opening one can still be closed by the lines to come
+        Err(e) => match e.span().map(|span| &program[span]) {
+            Some("[") | Some("(") | Some("Y") | Some("I")
+                if e.message().starts_with("unmatched") =>
+            {
+                "incomplete"
+            }
+            _ => "invalid",
+        },
+    };
+    json!({ "status": status, "indent": "" })
+}
+
+fn random_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64);
+    let mut rng = Rng::new(nanos ^ u64::from(std::process::id()));
+    format!("{:016x}{:016x}", rng.next_u64(), rng.next_u64())
+}
+
+// ISO 8601 in UTC, the way message headers want it
+fn now() -> String {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = elapsed.as_secs();
+    let (days, time) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // days since 1970 to a date, Howard Hinnant's `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        elapsed.subsec_micros()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    const KEY: &[u8] = b"c4a1e7a2-6d3b-4f0e-9a7c-1f2e3d4c5b6a";
+    // a `kernel_info_request` as jupyter_client signs it
+    const HEADER: &str = concat!(
+        r#"{"msg_id":"0f3c9a2e-1b7d-4e55-a1c2-9d8e7f6a5b4c","#,
+        r#""session":"5d2e8f1a-3c4b-4a6d-9e0f-1a2b3c4d5e6f","username":"jovyan","#,
+        r#""date":"2024-03-01T12:00:00.000000Z","msg_type":"kernel_info_request","version":"5.3"}"#
+    );
+    const SIGNATURE: &str = "493127286551dc3ce1b83b5e7dcccb770489112168e8b3906e5937a0d20dbfb1";
+
+    fn frames(signature: &str, header: &str, content: &str) -> Vec<Vec<u8>> {
+        [
+            "routing-id",
+            "<IDS|MSG>",
+            signature,
+            header,
+            "{}",
+            "{}",
+            content,
+        ]
+        .iter()
+        .map(|frame| frame.as_bytes().to_vec())
+        .collect()
+    }
+
+    // what a client sends, signed with `KEY`
+    fn request(msg_type: &str, content: &str) -> Vec<Vec<u8>> {
+        let header = json!({ "msg_id": msg_type, "msg_type": msg_type }).to_string();
+        let parts: &[&[u8]] = &[header.as_bytes(), b"{}", b"{}", content.as_bytes()];
+        frames(&signature::sign(KEY, parts), &header, content)[1..].to_vec()
+    }
+
+    #[test]
+    fn opens_messages_signed_by_jupyter() {
+        let message = open(KEY, frames(SIGNATURE, HEADER, "{}")).unwrap();
+        assert_eq!(message.identities, vec![b"routing-id".to_vec()]);
+        assert_eq!(message.header["msg_type"], "kernel_info_request");
+        assert_eq!(message.content, json!({}));
+
+        assert!(open(b"another key", frames(SIGNATURE, HEADER, "{}")).is_none());
+        assert!(open(KEY, frames(SIGNATURE, HEADER, r#"{"a":1}"#)).is_none());
+        // without a key nothing is signed
+        assert!(open(b"", frames("", HEADER, "{}")).is_some());
+    }
+
+    #[test]
+    fn answers_over_zeromq() {
+        // ports nothing else has, held until all are picked so none comes up twice
+        let listeners = (0..5)
+            .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect::<Vec<_>>();
+        let ports = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap().port() as usize)
+            .collect::<Vec<_>>();
+        drop(listeners);
+        let connection = ConnectionInfo {
+            ip: "127.0.0.1".to_string(),
+            shell_port: ports[0],
+            iopub_port: ports[1],
+            stdin_port: ports[2],
+            control_port: ports[3],
+            hb_port: ports[4],
+            key: String::from_utf8(KEY.to_vec()).unwrap(),
+        };
+        let kernel = {
+            let connection = connection.clone();
+            thread::spawn(move || serve(&connection, Extensions::empty()))
+        };
+
+        let context = zmq::Context::new();
+        let connect = |socket_type, port| {
+            let socket = context.socket(socket_type).unwrap();
+            socket.connect(&connection.endpoint(port)).unwrap();
+            socket
+        };
+        let heartbeat = connect(zmq::REQ, connection.hb_port);
+        heartbeat.send(&b"ping"[..], 0).unwrap();
+        assert_eq!(heartbeat.recv_bytes(0).unwrap(), b"ping");
+
+        let shell = connect(zmq::DEALER, connection.shell_port);
+        shell
+            .send_multipart(request("kernel_info_request", "{}"), 0)
+            .unwrap();
+        let reply = open(KEY, shell.recv_multipart(0).unwrap()).unwrap();
+        assert_eq!(reply.header["msg_type"], "kernel_info_reply");
+        assert_eq!(reply.content["implementation"], "bf5d");
+
+        let control = connect(zmq::DEALER, connection.control_port);
+        control
+            .send_multipart(request("shutdown_request", r#"{"restart":false}"#), 0)
+            .unwrap();
+        let reply = open(KEY, control.recv_multipart(0).unwrap()).unwrap();
+        assert_eq!(reply.header["msg_type"], "shutdown_reply");
+        assert_eq!(reply.content["restart"], false);
+        kernel.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn open_brackets_ask_for_more() {
+        let session = Session::new(Extensions::all());
+        let status = |code| is_complete(code, &session)["status"].clone();
+        assert_eq!(status("+[>+"), "incomplete");
+        assert_eq!(status("%steps 5\n(+"), "incomplete");
+        assert_eq!(status("+[-]"), "complete");
+        assert_eq!(status("+]"), "invalid");
+    }
+}
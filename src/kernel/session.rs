@@ -0,0 +1,224 @@
+// A notebook's interpreter: the timelines outlive the cells, each cell is a new program for them.
+use std::{
+    mem,
+    panic::{self, AssertUnwindSafe},
+};
+
+use crate::{
+    interpreter::{
+        bf5d::Engine,
+        sandbox::{Limits, SandboxedRun},
+        types::BF5DContext,
+    },
+    parser::{bf5d::parse_with_spans, types::Extensions},
+};
+
+const DEFAULT_STEPS: usize = 1_000_000;
+
+/// What running a cell showed.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Outcome {
+    /// What the program wrote.
+    pub output: String,
+    /// What the magics rendered, one entry each.
+    pub displays: Vec<String>,
+    /// The kind and message of what stopped the cell.
+    pub error: Option<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub engine: Engine,
+    pub extensions: Extensions,
+    pub limits: Limits,
+}
+
+impl Session {
+    pub fn new(extensions: Extensions) -> Self {
+        Session {
+            engine: Engine::new(BF5DContext::new()),
+            extensions,
+            limits: Limits {
+                steps: Some(DEFAULT_STEPS),
+                ..Limits::default()
+            },
+        }
+    }
+
+    /// Runs the magics at the top of `cell`, then the rest of it as a program.
+    ///
+    /// Every timeline left over from the cells before starts the program from its first
+    /// instruction, with its cells and pointers as they were. The magics, one per line:
+    /// - `%state`: the timelines and their tapes
+    /// - `%reset`: a fresh interpreter, one timeline with nothing on its tape
+    /// - `%input TEXT`: `TEXT` and a newline for `,` to read, in this cell or later ones
+    /// - `%extensions LIST`: the extensions of the following cells, as on the command line
+    /// - `%steps N`: how many rounds a cell gets before it's stopped
+    ///
+    /// `%` is also `sleep`: a line of it followed by anything else than these is program.
+    pub fn execute(&mut self, cell: &str) -> Outcome {
+        let mut outcome = Outcome::default();
+        let mut lines = cell.lines().peekable();
+        while let Some(line) = lines.peek() {
+            let line = line.trim();
+            if line.is_empty() {
+                lines.next();
+                continue;
+            }
+            match self.magic(line) {
+                Some(Ok(Some(display))) => outcome.displays.push(display),
+                Some(Ok(None)) => (),
+                Some(Err(e)) => {
+                    outcome.error = Some(("UsageError".to_string(), e));
+                    return outcome;
+                }
+                None => break,
+            }
+            lines.next();
+        }
+
+        let program = lines.collect::<Vec<_>>().join("\n");
+        if !program.trim().is_empty() {
+            match self.run(&program) {
+                Ok(output) => outcome.output = output,
+                Err((output, error)) => {
+                    outcome.output = output;
+                    outcome.error = Some(error);
+                }
+            }
+        }
+        outcome
+    }
+
+    // `None` if `line` isn't a magic, then what it displays or why it couldn't be done
+    fn magic(&mut self, line: &str) -> Option<Result<Option<String>, String>> {
+        let line = line.strip_prefix('%')?;
+        let (name, argument) = match line.find(' ') {
+            Some(space) => (&line[..space], line[space + 1..].trim()),
+            None => (line, ""),
+        };
+        Some(match name {
+            "state" => Ok(Some(self.state())),
+            "reset" => {
+                self.engine = Engine::new(BF5DContext::new());
+                Ok(None)
+            }
+            "input" => {
+                self.engine.context.program_input.push_str(argument);
+                self.engine.context.program_input.push('\n');
+                Ok(None)
+            }
+            "extensions" => argument.parse::<Extensions>().map(|extensions| {
+                self.extensions = extensions;
+                None
+            }),
+            "steps" => argument
+                .parse::<usize>()
+                .map(|steps| {
+                    self.limits.steps = Some(steps);
+                    None
+                })
+                .map_err(|_| format!("expected a number of steps, got {:?}", argument)),
+            _ => return None,
+        })
+    }
+
+    /// What `%state` shows: the step count and every timeline's tape around its pointers.
+    pub fn state(&self) -> String {
+        let engine = &self.engine;
+        let mut state = format!(
+            "step {}, {} timeline{}",
+            engine.context.steps,
+            engine.timelines.len(),
+            if engine.timelines.len() == 1 { "" } else { "s" }
+        );
+        for timeline in engine.timelines.iter() {
+            state.push_str(&format!("\n\n{}", timeline));
+        }
+        state
+    }
+
+    // what the program wrote, along with the error that stopped it if one did
+    fn run(&mut self, program: &str) -> Result<String, (String, (String, String))> {
+        let (tokens, spans) = parse_with_spans(program, self.extensions)
+            .map_err(|e| ("".to_string(), ("ParseError".to_string(), e.to_string())))?;
+
+        let context = &mut self.engine.context;
+        // a rewind in a later cell still needs the history of the earlier ones
+        let need_history = context.need_history;
+        context.set_tokens(tokens);
+        context.need_history |= need_history;
+        context.spans = spans;
+        context.raw_program = program.to_string();
+        context.extensions = self.extensions;
+        context.deadlock = None;
+        let written = context.program_output.len();
+        for timeline in self.engine.timelines.iter_mut() {
+            timeline.instruction_pointer = 0;
+            // the return addresses and naps were in the last cell's program
+            timeline.call_stack.clear();
+            timeline.sleeping = 0;
+        }
+
+        let engine = mem::replace(&mut self.engine, Engine::new(BF5DContext::new()));
+        let mut sandbox = SandboxedRun::new(engine, self.limits.clone());
+        let result = panic::catch_unwind(AssertUnwindSafe(|| sandbox.run()));
+        self.engine = sandbox.engine;
+
+        let output = self.engine.context.program_output[written..].to_string();
+        match result {
+            Ok(Ok(())) => Ok(output),
+            Ok(Err(e)) => Err((output, ("RuntimeError".to_string(), e.to_string()))),
+            Err(_) => Err((
+                output,
+                (
+                    "Crash".to_string(),
+                    "the interpreter crashed, %reset starts over".to_string(),
+                ),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timelines_carry_over_between_cells() {
+        let mut session = Session::new(Extensions::empty());
+        assert_eq!(session.execute("+++>++").output, "");
+        let outcome = session.execute("<.>.");
+        assert_eq!(outcome.output, "\u{3}\u{2}");
+        assert_eq!(outcome.error, None);
+        assert!(session.execute("%state").displays[0].starts_with("step "));
+
+        session.execute("%reset");
+        assert_eq!(session.execute(".").output, "\0");
+    }
+
+    #[test]
+    fn magics_come_before_the_program() {
+        let mut session = Session::new(Extensions::empty());
+        let outcome = session.execute("%input a\n\n%steps 100\n,+.");
+        assert_eq!(outcome.output, "b");
+        assert_eq!(session.limits.steps, Some(100));
+        // the newline `%input` added is still to be read
+        assert_eq!(session.execute(",.").output, "\n");
+
+        let outcome = session.execute("%steps many\n+.");
+        assert_eq!(outcome.error.unwrap().0, "UsageError");
+        assert_eq!(outcome.output, "");
+        assert_eq!(session.limits.steps, Some(100));
+    }
+
+    #[test]
+    fn errors_keep_what_was_written() {
+        let mut session = Session::new(Extensions::empty());
+        assert_eq!(session.execute("+[").error.unwrap().0, "ParseError");
+
+        let outcome = session.execute("%steps 10\n+.[]");
+        assert_eq!(outcome.output, "\u{1}");
+        assert_eq!(outcome.error.unwrap().0, "RuntimeError");
+    }
+}
@@ -0,0 +1,50 @@
+// HMAC-SHA256, how Jupyter signs its messages.
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+/// The signature of `parts` read as one message, in lowercase hex.
+pub fn sign(key: &[u8], parts: &[&[u8]]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Whether `signature` is that of `parts`, taking as long whichever byte it differs in.
+pub fn verify(key: &[u8], parts: &[&[u8]], signature: &[u8]) -> bool {
+    sign(key, parts).as_bytes().ct_eq(signature).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 4231, test case 2
+    const SIGNATURE: &str = "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+
+    #[test]
+    fn signs_messages() {
+        assert_eq!(
+            sign(b"Jefe", &[b"what do ya ", b"want for nothing?"]),
+            SIGNATURE
+        );
+    }
+
+    #[test]
+    fn verifies_only_the_signature() {
+        let parts: &[&[u8]] = &[b"what do ya want for nothing?"];
+        assert!(verify(b"Jefe", parts, SIGNATURE.as_bytes()));
+        assert!(!verify(b"Jeff", parts, SIGNATURE.as_bytes()));
+        assert!(!verify(b"Jefe", parts, &SIGNATURE.as_bytes()[..63]));
+        assert!(!verify(b"Jefe", parts, b""));
+        let mut wrong = SIGNATURE.as_bytes().to_vec();
+        wrong[63] = b'2';
+        assert!(!verify(b"Jefe", parts, &wrong));
+    }
+}
@@ -0,0 +1,45 @@
+// lets `bf5d!` expansions name `::bf5d` from inside this crate too
+extern crate self as bf5d;
+
+pub use bf5d_macros::bf5d;
+
+#[cfg(unix)]
+pub mod daemon;
+pub mod interpreter;
+#[cfg(feature = "kernel")]
+pub mod kernel;
+pub mod lsp;
+pub mod optimizer;
+pub mod parser;
+pub mod protocol;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod testing;
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bf5d,
+        parser::{bf5d::parse, types::Token},
+    };
+
+    // forwarded literals arrive in an invisible group
+    macro_rules! forwarded {
+        ($source:literal) => {
+            bf5d!($source)
+        };
+    }
+
+    const ADD: &[Token] = bf5d!("[->+<]");
+    const RAW: &[Token] = bf5d!(r#"+ "a comment" [-(.)]"#);
+    const ESCAPED: &[Token] = bf5d!("\x2b\u{2e}\t,");
+
+    #[test]
+    fn expands_to_what_the_parser_gives() {
+        assert_eq!(ADD, parse("[->+<]").unwrap().as_slice());
+        assert_eq!(RAW, parse("+[-(.)]").unwrap().as_slice());
+        assert_eq!(ESCAPED, parse("+.,").unwrap().as_slice());
+        assert_eq!(forwarded!("(>)"), parse("(>)").unwrap().as_slice());
+        assert!(bf5d!("").is_empty());
+    }
+}
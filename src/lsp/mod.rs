@@ -0,0 +1,479 @@
+// A language server for `.bf5d` files, spoken over stdin and stdout by `bf5d-cli lsp`.
+//
+// Documents are synced whole and reparsed on every change, programs are small.
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    ops::Range,
+};
+
+use serde_json::{json, Value};
+
+use crate::parser::{
+    bf5d::parse_with_spans,
+    termination::{self, LoopVerdict},
+    types::{BF5DParseError, Extensions, JumpType, Token},
+};
+
+// LSP's `DiagnosticSeverity`
+const ERROR: usize = 1;
+const WARNING: usize = 2;
+const INFORMATION: usize = 3;
+
+// JSON-RPC's code for a request nobody handles
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// An open file, parsed.
+#[derive(Debug)]
+pub struct Document {
+    pub text: String,
+    pub parsed: Result<(Vec<Token>, Vec<Range<usize>>), BF5DParseError>,
+}
+
+impl Document {
+    pub fn new(text: String, extensions: Extensions) -> Self {
+        let parsed = parse_with_spans(&text, extensions);
+        Document { text, parsed }
+    }
+
+    /// The parse error, or the loops the termination analysis found can spin forever.
+    pub fn diagnostics(&self) -> Vec<Value> {
+        let (tokens, spans) = match &self.parsed {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                let span = e.span().unwrap_or(0..0);
+                return vec![self.diagnostic(span, ERROR, e.message())];
+            }
+        };
+
+        termination::analyze(tokens)
+            .loops
+            .iter()
+            .filter_map(|report| {
+                let (severity, message) = match report.verdict {
+                    LoopVerdict::Infinite => (WARNING, "this loop never stops"),
+                    LoopVerdict::InfiniteIfEntered => (
+                        INFORMATION,
+                        "this loop spins forever if entered on a nonzero cell",
+                    ),
+                    _ => return None,
+                };
+                let span = spans[report.start].start..spans[report.end].end;
+                Some(self.diagnostic(span, severity, message))
+            })
+            .collect()
+    }
+
+    fn diagnostic(&self, span: Range<usize>, severity: usize, message: &str) -> Value {
+        json!({
+            "range": self.range(span),
+            "severity": severity,
+            "source": "bf5d",
+            "message": message,
+        })
+    }
+
+    /// The instruction under `offset`, or right before it, the cursor usually sits after a
+    /// bracket.
+    pub fn instruction_at(&self, offset: usize) -> Option<usize> {
+        let (_, spans) = self.parsed.as_ref().ok()?;
+        spans
+            .iter()
+            .position(|span| span.contains(&offset))
+            .or_else(|| spans.iter().position(|span| span.end == offset))
+    }
+
+    /// The other half of the bracket or parenthesis at `instruction`.
+    pub fn partner(&self, instruction: usize) -> Option<usize> {
+        let (tokens, _) = self.parsed.as_ref().ok()?;
+        match tokens[instruction] {
+            Token::Jump { index, .. }
+            | Token::Spawn { index }
+            | Token::Fork { index }
+            | Token::SpawnIf { index } => Some(index),
+            Token::Kill => tokens.iter().position(|token| match token {
+                Token::Spawn { index } | Token::Fork { index } | Token::SpawnIf { index } => {
+                    *index == instruction
+                }
+                _ => false,
+            }),
+            _ => None,
+        }
+    }
+
+    /// How many loops `instruction` is inside of, its own brackets not counted.
+    pub fn loop_depth(&self, instruction: usize) -> usize {
+        let tokens = match &self.parsed {
+            Ok((tokens, _)) => tokens,
+            Err(_) => return 0,
+        };
+        tokens[..instruction]
+            .iter()
+            .filter(|token| match token {
+                Token::Jump {
+                    type_: JumpType::IfZero,
+                    index,
+                } => *index > instruction,
+                _ => false,
+            })
+            .count()
+    }
+
+    pub fn hover(&self, offset: usize) -> Option<Value> {
+        let instruction = self.instruction_at(offset)?;
+        let (tokens, spans) = self.parsed.as_ref().ok()?;
+        let span = spans[instruction].clone();
+
+        let mut lines = vec![format!(
+            "`{}`, instruction {}",
+            &self.text[span.clone()],
+            instruction
+        )];
+        let target = match tokens[instruction] {
+            Token::Jump {
+                type_: JumpType::IfZero,
+                index,
+            } => Some(("jumps past", index, "when the cell is zero")),
+            Token::Jump {
+                type_: JumpType::IfNotZero,
+                index,
+            } => Some(("jumps back to", index, "unless the cell is zero")),
+            Token::Spawn { index } | Token::Fork { index } | Token::SpawnIf { index } => {
+                Some(("the body ends at", index, ""))
+            }
+            Token::Goto { index } | Token::Call { index } => Some(("jumps to", index, "")),
+            _ => None,
+        };
+        if let Some((verb, index, condition)) = target {
+            lines.push(
+                format!(
+                    "{} instruction {} ({}) {}",
+                    verb,
+                    index,
+                    self.describe(index),
+                    condition
+                )
+                .trim_end()
+                .to_string(),
+            );
+        }
+        lines.push(format!("loop depth {}", self.loop_depth(instruction)));
+
+        Some(json!({
+            "contents": { "kind": "markdown", "value": lines.join("\n\n") },
+            "range": self.range(span),
+        }))
+    }
+
+    // `line:column` of an instruction, counting from one the way editors show it
+    fn describe(&self, instruction: usize) -> String {
+        let (line, character) = self.line_and_character(self.start_of(instruction));
+        format!("{}:{}", line + 1, character + 1)
+    }
+
+    // where an instruction starts, the end of the text for one past the last
+    fn start_of(&self, instruction: usize) -> usize {
+        match &self.parsed {
+            Ok((_, spans)) => spans
+                .get(instruction)
+                .map_or(self.text.len(), |span| span.start),
+            Err(_) => 0,
+        }
+    }
+
+    /// Both halves of the bracket or parenthesis at `offset`.
+    pub fn highlights(&self, offset: usize) -> Vec<Value> {
+        let (instruction, partner) = match self.instruction_at(offset).and_then(|instruction| {
+            self.partner(instruction)
+                .map(|partner| (instruction, partner))
+        }) {
+            Some(pair) => pair,
+            None => return vec![],
+        };
+        let spans = match &self.parsed {
+            Ok((_, spans)) => spans,
+            Err(_) => return vec![],
+        };
+        [instruction, partner]
+            .iter()
+            .map(|&i| json!({ "range": self.range(spans[i].clone()) }))
+            .collect()
+    }
+
+    /// The matching bracket or parenthesis, or where a goto or call lands.
+    pub fn definition(&self, offset: usize) -> Option<Range<usize>> {
+        let instruction = self.instruction_at(offset)?;
+        let (tokens, spans) = self.parsed.as_ref().ok()?;
+        match tokens[instruction] {
+            Token::Goto { index } | Token::Call { index } => {
+                let start = self.start_of(index);
+                Some(start..start)
+            }
+            _ => self.partner(instruction).map(|i| spans[i].clone()),
+        }
+    }
+
+    // zero based, characters in UTF-16 code units the way LSP counts them
+    fn line_and_character(&self, offset: usize) -> (usize, usize) {
+        let before = &self.text[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (
+            before.matches('\n').count(),
+            before[line_start..].encode_utf16().count(),
+        )
+    }
+
+    /// LSP's `Position` of a byte offset.
+    pub fn position(&self, offset: usize) -> Value {
+        let (line, character) = self.line_and_character(offset);
+        json!({ "line": line, "character": character })
+    }
+
+    fn range(&self, span: Range<usize>) -> Value {
+        json!({
+            "start": self.position(span.start),
+            "end": self.position(span.end),
+        })
+    }
+
+    /// The byte offset of an LSP `Position`, clamped to the text.
+    pub fn offset(&self, position: &Value) -> usize {
+        let line = position["line"].as_u64().unwrap_or(0) as usize;
+        let character = position["character"].as_u64().unwrap_or(0) as usize;
+
+        let line_start = match line {
+            0 => 0,
+            _ => match self.text.match_indices('\n').nth(line - 1) {
+                Some((i, _)) => i + 1,
+                None => return self.text.len(),
+            },
+        };
+        let mut units = 0;
+        for (i, c) in self.text[line_start..].char_indices() {
+            if units >= character || c == '\n' {
+                return line_start + i;
+            }
+            units += c.len_utf16();
+        }
+        self.text.len()
+    }
+}
+
+/// Keeps the open documents and answers what the editor asks about them.
+#[derive(Debug)]
+pub struct Server {
+    pub extensions: Extensions,
+    pub documents: HashMap<String, Document>,
+    // `shutdown` came, `exit` is all that's left
+    pub shutting_down: bool,
+}
+
+impl Server {
+    pub fn new(extensions: Extensions) -> Self {
+        Server {
+            extensions,
+            documents: HashMap::new(),
+            shutting_down: false,
+        }
+    }
+
+    /// The messages to send back for one from the editor, replies and notifications.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or("")
+            .to_string();
+
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    // full documents on every change
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "documentHighlightProvider": true,
+                    "definitionProvider": true,
+                },
+                "serverInfo": { "name": "bf5d" },
+            }),
+            "shutdown" => {
+                self.shutting_down = true;
+                Value::Null
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str();
+                return self.update(uri, text.unwrap_or("").to_string());
+            }
+            "textDocument/didChange" => {
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                return match text {
+                    Some(text) => self.update(uri, text.to_string()),
+                    None => vec![],
+                };
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish(&uri, vec![])];
+            }
+            "textDocument/hover" | "textDocument/documentHighlight" | "textDocument/definition" => {
+                match self.documents.get(&uri) {
+                    Some(document) => {
+                        let offset = document.offset(&params["position"]);
+                        match method {
+                            "textDocument/hover" => document.hover(offset).unwrap_or(Value::Null),
+                            "textDocument/documentHighlight" => document.highlights(offset).into(),
+                            _ => document.definition(offset).map_or(
+                                Value::Null,
+                                |span| json!({ "uri": uri, "range": document.range(span) }),
+                            ),
+                        }
+                    }
+                    None => Value::Null,
+                }
+            }
+            _ => {
+                // notifications we don't care about get no answer, requests an error
+                if message["id"].is_null() {
+                    return vec![];
+                }
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "error": {
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("unknown method {:?}", method),
+                    },
+                })];
+            }
+        };
+
+        vec![json!({ "jsonrpc": "2.0", "id": message["id"], "result": result })]
+    }
+
+    fn update(&mut self, uri: String, text: String) -> Vec<Value> {
+        let document = Document::new(text, self.extensions);
+        let diagnostics = document.diagnostics();
+        self.documents.insert(uri.clone(), document);
+        vec![publish(&uri, diagnostics)]
+    }
+}
+
+fn publish(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/// Answers messages from `input` on `output` until the editor says `exit`, the exit code is
+/// 0 if it asked for a `shutdown` first and 1 otherwise, as the protocol wants.
+pub fn serve(
+    mut input: impl BufRead,
+    mut output: impl Write,
+    extensions: Extensions,
+) -> io::Result<i32> {
+    let mut server = Server::new(extensions);
+    while let Some(body) = read_message(&mut input)? {
+        let message = match serde_json::from_str::<Value>(&body) {
+            Ok(message) => message,
+            // nothing to answer to without an id, skipping it keeps the session alive
+            Err(_) => continue,
+        };
+        if message["method"] == "exit" {
+            return Ok(if server.shutting_down { 0 } else { 1 });
+        }
+        for reply in server.handle(&message) {
+            let reply = reply.to_string();
+            write!(output, "Content-Length: {}\r\n\r\n{}", reply.len(), reply)?;
+        }
+        output.flush()?;
+    }
+    // the editor went away without saying goodbye
+    Ok(1)
+}
+
+// the body of the next `Content-Length` framed message, `None` at the end of the input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(text: &str) -> Document {
+        Document::new(text.to_string(), Extensions::all())
+    }
+
+    fn position(line: usize, character: usize) -> Value {
+        json!({ "line": line, "character": character })
+    }
+
+    #[test]
+    fn offsets_count_utf16_units_per_line() {
+        // `é` is two bytes and one unit, `😀` four bytes and two units
+        let document = document("é+\n😀[-]\n");
+        assert_eq!(document.offset(&position(0, 1)), 2);
+        assert_eq!(document.offset(&position(1, 0)), 4);
+        assert_eq!(document.offset(&position(1, 2)), 8);
+        // halfway into a surrogate pair, past the end of a line and past the last line
+        assert_eq!(document.offset(&position(1, 1)), 8);
+        assert_eq!(document.offset(&position(0, 10)), 3);
+        assert_eq!(document.offset(&position(5, 0)), document.text.len());
+    }
+
+    #[test]
+    fn lines_and_characters_undo_offsets() {
+        let document = document("é+\n😀[-]\n");
+        assert_eq!(document.line_and_character(2), (0, 1));
+        assert_eq!(document.line_and_character(3), (0, 2));
+        assert_eq!(document.line_and_character(8), (1, 2));
+        assert_eq!(document.line_and_character(document.text.len()), (2, 0));
+        for &(line, character) in &[(0, 0), (0, 1), (1, 0), (1, 2), (1, 4)] {
+            let offset = document.offset(&position(line, character));
+            assert_eq!(document.line_and_character(offset), (line, character));
+        }
+    }
+
+    #[test]
+    fn definitions_are_byte_spans() {
+        let document = document("é[\n-]");
+        assert_eq!(document.definition(2), Some(5..6));
+        assert_eq!(document.definition(5), Some(2..3));
+        assert_eq!(document.definition(4), None);
+
+        // a label isn't an instruction, a goto lands where the next one starts
+        let document = self::document("é'a'\n+\"a\"");
+        assert_eq!(document.definition(8), Some(6..6));
+        assert_eq!(document.definition(6), None);
+    }
+}
@@ -5,9 +5,9 @@ extern crate console_error_panic_hook;
 use yew::prelude::*;
 
 mod components;
-mod interpreter;
 mod model;
-mod parser;
+
+use bf5d::{interpreter, parser};
 
 use components::{
     bf5d_editor::BF5DEditor, program_input_editor::ProgramInputEditor,
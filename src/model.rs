@@ -7,7 +7,10 @@ use std::{cell::RefCell, rc::Rc, vec};
 use yew::prelude::*;
 
 use crate::{
-    interpreter::types::{BF5DContext, Timeline},
+    interpreter::{
+        bf5d::step,
+        types::{BF5DContext, Timeline},
+    },
     parser::bf5d,
 };
 
@@ -30,12 +33,8 @@ impl Default for Model {
             context: Rc::new(RefCell::new(BF5DContext {
                 raw_program: LocalStorage::get(RAW_PROGRAM_KEY)
                     .unwrap_or("(>^)@-[>,.<]".to_string()),
-                tokens: vec![],
                 program_input: "hello".to_string(),
-                program_output: "".to_string(),
-                total_timelines: 0,
-                metadata: vec![],
-                need_history: true,
+                ..BF5DContext::new()
             })),
             error: None,
             timelines: Rc::new(RefCell::new(vec![Timeline::new()])),
@@ -72,15 +71,16 @@ impl Reducible for Model {
                 Self { ..(*self).clone() }.into()
             }
             ParseUserInput => {
-                let parsed = bf5d::parse(self.context.clone().borrow_mut().raw_program.as_str());
+                let parsed = {
+                    let context = self.context.borrow();
+                    bf5d::parse_with(context.raw_program.as_str(), context.extensions)
+                };
 
                 match parsed {
                     Ok(tokens) => {
                         let context = self.context.clone();
                         let mut context = context.borrow_mut();
-                        context.need_history =
-                            tokens.contains(&crate::parser::types::Token::Rewind);
-                        context.tokens = tokens;
+                        context.set_tokens(tokens);
                         Self { ..(*self).clone() }.into()
                     }
                     Err(e) => Self {
@@ -94,21 +94,19 @@ impl Reducible for Model {
                 let context = self.context.clone();
                 let mut context = context.borrow_mut();
                 let timelines = self.timelines.clone();
+                let mut timelines = timelines.borrow_mut();
 
-                context.collect_timeline_metadata(&(*timelines.clone()).borrow());
-
-                let commands = timelines
-                    .borrow_mut()
-                    .iter_mut()
-                    .map(|t| t.update(&mut context))
-                    .map(|(_, cmd)| cmd)
-                    .collect::<Vec<_>>();
-
-                for cmd in commands {
-                    context.execute_command(cmd, &mut (timelines.clone().borrow_mut()));
+                match step(&mut context, &mut timelines) {
+                    Ok(()) => Self { ..(*self).clone() }.into(),
+                    Err(e) => {
+                        *self.interval.borrow_mut() = None;
+                        Self {
+                            error: Some(e.to_string()),
+                            ..(*self).clone()
+                        }
+                        .into()
+                    }
                 }
-
-                Self { ..(*self).clone() }.into()
             }
             ResetProgram => {
                 let context = self.context.clone();
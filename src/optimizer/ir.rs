@@ -0,0 +1,294 @@
+// What the passes rewrite: the instructions of a program with only `<>+-[].,`, so run by the root
+// timeline alone with a single pointer, and a runner for them.
+use std::str::Chars;
+
+use crate::parser::{
+    tape,
+    types::{JumpType, MoveDirection, Token, UpdateType},
+};
+
+/// One instruction, `offset` is how far from the pointer the cell it works on is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// `+` and `-`, `amount` is added wrapping around.
+    Add {
+        offset: isize,
+        amount: u8,
+    },
+    Clear {
+        offset: isize,
+    },
+    Move(isize),
+    Write {
+        offset: isize,
+    },
+    Read {
+        offset: isize,
+    },
+    /// `[`, `end` is the `Close` it skips past on a zero cell.
+    Open {
+        end: usize,
+    },
+    /// `]`, `start` is the `Open` it goes back to on a nonzero cell.
+    Close {
+        start: usize,
+    },
+}
+
+/// Where a run keeps its cells.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tape {
+    /// Grows whichever way the pointer goes.
+    Growing,
+    /// The `len` cells from `lowest` on, allocated up front.
+    Bounded { lowest: isize, len: usize },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub ops: Vec<Op>,
+    pub tape: Tape,
+    // the lowest and highest cell `tape_bounds` found the source reaching, if it could tell
+    pub reach: Option<(isize, isize)>,
+}
+
+/// How far a run got.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub output: String,
+    // ops, which after fusing stand for more than one instruction each
+    pub steps: usize,
+    pub halted: bool,
+}
+
+impl Program {
+    /// The ops `tokens` are one for one, or the first instruction that isn't one of `<>+-[].,`.
+    pub fn lower(tokens: &[Token]) -> Result<Program, usize> {
+        let ops = tokens
+            .iter()
+            .enumerate()
+            .map(|(i, token)| match *token {
+                Token::Move(MoveDirection::Left) => Ok(Op::Move(-1)),
+                Token::Move(MoveDirection::Right) => Ok(Op::Move(1)),
+                Token::Update(UpdateType::Increment) => Ok(Op::Add {
+                    offset: 0,
+                    amount: 1,
+                }),
+                Token::Update(UpdateType::Decrement) => Ok(Op::Add {
+                    offset: 0,
+                    amount: u8::MAX,
+                }),
+                Token::Jump {
+                    type_: JumpType::IfZero,
+                    index,
+                } => Ok(Op::Open { end: index }),
+                Token::Jump {
+                    type_: JumpType::IfNotZero,
+                    index,
+                } => Ok(Op::Close { start: index }),
+                Token::Write => Ok(Op::Write { offset: 0 }),
+                Token::Read => Ok(Op::Read { offset: 0 }),
+                _ => Err(i),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let root = tape::tape_bounds(tokens).ok().map(|bounds| bounds.root);
+        let reach = root.and_then(|root| Some((root.lowest?, root.highest?)));
+        Ok(Program {
+            ops,
+            tape: Tape::Growing,
+            reach,
+        })
+    }
+
+    /// Points each `Open` and `Close` at its partner again, after ops were added or taken out.
+    pub fn relink(&mut self) {
+        let mut open = vec![];
+        for i in 0..self.ops.len() {
+            match self.ops[i] {
+                Op::Open { .. } => open.push(i),
+                Op::Close { .. } => {
+                    let start = open.pop().expect("passes keep the loops balanced");
+                    self.ops[start] = Op::Open { end: i };
+                    self.ops[i] = Op::Close { start };
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// What's wrong with the program, the first thing found: a loop whose ends don't point at
+    /// each other, or a bounded tape the cells the source reaches don't fit in.
+    pub fn verify(&self) -> Result<(), String> {
+        let mut open = vec![];
+        for (i, op) in self.ops.iter().enumerate() {
+            match *op {
+                Op::Open { end } => {
+                    if self.ops.get(end) != Some(&Op::Close { start: i }) {
+                        return Err(format!("the loop at {} doesn't end at {}", i, end));
+                    }
+                    open.push(i);
+                }
+                Op::Close { start } => {
+                    let opened = open.pop();
+                    if opened != Some(start) {
+                        return Err(format!(
+                            "the loop ending at {} doesn't start at {}",
+                            i, start
+                        ));
+                    }
+                }
+                _ => (),
+            }
+        }
+        if let Some(&start) = open.last() {
+            return Err(format!("the loop at {} never ends", start));
+        }
+
+        if let Tape::Bounded { lowest, len } = self.tape {
+            let fits = self
+                .reach
+                .is_some_and(|(low, high)| low >= lowest && high < lowest + len as isize);
+            if !fits {
+                return Err(format!(
+                    "the cells reached don't fit the {} from {} on",
+                    len, lowest
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs on `input` to the end, or until `max_steps` ops went by or `go_on` says to stop.
+    pub fn run(&self, input: &str, max_steps: usize, mut go_on: impl FnMut() -> bool) -> Run {
+        let mut machine = Machine::new(self, input);
+        while !machine.is_halted() && machine.steps < max_steps && go_on() {
+            machine.step();
+        }
+        Run {
+            halted: machine.is_halted(),
+            output: machine.output,
+            steps: machine.steps,
+        }
+    }
+}
+
+/// A run of a program, an op at a time.
+///
+/// Reads past the end of the input get 0 and every byte written is a character, like the
+/// engine's.
+pub struct Machine<'a> {
+    program: &'a Program,
+    cells: Cells,
+    input: Chars<'a>,
+    pub output: String,
+    pub pointer: isize,
+    // the op to run next
+    pub ip: usize,
+    pub steps: usize,
+    // characters taken off the front of the input so far
+    pub input_read: usize,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(program: &'a Program, input: &'a str) -> Self {
+        Machine {
+            program,
+            cells: Cells::new(program.tape),
+            input: input.chars(),
+            output: String::new(),
+            pointer: 0,
+            ip: 0,
+            steps: 0,
+            input_read: 0,
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.ip >= self.program.ops.len()
+    }
+
+    /// Runs the next op, if there's one left.
+    pub fn step(&mut self) {
+        let op = match self.program.ops.get(self.ip) {
+            Some(&op) => op,
+            None => return,
+        };
+        let cells = &mut self.cells;
+        let pointer = self.pointer;
+        self.steps += 1;
+        match op {
+            Op::Add { offset, amount } => {
+                let cell = cells.at(pointer + offset);
+                *cell = cell.wrapping_add(amount);
+            }
+            Op::Clear { offset } => *cells.at(pointer + offset) = 0,
+            Op::Move(distance) => self.pointer += distance,
+            Op::Write { offset } => self.output.push(*cells.at(pointer + offset) as char),
+            Op::Read { offset } => {
+                let c = self.input.next();
+                self.input_read += c.is_some() as usize;
+                *cells.at(pointer + offset) = c.map_or(0, |c| c as u8);
+            }
+            Op::Open { end } if *cells.at(pointer) == 0 => self.ip = end,
+            Op::Close { start } if *cells.at(pointer) != 0 => self.ip = start,
+            Op::Open { .. } | Op::Close { .. } => (),
+        }
+        self.ip += 1;
+    }
+
+    /// The cells that aren't 0, lowest first.
+    pub fn nonzero_cells(&self) -> Vec<(isize, u8)> {
+        self.cells.nonzero()
+    }
+}
+
+// `origin` is where cell 0 is in `cells`
+struct Cells {
+    cells: Vec<u8>,
+    origin: isize,
+    growing: bool,
+}
+
+impl Cells {
+    fn new(tape: Tape) -> Self {
+        match tape {
+            Tape::Growing => Cells {
+                cells: vec![0],
+                origin: 0,
+                growing: true,
+            },
+            Tape::Bounded { lowest, len } => Cells {
+                cells: vec![0; len],
+                origin: -lowest,
+                growing: false,
+            },
+        }
+    }
+
+    fn at(&mut self, cell: isize) -> &mut u8 {
+        let mut index = cell + self.origin;
+        if self.growing && index < 0 {
+            // at least double, so walking left stays linear
+            let more = (-index as usize).max(self.cells.len());
+            self.cells.splice(0..0, vec![0; more]);
+            self.origin += more as isize;
+            index += more as isize;
+        } else if self.growing && index as usize >= self.cells.len() {
+            let len = (index as usize + 1).max(self.cells.len() * 2);
+            self.cells.resize(len, 0);
+        }
+        match self.cells.get_mut(index as usize) {
+            Some(cell) => cell,
+            None => panic!("cell {} is past the tape bounded-tape made", cell),
+        }
+    }
+
+    fn nonzero(&self) -> Vec<(isize, u8)> {
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, &value)| value != 0)
+            .map(|(index, &value)| (index as isize - self.origin, value))
+            .collect()
+    }
+}
@@ -0,0 +1,152 @@
+// Makes programs run faster, as named passes a `Pipeline` runs one after the other.
+//
+// Only programs of `<>+-[].,` are taken: they never have more than the root timeline with one
+// pointer, so the passes don't have to keep the scheduling, history and pointers of the others in
+// step. When an optimized program does something its source doesn't, listing fewer passes finds
+// the one to blame.
+pub mod ir;
+pub mod obfuscate;
+pub mod partial;
+pub mod passes;
+pub mod symbolic;
+
+use std::{fmt, str::FromStr};
+
+use self::ir::Program;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pass {
+    Fuse,
+    ClearLoop,
+    DeadLoop,
+    Offsets,
+    BoundedTape,
+    /// Changes nothing, fails if an earlier pass left the program broken.
+    Verify,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 6] = [
+        Pass::Fuse,
+        Pass::ClearLoop,
+        Pass::DeadLoop,
+        Pass::Offsets,
+        Pass::BoundedTape,
+        Pass::Verify,
+    ];
+
+    /// What `--passes` calls it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pass::Fuse => "fuse",
+            Pass::ClearLoop => "clear-loop",
+            Pass::DeadLoop => "dead-loop",
+            Pass::Offsets => "offsets",
+            Pass::BoundedTape => "bounded-tape",
+            Pass::Verify => "verify",
+        }
+    }
+
+    pub fn apply(&self, program: &mut Program) -> Result<(), String> {
+        match self {
+            Pass::Fuse => passes::fuse(program),
+            Pass::ClearLoop => passes::clear_loops(program),
+            Pass::DeadLoop => passes::dead_loops(program),
+            Pass::Offsets => passes::offsets(program),
+            Pass::BoundedTape => passes::bounded_tape(program),
+            Pass::Verify => return program.verify(),
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Pass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Pass::ALL
+            .iter()
+            .find(|pass| pass.name() == s)
+            .copied()
+            .ok_or_else(|| {
+                let names = Pass::ALL.iter().map(Pass::name).collect::<Vec<_>>();
+                format!("unknown pass {:?}, the passes are {}", s, names.join(", "))
+            })
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A pass that left the program broken, by what `verify` found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassError {
+    pub pass: Pass,
+    pub message: String,
+}
+
+impl fmt::Display for PassError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} after the {} pass", self.message, self.pass)
+    }
+}
+
+/// The passes to run, in order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pipeline {
+    pub passes: Vec<Pass>,
+}
+
+impl Pipeline {
+    /// What `--opt-level` `level` runs: nothing at 0, the rewrites of a few ops at a time at 1,
+    /// and from 2 up those again after `offsets`, which gives them more to work with.
+    pub fn level(level: usize) -> Pipeline {
+        use Pass::*;
+
+        let passes = match level {
+            0 => vec![],
+            1 => vec![Fuse, ClearLoop, DeadLoop],
+            _ => vec![
+                Fuse,
+                ClearLoop,
+                DeadLoop,
+                Offsets,
+                Fuse,
+                DeadLoop,
+                BoundedTape,
+            ],
+        };
+        Pipeline { passes }
+    }
+
+    /// Runs the passes over `program`; debug builds verify it after each one, for the error to
+    /// name the pass that broke it rather than a later one tripping over it.
+    pub fn run(&self, program: &mut Program) -> Result<(), PassError> {
+        for &pass in self.passes.iter() {
+            let mut result = pass.apply(program);
+            if cfg!(debug_assertions) && pass != Pass::Verify {
+                result = result.and_then(|()| program.verify());
+            }
+            result.map_err(|message| PassError { pass, message })?;
+        }
+        Ok(())
+    }
+}
+
+/// Pass names separated by commas, the order they run in.
+impl FromStr for Pipeline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let passes = s
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Pipeline { passes })
+    }
+}
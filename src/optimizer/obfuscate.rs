@@ -0,0 +1,290 @@
+// Rewrites a program into one that does the same in another way, for golf and obfuscation
+// contests: cancelling pairs like `<>` and `+-` between the instructions, and loops `[x]` turned
+// into one of the forms that run like them, `[[x]]`, `[x][x]` and `[x[x]]`.
+//
+// Only programs of one timeline are taken, with more the extra instructions would show in how
+// theirs interleave. Programs that rewind get no `+-`, `~` would undo it instead of what it's
+// meant to, and the pairs need cells that wrap around, the default overflow policy. Comments and
+// labels stay where they were, a loop with a label in it is never written out twice.
+use std::ops::Range;
+
+use crate::{
+    interpreter::{
+        bf5d::Engine,
+        rng::Rng,
+        sandbox::{Limit, Limits, SandboxedRun},
+    },
+    parser::types::{BF5DParseError, Extensions, JumpType, Token},
+    testing::equivalence::{Behavior, Divergence},
+};
+
+/// How many rounds an obfuscated program can take for each of the original's. It's 4 at most,
+/// an instruction with a pair after it and a bracket, the rest is room for priorities.
+pub const SLOWDOWN: usize = 8;
+
+/// Rewrites what `engine` was going to run, an engine made with `Engine::from_source`, the same
+/// `seed` giving the same program; or gives back the first instruction that spawns.
+pub fn obfuscate(engine: &Engine, seed: u64) -> Result<String, usize> {
+    let context = &engine.context;
+    let tokens = &context.tokens;
+    if let Some(spawn) = tokens.iter().position(|token| {
+        matches!(
+            token,
+            Token::Spawn { .. } | Token::Fork { .. } | Token::SpawnIf { .. }
+        )
+    }) {
+        return Err(spawn);
+    }
+    let rewinds = tokens
+        .iter()
+        .any(|token| matches!(token, Token::Rewind | Token::RewindToMarker));
+
+    let mut writer = Writer {
+        source: &context.raw_program,
+        tokens,
+        spans: &context.spans,
+        pairs: if rewinds {
+            &["<>", "><"]
+        } else {
+            &["<>", "><", "+-", "-+"]
+        },
+        rng: Rng::new(seed),
+        output: String::new(),
+    };
+    writer.pad();
+    writer.block(0..tokens.len());
+    let end = writer.gap(tokens.len());
+    writer.output.push_str(end);
+    Ok(writer.output)
+}
+
+struct Writer<'a> {
+    source: &'a str,
+    tokens: &'a [Token],
+    spans: &'a [Range<usize>],
+    pairs: &'static [&'static str],
+    rng: Rng,
+    output: String,
+}
+
+impl<'a> Writer<'a> {
+    // what's between instruction `i` and the one before it, comments and labels
+    fn gap(&self, i: usize) -> &'a str {
+        let start = match i {
+            0 => 0,
+            _ => self.spans[i - 1].end,
+        };
+        let end = self
+            .spans
+            .get(i)
+            .map_or(self.source.len(), |span| span.start);
+        &self.source[start..end]
+    }
+
+    fn text(&self, i: usize) -> &'a str {
+        &self.source[self.spans[i].clone()]
+    }
+
+    // a pair that cancels out, a third of the time
+    fn pad(&mut self) {
+        if self.rng.next_u8() < 85 {
+            let pair = self.pairs[self.rng.next_u64() as usize % self.pairs.len()];
+            self.output.push_str(pair);
+        }
+    }
+
+    // instructions `range` with what's before each of them, the loops in it whole
+    fn block(&mut self, range: Range<usize>) {
+        let mut i = range.start;
+        while i < range.end {
+            let gap = self.gap(i);
+            self.output.push_str(gap);
+            match self.tokens[i] {
+                Token::Jump {
+                    type_: JumpType::IfZero,
+                    index: close,
+                } => {
+                    self.each_loop(i, close);
+                    i = close + 1;
+                }
+                _ => {
+                    let text = self.text(i);
+                    self.output.push_str(text);
+                    i += 1;
+                }
+            }
+            self.pad();
+        }
+    }
+
+    // the loop from `open` to `close` in one of its forms, a second run of it starts with every
+    // cell under the pointers at 0, so it's skipped
+    fn each_loop(&mut self, open: usize, close: usize) {
+        let (open_text, close_text) = (self.text(open), self.text(close));
+        let nested = self.tokens[open + 1..close]
+            .iter()
+            .any(|token| matches!(token, Token::Jump { .. }));
+        let labelled = self.source[self.spans[open].end..self.spans[close].start].contains('\'');
+        // only small bodies are written out twice, nested ones would double at every level
+        let twice = !nested && !labelled;
+
+        let body = |writer: &mut Self| {
+            writer.block(open + 1..close);
+            let gap = writer.gap(close);
+            writer.output.push_str(gap);
+        };
+        match self.rng.next_u64() % 4 {
+            1 => {
+                self.output.push_str(open_text);
+                self.pad();
+                self.output.push_str(open_text);
+                body(self);
+                self.output.push_str(close_text);
+                self.pad();
+                self.output.push_str(close_text);
+            }
+            2 if twice => {
+                self.output.push_str(open_text);
+                body(self);
+                self.output.push_str(close_text);
+                self.pad();
+                self.output.push_str(open_text);
+                body(self);
+                self.output.push_str(close_text);
+            }
+            3 if twice => {
+                self.output.push_str(open_text);
+                body(self);
+                self.output.push_str(open_text);
+                body(self);
+                self.output.push_str(close_text);
+                self.output.push_str(close_text);
+            }
+            _ => {
+                self.output.push_str(open_text);
+                body(self);
+                self.output.push_str(close_text);
+            }
+        }
+    }
+}
+
+/// Runs `original` and `obfuscated` on every input, `obfuscated` for `SLOWDOWN` times the rounds,
+/// and gives back the first input they don't agree on.
+///
+/// An original cut off by `max_steps` agrees with an obfuscated program that halts if what it
+/// wrote by then leads into what that one did.
+pub fn verify<'a>(
+    original: &str,
+    obfuscated: &str,
+    extensions: Extensions,
+    inputs: impl IntoIterator<Item = &'a str>,
+    max_steps: usize,
+) -> Result<Option<Divergence>, BF5DParseError> {
+    let limits = |steps| Limits {
+        steps: Some(steps),
+        ..Limits::default()
+    };
+    for input in inputs {
+        let left = Behavior::of(SandboxedRun::new(
+            Engine::from_source_with(original, input, extensions)?,
+            limits(max_steps),
+        ));
+        let right = Behavior::of(SandboxedRun::new(
+            Engine::from_source_with(obfuscated, input, extensions)?,
+            limits(max_steps.saturating_mul(SLOWDOWN)),
+        ));
+        let agrees = match (&left, &right) {
+            (
+                Behavior::LimitExceeded {
+                    limit: Limit::Steps,
+                    output: left,
+                },
+                Behavior::Halted { output: right },
+            ) => right.starts_with(left.as_str()),
+            _ => left.agrees_with(&right),
+        };
+        if !agrees {
+            return Ok(Some(Divergence {
+                input: input.to_string(),
+                left,
+                right,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// The empty input and `count` more of up to 8 printable characters, for `verify`.
+pub fn sample_inputs(count: usize, seed: u64) -> Vec<String> {
+    let mut rng = Rng::new(seed);
+    let mut inputs = vec![String::new()];
+    for _ in 0..count {
+        let len = rng.next_u64() % 9;
+        inputs.push(
+            (0..len)
+                .map(|_| (b' ' + rng.next_u8() % 95) as char)
+                .collect(),
+        );
+    }
+    inputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agrees(original: &str, obfuscated: &str) {
+        let inputs = sample_inputs(20, 1);
+        let divergence = verify(
+            original,
+            obfuscated,
+            Extensions::default(),
+            inputs.iter().map(String::as_str),
+            10_000,
+        )
+        .unwrap();
+        assert_eq!(divergence, None, "{:?} against {:?}", obfuscated, original);
+    }
+
+    fn obfuscated(source: &str, seed: u64) -> String {
+        obfuscate(&Engine::from_source(source, "").unwrap(), seed).unwrap()
+    }
+
+    #[test]
+    fn the_loop_forms_run_like_the_loop() {
+        let x = "->++<";
+        let original = format!(",[{}]>.", x);
+        for form in &["[{x}][{x}]", "[[{x}]]", "[{x}[{x}]]"] {
+            let form = form.replace("{x}", x);
+            agrees(&original, &format!(",{}>.", form));
+        }
+    }
+
+    #[test]
+    fn obfuscated_programs_run_like_the_original() {
+        let source = ",[->++<]>.<,[.[-],]";
+        for seed in 0..20 {
+            agrees(source, &obfuscated(source, seed));
+        }
+    }
+
+    #[test]
+    fn programs_that_rewind_get_no_cell_pairs() {
+        let source = "+>++~.<,[.>+<-]~.";
+        for seed in 0..50 {
+            let obfuscated = obfuscated(source, seed);
+            assert!(
+                !obfuscated.contains("+-") && !obfuscated.contains("-+"),
+                "{:?}",
+                obfuscated
+            );
+            agrees(source, &obfuscated);
+        }
+        // the same program without the rewinds does get them
+        let source = source.replace('~', "");
+        assert!((0..50)
+            .map(|seed| obfuscated(&source, seed))
+            .any(|obfuscated| obfuscated.contains("+-") || obfuscated.contains("-+")));
+    }
+}
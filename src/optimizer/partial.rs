@@ -0,0 +1,202 @@
+// Runs a program ahead of time on the input it's going to get and writes out what's left of it,
+// for builds with the data baked in.
+//
+// A residual can't start inside a loop, so it carries on from the last time the run was outside
+// all of them: it writes what had been written by then, sets the cells up as they were and goes on
+// with the source from there, reading the input that hadn't been read yet. A run that halts leaves
+// only the output to write.
+use super::ir::{Machine, Op, Program};
+use crate::interpreter::bf5d::Engine;
+
+/// What's left of a program once it ran ahead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Residual {
+    pub source: String,
+    // ops run ahead of time, the residual doesn't run these again
+    pub steps: usize,
+    /// The instruction of the source the residual carries on from, `None` if the run halted.
+    pub resumes_at: Option<usize>,
+    /// Characters of the input folded in, the residual reads the rest.
+    pub input_read: usize,
+}
+
+// the run the last time it was outside every loop
+struct Checkpoint {
+    ip: usize,
+    pointer: isize,
+    cells: Vec<(isize, u8)>,
+    // bytes of the output string, not characters
+    output: usize,
+    input_read: usize,
+}
+
+/// Runs what `engine` was going to, on its input, for up to `max_steps` ops, or gives back the
+/// first instruction that isn't one of `<>+-[].,`.
+pub fn specialize(engine: &Engine, max_steps: usize) -> Result<Residual, usize> {
+    let context = &engine.context;
+    let program = Program::lower(&context.tokens)?;
+    let depths = depths(&program);
+    let mut machine = Machine::new(&program, &context.program_input);
+    let mut checkpoint = None;
+    while !machine.is_halted() && machine.steps < max_steps {
+        // every op outside a loop runs once at most, this is once per instruction at most
+        if depths[machine.ip] == 0 {
+            checkpoint = Some(Checkpoint {
+                ip: machine.ip,
+                pointer: machine.pointer,
+                cells: machine.nonzero_cells(),
+                output: machine.output.len(),
+                input_read: machine.input_read,
+            });
+        }
+        machine.step();
+    }
+
+    if machine.is_halted() {
+        return Ok(Residual {
+            source: literal(&bytes(&machine.output)),
+            steps: machine.steps,
+            resumes_at: None,
+            input_read: machine.input_read,
+        });
+    }
+    // the very first op is outside every loop, there's a checkpoint unless nothing ran
+    let checkpoint = checkpoint.unwrap_or(Checkpoint {
+        ip: 0,
+        pointer: 0,
+        cells: vec![],
+        output: 0,
+        input_read: 0,
+    });
+    let output = bytes(&machine.output[..checkpoint.output]);
+    let mut source = literal(&output);
+    set_up(&mut source, &output, &checkpoint.cells, checkpoint.pointer);
+    if !source.is_empty() {
+        source.push('\n');
+    }
+    source.push_str(&context.raw_program[context.spans[checkpoint.ip].start..]);
+    Ok(Residual {
+        source,
+        steps: machine.steps,
+        resumes_at: Some(checkpoint.ip),
+        input_read: checkpoint.input_read,
+    })
+}
+
+fn bytes(output: &str) -> Vec<u8> {
+    output.chars().map(|c| c as u8).collect()
+}
+
+// how many loops each op is in
+fn depths(program: &Program) -> Vec<usize> {
+    let mut depth = 0;
+    program
+        .ops
+        .iter()
+        .map(|op| match op {
+            Op::Open { .. } => {
+                depth += 1;
+                depth - 1
+            }
+            Op::Close { .. } => {
+                depth -= 1;
+                depth + 1
+            }
+            _ => depth,
+        })
+        .collect()
+}
+
+// writes `output` on cell 0
+fn literal(output: &[u8]) -> String {
+    let mut source = String::new();
+    let mut value = 0;
+    for &byte in output {
+        adjust(&mut source, value, byte);
+        source.push('.');
+        value = byte;
+    }
+    source
+}
+
+// after `literal`, leaves `cells` as given and the pointer on `pointer`
+fn set_up(source: &mut String, output: &[u8], cells: &[(isize, u8)], pointer: isize) {
+    let zero = cells
+        .iter()
+        .find(|(cell, _)| *cell == 0)
+        .map_or(0, |&(_, value)| value);
+    adjust(source, output.last().copied().unwrap_or(0), zero);
+
+    let mut at = 0;
+    for &(cell, value) in cells.iter().filter(|(cell, _)| *cell != 0) {
+        walk(source, at, cell);
+        adjust(source, 0, value);
+        at = cell;
+    }
+    walk(source, at, pointer);
+}
+
+// the shorter way round from `from` to `to`
+fn adjust(source: &mut String, from: u8, to: u8) {
+    let up = to.wrapping_sub(from) as usize;
+    if up <= 128 {
+        source.push_str(&"+".repeat(up));
+    } else {
+        source.push_str(&"-".repeat(256 - up));
+    }
+}
+
+fn walk(source: &mut String, from: isize, to: isize) {
+    let step = if to > from { ">" } else { "<" };
+    source.push_str(&step.repeat((to - from).unsigned_abs()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the residual, run on what's left of the input, writes what the program would have
+    fn agrees(source: &str, input: &str, max_steps: usize) -> Residual {
+        let residual = specialize(&Engine::from_source(source, input).unwrap(), max_steps).unwrap();
+        let rest = input.chars().skip(residual.input_read).collect::<String>();
+        let expected = Engine::from_source(source, input).unwrap().run(100_000);
+        let got = Engine::from_source(&residual.source, &rest)
+            .unwrap()
+            .run(100_000);
+        assert!(expected.halted() && got.halted());
+        assert_eq!(
+            got.output, expected.output,
+            "{:?} on {:?} after {} steps: {:?}",
+            source, input, max_steps, residual
+        );
+        residual
+    }
+
+    #[test]
+    fn residuals_carry_on_where_the_run_stopped() {
+        let cases = [
+            (",[.,]", "abc"),
+            (",>,<[->+<]>.", "\u{3}\u{4}"),
+            ("++++[>++++<-]>.<,.", "z"),
+            // cells left of the start and a pointer that ends up there
+            ("<<+++>--->+[<<+>>-]<<.>.<<,.", "q"),
+        ];
+        for &(source, input) in &cases {
+            let mut resumed = false;
+            for max_steps in 0..60 {
+                resumed |= agrees(source, input, max_steps).resumes_at.is_some();
+            }
+            assert!(resumed, "{:?} never resumed", source);
+            assert_eq!(agrees(source, input, 100_000).resumes_at, None);
+        }
+    }
+
+    #[test]
+    fn input_read_ahead_is_cut() {
+        let residual = agrees(",.,.,[.,]", "abcd", 6);
+        assert_eq!(residual.input_read, 3);
+        assert!(residual
+            .source
+            .starts_with("+".repeat(b'a' as usize).as_str()));
+    }
+}
@@ -0,0 +1,138 @@
+// The rewrites, each one keeps what the program writes for every input whatever ran before it.
+use super::ir::{Op, Program, Tape};
+
+// a bigger tape isn't worth allocating up front, it keeps growing as it goes
+const MAX_BOUNDED: usize = 1 << 24;
+
+/// Runs of `+` and `-` on one cell and runs of moves become one op, dropping those that cancel.
+pub fn fuse(program: &mut Program) {
+    let mut ops: Vec<Op> = Vec::with_capacity(program.ops.len());
+    for &op in program.ops.iter() {
+        match (ops.last_mut(), op) {
+            (
+                Some(Op::Add { offset, amount }),
+                Op::Add {
+                    offset: next,
+                    amount: more,
+                },
+            ) if *offset == next => *amount = amount.wrapping_add(more),
+            (Some(Op::Move(distance)), Op::Move(more)) => *distance += more,
+            _ => ops.push(op),
+        }
+        // what's before it may fuse with what comes next now
+        if let Some(Op::Add { amount: 0, .. }) | Some(Op::Move(0)) = ops.last() {
+            ops.pop();
+        }
+    }
+    program.ops = ops;
+    program.relink();
+}
+
+/// `[-]`, `[+]` and any other loop adding an odd amount to its own cell and nothing else leave
+/// that cell at 0, which is one op.
+///
+/// An even amount never gets an odd cell to 0, those loops can spin forever and stay.
+pub fn clear_loops(program: &mut Program) {
+    let mut ops = Vec::with_capacity(program.ops.len());
+    let mut i = 0;
+    while i < program.ops.len() {
+        match program.ops[i..] {
+            [Op::Open { .. }, Op::Add { offset: 0, amount }, Op::Close { .. }, ..]
+                if amount % 2 == 1 =>
+            {
+                ops.push(Op::Clear { offset: 0 });
+                i += 3;
+            }
+            _ => {
+                ops.push(program.ops[i]);
+                i += 1;
+            }
+        }
+    }
+    program.ops = ops;
+    program.relink();
+}
+
+/// Drops loops that start on a cell known to be 0: at the very beginning, where every cell is,
+/// and right after a loop or a clear, nothing having changed the cell since.
+pub fn dead_loops(program: &mut Program) {
+    let mut ops = Vec::with_capacity(program.ops.len());
+    let mut zero = true;
+    let mut i = 0;
+    while i < program.ops.len() {
+        let op = program.ops[i];
+        if let Op::Open { end } = op {
+            if zero {
+                i = end + 1;
+                continue;
+            }
+        }
+        zero = match op {
+            Op::Close { .. } | Op::Clear { offset: 0 } => true,
+            // the cell under the pointer stays what it was
+            Op::Write { .. } => zero,
+            Op::Add { offset, .. } | Op::Clear { offset } | Op::Read { offset } => {
+                zero && offset != 0
+            }
+            _ => false,
+        };
+        ops.push(op);
+        i += 1;
+    }
+    program.ops = ops;
+    program.relink();
+}
+
+/// Moves between loops are added up and made once at the end, the ops in between address their
+/// cells by how far they are from where the pointer is until then.
+///
+/// A loop tests the cell under the pointer, so the pointer is where it would have been before
+/// each `Open` and `Close`.
+pub fn offsets(program: &mut Program) {
+    let mut ops = Vec::with_capacity(program.ops.len());
+    let mut shift = 0;
+    for &op in program.ops.iter() {
+        match op {
+            Op::Move(distance) => {
+                shift += distance;
+                continue;
+            }
+            Op::Add { offset, amount } => ops.push(Op::Add {
+                offset: offset + shift,
+                amount,
+            }),
+            Op::Clear { offset } => ops.push(Op::Clear {
+                offset: offset + shift,
+            }),
+            Op::Write { offset } => ops.push(Op::Write {
+                offset: offset + shift,
+            }),
+            Op::Read { offset } => ops.push(Op::Read {
+                offset: offset + shift,
+            }),
+            Op::Open { .. } | Op::Close { .. } => {
+                if shift != 0 {
+                    ops.push(Op::Move(shift));
+                    shift = 0;
+                }
+                ops.push(op);
+            }
+        }
+    }
+    if shift != 0 {
+        ops.push(Op::Move(shift));
+    }
+    program.ops = ops;
+    program.relink();
+}
+
+/// Allocates the cells `tape_bounds` found the source reaching up front, if it found a bound,
+/// sparing the run the checks for whether the tape has to grow.
+pub fn bounded_tape(program: &mut Program) {
+    if let Some((lowest, highest)) = program.reach {
+        let len = (highest - lowest) as usize + 1;
+        if len <= MAX_BOUNDED {
+            program.tape = Tape::Bounded { lowest, len };
+        }
+    }
+}
@@ -0,0 +1,306 @@
+// Runs a program of `<>+-[].,` on every input of some length at once, as far as a bound lets it,
+// to find the outputs it can write. Experimental.
+//
+// The bytes read are unknowns, a cell holds a number or an unknown plus a number. A loop testing
+// an unknown splits the run in two, one where it's 0 and one where it isn't, and the run where
+// it's 0 knows that byte from then on. Reading past the end gives 0 like the engine's, which an
+// unknown can be too, so the inputs looked at are all those up to that length.
+use std::fmt;
+
+use super::ir::{Op, Program};
+
+/// What a cell holds: a number, or byte `index` of the input plus `plus`, wrapping around.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Known(u8),
+    Input { index: usize, plus: u8 },
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Value::Known(value) => write!(f, "{}", value),
+            Value::Input { index, plus: 0 } => write!(f, "in[{}]", index),
+            Value::Input { index, plus } => write!(f, "in[{}]+{}", index, plus),
+        }
+    }
+}
+
+/// What a run found out about a byte of the input.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Unknown {
+    Is(u8),
+    /// Any but these, the run never tested it if there are none.
+    Not(Vec<u8>),
+}
+
+impl Unknown {
+    /// A byte it can be, the lowest.
+    pub fn example(&self) -> u8 {
+        match self {
+            Unknown::Is(value) => *value,
+            Unknown::Not(excluded) => (0..=u8::MAX)
+                .find(|value| !excluded.contains(value))
+                .unwrap_or(0),
+        }
+    }
+}
+
+/// One way the program can go.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ending {
+    pub output: Vec<Value>,
+    /// What each byte of the input has to be for it to go this way.
+    pub input: Vec<Unknown>,
+    /// `false` if it was cut off by `max_steps`.
+    pub halted: bool,
+}
+
+impl Ending {
+    /// The output as text, what's known in quotes and the unknowns as `in[i]`, like
+    /// `"x=" in[0]+1`.
+    pub fn describe_output(&self) -> String {
+        let mut parts = vec![];
+        let mut known = String::new();
+        for value in self.output.iter() {
+            match value {
+                Value::Known(byte) => known.push(*byte as char),
+                unknown => {
+                    if !known.is_empty() {
+                        parts.push(format!("{:?}", known));
+                        known.clear();
+                    }
+                    parts.push(unknown.to_string());
+                }
+            }
+        }
+        if !known.is_empty() || parts.is_empty() {
+            parts.push(format!("{:?}", known));
+        }
+        parts.join(" ")
+    }
+
+    /// The bytes of the input it was told about, `in[0] = 0, in[2] != 10`, or `any input`.
+    pub fn describe_input(&self) -> String {
+        let conditions = self
+            .input
+            .iter()
+            .enumerate()
+            .filter_map(|(index, unknown)| match unknown {
+                Unknown::Is(value) => Some(format!("in[{}] = {}", index, value)),
+                Unknown::Not(excluded) if excluded.is_empty() => None,
+                Unknown::Not(excluded) => {
+                    let excluded = excluded.iter().map(u8::to_string).collect::<Vec<_>>();
+                    Some(format!("in[{}] != {}", index, excluded.join(", ")))
+                }
+            })
+            .collect::<Vec<_>>();
+        if conditions.is_empty() {
+            "any input".to_string()
+        } else {
+            conditions.join(", ")
+        }
+    }
+}
+
+/// How far to look.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    /// Bytes of input, those past it read as 0.
+    pub input: usize,
+    /// Ops run along each way, from the start.
+    pub max_steps: usize,
+    /// Ways there can be, ended or not, a split making two of one; those past it aren't followed.
+    pub max_paths: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exploration {
+    /// In the order they were come to.
+    pub endings: Vec<Ending>,
+    /// Ways split off and left alone once there were `max_paths`.
+    pub unexplored: usize,
+}
+
+// one way the run is going
+#[derive(Debug, Clone)]
+struct Path {
+    ip: usize,
+    pointer: isize,
+    // the cells that aren't a known 0, by index
+    cells: Vec<(isize, Value)>,
+    input: Vec<Unknown>,
+    output: Vec<Value>,
+    steps: usize,
+}
+
+impl Path {
+    fn cell(&self, index: isize) -> Value {
+        let value = self
+            .cells
+            .iter()
+            .find(|(cell, _)| *cell == index)
+            .map_or(Value::Known(0), |&(_, value)| value);
+        self.resolve(value)
+    }
+
+    fn set(&mut self, index: isize, value: Value) {
+        self.cells.retain(|(cell, _)| *cell != index);
+        if value != Value::Known(0) {
+            self.cells.push((index, value));
+        }
+    }
+
+    // an unknown this run found out is known
+    fn resolve(&self, value: Value) -> Value {
+        match value {
+            Value::Input { index, plus } => match self.input[index] {
+                Unknown::Is(byte) => Value::Known(byte.wrapping_add(plus)),
+                Unknown::Not(_) => value,
+            },
+            known => known,
+        }
+    }
+
+    // the way it goes if byte `index` of the input is `byte`, `None` if it can't be
+    fn with(&self, index: usize, byte: u8) -> Option<Path> {
+        match &self.input[index] {
+            Unknown::Not(excluded) if excluded.contains(&byte) => None,
+            _ => {
+                let mut path = self.clone();
+                path.input[index] = Unknown::Is(byte);
+                Some(path)
+            }
+        }
+    }
+
+    // the way it goes if byte `index` of the input isn't `byte`
+    fn without(&self, index: usize, byte: u8) -> Path {
+        let mut path = self.clone();
+        if let Unknown::Not(excluded) = &mut path.input[index] {
+            excluded.push(byte);
+            excluded.sort_unstable();
+            excluded.dedup();
+            // the one it can still be
+            if excluded.len() == 255 {
+                let last = (0..=u8::MAX).find(|b| !excluded.contains(b)).unwrap();
+                path.input[index] = Unknown::Is(last);
+            }
+        }
+        path
+    }
+
+    fn ending(self, halted: bool) -> Ending {
+        let output = self
+            .output
+            .iter()
+            .map(|&value| self.resolve(value))
+            .collect();
+        Ending {
+            output,
+            input: self.input,
+            halted,
+        }
+    }
+}
+
+/// Follows every way `program` can go on `bounds.input` bytes of input.
+pub fn explore(program: &Program, bounds: Bounds) -> Exploration {
+    let mut exploration = Exploration {
+        endings: vec![],
+        unexplored: 0,
+    };
+    let mut stack = vec![Path {
+        ip: 0,
+        pointer: 0,
+        cells: vec![],
+        input: vec![],
+        output: vec![],
+        steps: 0,
+    }];
+    let mut paths = 1;
+    while let Some(mut path) = stack.pop() {
+        loop {
+            let op = match program.ops.get(path.ip) {
+                Some(&op) => op,
+                None => {
+                    exploration.endings.push(path.ending(true));
+                    break;
+                }
+            };
+            if path.steps >= bounds.max_steps {
+                exploration.endings.push(path.ending(false));
+                break;
+            }
+            path.steps += 1;
+            let pointer = path.pointer;
+            match op {
+                Op::Add { offset, amount } => {
+                    let value = match path.cell(pointer + offset) {
+                        Value::Known(value) => Value::Known(value.wrapping_add(amount)),
+                        Value::Input { index, plus } => Value::Input {
+                            index,
+                            plus: plus.wrapping_add(amount),
+                        },
+                    };
+                    path.set(pointer + offset, value);
+                }
+                Op::Clear { offset } => path.set(pointer + offset, Value::Known(0)),
+                Op::Move(distance) => path.pointer += distance,
+                Op::Write { offset } => {
+                    let value = path.cell(pointer + offset);
+                    path.output.push(value);
+                }
+                Op::Read { offset } => {
+                    let value = if path.input.len() < bounds.input {
+                        path.input.push(Unknown::Not(vec![]));
+                        Value::Input {
+                            index: path.input.len() - 1,
+                            plus: 0,
+                        }
+                    } else {
+                        Value::Known(0)
+                    };
+                    path.set(pointer + offset, value);
+                }
+                Op::Open { end: target } | Op::Close { start: target } => {
+                    let jumps_on_zero = matches!(op, Op::Open { .. });
+                    // where it goes if the cell is 0 and if it isn't
+                    let (zero, nonzero) = if jumps_on_zero {
+                        (target + 1, path.ip + 1)
+                    } else {
+                        (path.ip + 1, target + 1)
+                    };
+                    match path.cell(pointer) {
+                        Value::Known(0) => path.ip = zero - 1,
+                        Value::Known(_) => path.ip = nonzero - 1,
+                        Value::Input { index, plus } => {
+                            // the byte that makes it 0
+                            let byte = 0u8.wrapping_sub(plus);
+                            let mut ways = vec![path.without(index, byte)];
+                            ways[0].ip = nonzero;
+                            if let Some(mut way) = path.with(index, byte) {
+                                way.ip = zero;
+                                ways.push(way);
+                            }
+                            // this one splits into those, the way on with the cell at 0 is
+                            // followed first, it's the one out of the loop
+                            paths -= 1;
+                            for way in ways {
+                                if paths < bounds.max_paths {
+                                    paths += 1;
+                                    stack.push(way);
+                                } else {
+                                    exploration.unexplored += 1;
+                                }
+                            }
+                            break;
+                        }
+                    }
+                }
+            }
+            path.ip += 1;
+        }
+    }
+    exploration
+}
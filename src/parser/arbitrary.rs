@@ -0,0 +1,87 @@
+use arbitrary::{Arbitrary, Error, Result, Unstructured};
+
+use super::{
+    bf5d::parse_with,
+    types::{Extensions, Token},
+};
+
+// every instruction of every extension that doesn't pair up with another or name a label
+const INSTRUCTIONS: [char; 25] = [
+    '<', '>', '^', 'v', '+', '-', '.', ',', '~', '@', '#', '?', ':', ';', '!', '_', '%', '$', '&',
+    '*', '=', '|', '`', '\\', '0',
+];
+// `(`, `Y` and `I` all end at a `)`
+const OPENERS: [(char, char); 4] = [('[', ']'), ('(', ')'), ('Y', ')'), ('I', ')')];
+// keeps generated programs small enough for a fuzzer to actually run them
+const MAX_LEN: usize = 1024;
+
+/// A token stream of every extension's instructions that is structurally valid: brackets,
+/// spawns, forks and conditional spawns are balanced, every jump/spawn index points at its
+/// partner and every goto and call names a label defined before it. Run it with
+/// `Extensions::all()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program(pub Vec<Token>);
+
+impl<'a> Arbitrary<'a> for Program {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut source = String::new();
+        // closing characters still owed, innermost last
+        let mut closers = Vec::new();
+        let mut labels = 0u32;
+
+        while !u.is_empty() && source.len() + closers.len() < MAX_LEN {
+            match u.int_in_range(0..=5u8)? {
+                0 => source.push(*u.choose(&INSTRUCTIONS)?),
+                1 | 2 => {
+                    let (opener, closer) = *u.choose(&OPENERS)?;
+                    source.push(opener);
+                    closers.push(closer);
+                }
+                3 => {
+                    if let Some(closer) = closers.pop() {
+                        source.push(closer);
+                    }
+                }
+                4 => {
+                    source.push_str(&format!("'l{}'", labels));
+                    labels += 1;
+                }
+                _ if labels == 0 => (),
+                _ => {
+                    let label = u.int_in_range(0..=labels - 1)?;
+                    if u.ratio(1u8, 2)? {
+                        source.push_str(&format!("\"l{}\"", label));
+                    } else {
+                        source.push_str(&format!("{{l{}}}", label));
+                    }
+                }
+            }
+        }
+        source.extend(closers.into_iter().rev());
+
+        // let the parser resolve the indices so they follow the engine's own rules
+        parse_with(source.as_str(), Extensions::all())
+            .map(Program)
+            .map_err(|_| Error::IncorrectFormat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::rng::Rng;
+
+    #[test]
+    fn programs_use_every_extension() {
+        let mut rng = Rng::new(7);
+        let mut seen = Extensions::empty();
+        for _ in 0..200 {
+            let bytes = (0..256).map(|_| rng.next_u8()).collect::<Vec<_>>();
+            let Program(tokens) = Program::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+            for extension in tokens.iter().filter_map(Token::extension) {
+                seen |= extension;
+            }
+        }
+        assert_eq!(seen, Extensions::all());
+    }
+}
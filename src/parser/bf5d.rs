@@ -1,18 +1,21 @@
+use std::{collections::HashMap, ops::Range};
+
 use nom::{
     branch::alt,
     bytes::complete::{is_not, tag, take_until},
-    character::complete::anychar,
+    character::complete::{anychar, char},
     combinator::{map, map_res, recognize},
-    error::{ParseError, VerboseError},
+    error::{ErrorKind, ParseError},
     sequence::{pair, tuple},
     IResult,
 };
 
+use super::brackets::Matcher;
 use super::types::*;
 use super::utils::{fold_many0_while, FoldWhile};
 
 impl Token {
-    fn from(input: char) -> Result<Token, () /* TODO: error message */> {
+    pub(crate) fn from(input: char) -> Result<Token, () /* TODO: error message */> {
         use self::JumpType::*;
         use self::MoveDirection::*;
         use self::Token::*;
@@ -39,6 +42,23 @@ impl Token {
             '(' => Ok(Spawn { index: 0 }),
             ')' => Ok(Kill),
             '@' => Ok(Await),
+            '#' => Ok(DebugDump),
+            '?' => Ok(Random),
+            ':' => Ok(WriteNumber),
+            ';' => Ok(Return),
+            '!' => Ok(RaisePriority),
+            '_' => Ok(LowerPriority),
+            '%' => Ok(Sleep),
+            '$' => Ok(CopyToBelow),
+            '&' => Ok(CopyFromBelow),
+            '*' => Ok(SplitPointer),
+            '=' => Ok(MergePointers),
+            'Y' => Ok(Fork { index: 0 }),
+            'I' => Ok(SpawnIf { index: 0 }),
+            '|' => Ok(Barrier),
+            '`' => Ok(SetMarker),
+            '\\' => Ok(RewindToMarker),
+            '0' => Ok(ResetTape),
             _ => Err(()),
         }
     }
@@ -57,99 +77,175 @@ pub fn c_comment(i: &str) -> IResult<&str, &str, BF5DParseError> {
     ))(i)
 }
 
-pub fn parse<'a>(input: &str) -> Result<Vec<Token>, BF5DParseError> {
+pub fn parse(input: &str) -> Result<Vec<Token>, BF5DParseError> {
+    parse_with(input, Extensions::default())
+}
+
+// `'name'`, `"name"` and `{name}`, `None` when the closing character is missing
+fn quoted<'a>(
+    open: char,
+    close: char,
+    enabled: bool,
+) -> impl Fn(&'a str) -> IResult<&'a str, Option<&'a str>, BF5DParseError> {
+    move |i: &'a str| {
+        if !enabled {
+            return Err(nom::Err::Error(BF5DParseError::from_error_kind(
+                i,
+                ErrorKind::Char,
+            )));
+        }
+
+        let (rest, _) = char(open)(i)?;
+        Ok(match rest.find(close) {
+            Some(end) => (&rest[end + close.len_utf8()..], Some(&rest[..end])),
+            None => ("", None),
+        })
+    }
+}
+
+// pairs what `parser` parsed with where in `input` it was
+fn spanned<'a, O>(
+    input: &'a str,
+    mut parser: impl FnMut(&'a str) -> IResult<&'a str, O, BF5DParseError>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, (Range<usize>, O), BF5DParseError> {
+    move |i: &'a str| {
+        let start = input.len() - i.len();
+        let (rest, output) = parser(i)?;
+        Ok((rest, (start..input.len() - rest.len(), output)))
+    }
+}
+
+pub fn parse_with(input: &str, extensions: Extensions) -> Result<Vec<Token>, BF5DParseError> {
+    parse_with_spans(input, extensions).map(|(tokens, _)| tokens)
+}
+
+/// Like `parse_with`, along with the bytes of `input` each token was parsed from.
+pub fn parse_with_spans(
+    input: &str,
+    extensions: Extensions,
+) -> Result<(Vec<Token>, Vec<Range<usize>>), BF5DParseError> {
     enum Temp {
         Token(Token),
         Comment(String),
+        Label(Option<String>),
+        Goto(Option<String>),
+        Call(Option<String>),
     }
 
     use nom::Err::*;
 
+    let temp = alt((
+        // instructions of disabled extensions are comments, `Y` and `I` would pair with `)`
+        map(
+            map_res(anychar, |c| match Token::from(c) {
+                Ok(token) if !token.extension().is_none_or(|e| extensions.contains(e)) => Err(()),
+                token => token,
+            }),
+            Temp::Token,
+        ),
+        // procedures are called by label, so they need the definitions too
+        map(
+            quoted(
+                '\'',
+                '\'',
+                extensions.intersects(Extensions::LABELS | Extensions::PROCEDURES),
+            ),
+            |name| Temp::Label(name.map(str::to_string)),
+        ),
+        map(
+            quoted('"', '"', extensions.contains(Extensions::LABELS)),
+            |name| Temp::Goto(name.map(str::to_string)),
+        ),
+        map(
+            quoted('{', '}', extensions.contains(Extensions::PROCEDURES)),
+            |name| Temp::Call(name.map(str::to_string)),
+        ),
+        map(c_comment, |c| Temp::Comment(c.to_string())),
+        map(anychar, |c| Temp::Comment(c.to_string())),
+    ));
+
     match fold_many0_while(
-        alt((
-            map(map_res(anychar, Token::from), Temp::Token),
-            map(c_comment, |c| Temp::Comment(c.to_string())),
-            map(anychar, |c| Temp::Comment(c.to_string())),
-        )),
+        spanned(input, temp),
         // map_res(anychar, Token::from),
         || {
             (
-                Vec::new(), /* tokens */
-                Vec::new(), /* bracket stack */
-                Vec::new(), /* parens stack */
-                0usize,     /* index */
+                Vec::new(),         /* tokens */
+                Matcher::default(), /* what the brackets and spawns pair up with */
+                0usize,             /* index */
+                HashMap::new(),     /* label name -> index of the token after it */
+                Vec::new(),         /* (index, label name) of jumps to resolve */
+                Vec::new(),         /* spans of the tokens */
             )
         },
-        |(mut tokens, mut brackets, mut parens, i), token| {
-            if let Temp::Token(token) = token {
-                match token {
-                    Token::Jump {
-                        type_: JumpType::IfZero,
-                        index: _,
-                    } => {
-                        brackets.push(i);
-                        tokens.push(token);
-                    }
-                    Token::Spawn { index: _ } => {
-                        parens.push(i);
-                        tokens.push(token);
-                    }
-                    Token::Jump {
-                        type_: JumpType::IfNotZero,
-                        index: _,
-                    } => {
-                        if let Some(last) = brackets.pop() {
-                            tokens.push(Token::Jump {
-                                type_: JumpType::IfNotZero,
-                                index: last,
-                            });
-                            tokens[last] = Token::Jump {
-                                type_: JumpType::IfZero,
-                                index: i,
-                            };
-                        } else {
-                            return FoldWhile::Throw(Failure(BF5DParseError::new(
-                                "unmatched bracket",
-                                i,
-                            )));
-                        }
-                    }
-                    Token::Kill => {
-                        if let Some(last) = parens.pop() {
-                            tokens.push(token);
-                            tokens[last] = Token::Spawn { index: i };
-                        } else {
-                            return FoldWhile::Throw(Failure(BF5DParseError::new(
-                                "unmatched parentheses",
-                                i,
-                            )));
-                        }
+        |(mut tokens, mut matcher, i, mut labels, mut references, mut spans), (span, token)| {
+            match token {
+                Temp::Token(token) => {
+                    // the jumps and spawns get their targets once the whole program is in
+                    if let Err(e) = matcher.push(i, &token) {
+                        return FoldWhile::Throw(Failure(
+                            BF5DParseError::new(e.message(), i).with_span(span),
+                        ));
                     }
-                    token => {
-                        tokens.push(token);
+                    tokens.push(token);
+                    spans.push(span);
+                    FoldWhile::Continue((tokens, matcher, i + 1, labels, references, spans))
+                }
+                Temp::Label(Some(name)) => {
+                    if labels.insert(name, i).is_some() {
+                        return FoldWhile::Throw(Failure(
+                            BF5DParseError::new("duplicate label", i).with_span(span),
+                        ));
                     }
+                    FoldWhile::Continue((tokens, matcher, i, labels, references, spans))
+                }
+                Temp::Goto(Some(name)) => {
+                    tokens.push(Token::Goto { index: 0 });
+                    references.push((i, name));
+                    spans.push(span);
+                    FoldWhile::Continue((tokens, matcher, i + 1, labels, references, spans))
+                }
+                Temp::Call(Some(name)) => {
+                    tokens.push(Token::Call { index: 0 });
+                    references.push((i, name));
+                    spans.push(span);
+                    FoldWhile::Continue((tokens, matcher, i + 1, labels, references, spans))
+                }
+                Temp::Label(None) | Temp::Goto(None) | Temp::Call(None) => FoldWhile::Throw(
+                    Failure(BF5DParseError::new("unterminated label", i).with_span(span)),
+                ),
+                Temp::Comment(_) => {
+                    FoldWhile::Continue((tokens, matcher, i, labels, references, spans))
                 }
-                FoldWhile::Continue((tokens, brackets, parens, i + 1))
-            } else {
-                FoldWhile::Continue((tokens, brackets, parens, i))
             }
         },
     )(input)
     {
-        Ok((_, (tokens, brackets, parens, _))) => {
-            match (!brackets.is_empty(), !parens.is_empty()) {
-                (true, _) => Err(BF5DParseError::new(
-                    "unmatched parentheses",
-                    *parens.last().unwrap(),
-                )),
-                (_, true) => Err(BF5DParseError::new(
-                    "unmatched parentheses",
-                    *parens.last().unwrap(),
-                )),
-                _ => Ok(tokens),
+        Ok((_, (mut tokens, matcher, _, labels, references, spans))) => {
+            matcher
+                .finish(tokens.len())
+                .map_err(|e| {
+                    BF5DParseError::new(e.message(), e.instruction())
+                        .with_span(spans[e.instruction()].clone())
+                })?
+                .apply(&mut tokens);
+            // labels may be used before they are defined
+            for (at, name) in references {
+                let index = match labels.get(&name) {
+                    Some(&index) => index,
+                    None => {
+                        return Err(
+                            BF5DParseError::new("undefined label", at).with_span(spans[at].clone())
+                        )
+                    }
+                };
+                tokens[at] = match tokens[at] {
+                    Token::Call { .. } => Token::Call { index },
+                    _ => Token::Goto { index },
+                };
             }
+            Ok((tokens, spans))
         }
-        Err(nom::Err::Error(e)) => Err(e),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Err(e),
         _ => panic!("this should never happen"),
     }
 }
@@ -0,0 +1,158 @@
+// How `[` pairs with `]` and what spawns with `)`, the rules the parser fills in jump targets by.
+//
+// The two are matched each on their own stack, `( [ ) ]` pairs the `(` with the `)` and the `[`
+// with the `]`. Tools that rewrite programs can match them here instead of keeping a copy of those
+// rules that drifts away from the engine's.
+use std::fmt;
+
+use super::types::{JumpType, Token};
+
+/// The instruction each `[`, `]`, `(`, `Y`, `I` and `)` of a program pairs up with.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JumpTable {
+    partners: Vec<Option<usize>>,
+}
+
+impl JumpTable {
+    /// What `instruction` pairs up with, `None` if it doesn't open or close anything.
+    pub fn partner(&self, instruction: usize) -> Option<usize> {
+        self.partners.get(instruction).copied().flatten()
+    }
+
+    /// Each `(opening, closing)` pair, by where it opens.
+    pub fn pairs(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.partners
+            .iter()
+            .enumerate()
+            .filter_map(|(open, partner)| match *partner {
+                Some(close) if close > open => Some((open, close)),
+                _ => None,
+            })
+    }
+
+    /// Writes the partners into the jumps and spawns of `tokens`, the ones this was made from.
+    ///
+    /// A `)` has nowhere to keep the index, the spawn it closes points at it.
+    pub fn apply(&self, tokens: &mut [Token]) {
+        for (i, token) in tokens.iter_mut().enumerate() {
+            let partner = match self.partner(i) {
+                Some(partner) => partner,
+                None => continue,
+            };
+            *token = match *token {
+                Token::Jump { type_, .. } => Token::Jump {
+                    type_,
+                    index: partner,
+                },
+                Token::Spawn { .. } => Token::Spawn { index: partner },
+                Token::Fork { .. } => Token::Fork { index: partner },
+                Token::SpawnIf { .. } => Token::SpawnIf { index: partner },
+                token => token,
+            };
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchError {
+    /// A `]` with no `[` before it, or the last `[` left open at the end.
+    UnmatchedBracket(usize),
+    /// A `)` with nothing spawning before it, or the last spawn left open at the end.
+    UnmatchedParentheses(usize),
+}
+
+impl MatchError {
+    pub fn instruction(&self) -> usize {
+        match *self {
+            MatchError::UnmatchedBracket(instruction)
+            | MatchError::UnmatchedParentheses(instruction) => instruction,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            MatchError::UnmatchedBracket(_) => "unmatched bracket",
+            MatchError::UnmatchedParentheses(_) => "unmatched parentheses",
+        }
+    }
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at instruction {}",
+            self.message(),
+            self.instruction()
+        )
+    }
+}
+
+/// Pairs up the brackets and spawns of `tokens`, whatever indices they have now.
+///
+/// The error is the first closer without an opener, and past the end a `[` left open before a
+/// spawn left open.
+pub fn match_brackets(tokens: &[Token]) -> Result<JumpTable, MatchError> {
+    let mut matcher = Matcher::default();
+    for (i, token) in tokens.iter().enumerate() {
+        matcher.push(i, token)?;
+    }
+    matcher.finish(tokens.len())
+}
+
+/// `match_brackets` a token at a time, for a closer without an opener to turn up as soon as it's
+/// read. Only the brackets and spawns have to be pushed, in order.
+#[derive(Debug, Clone, Default)]
+pub struct Matcher {
+    pairs: Vec<(usize, usize)>,
+    brackets: Vec<usize>,
+    parens: Vec<usize>,
+}
+
+impl Matcher {
+    /// Takes the token at `instruction`, what it closes is known from here on.
+    pub fn push(&mut self, instruction: usize, token: &Token) -> Result<(), MatchError> {
+        let open = match token {
+            Token::Jump {
+                type_: JumpType::IfZero,
+                ..
+            } => {
+                self.brackets.push(instruction);
+                return Ok(());
+            }
+            Token::Spawn { .. } | Token::Fork { .. } | Token::SpawnIf { .. } => {
+                self.parens.push(instruction);
+                return Ok(());
+            }
+            Token::Jump {
+                type_: JumpType::IfNotZero,
+                ..
+            } => self
+                .brackets
+                .pop()
+                .ok_or(MatchError::UnmatchedBracket(instruction))?,
+            Token::Kill => self
+                .parens
+                .pop()
+                .ok_or(MatchError::UnmatchedParentheses(instruction))?,
+            _ => return Ok(()),
+        };
+        self.pairs.push((open, instruction));
+        Ok(())
+    }
+
+    /// The table for a program `len` instructions long, once they've all been pushed.
+    pub fn finish(self, len: usize) -> Result<JumpTable, MatchError> {
+        match (self.brackets.last(), self.parens.last()) {
+            (Some(&open), _) => return Err(MatchError::UnmatchedBracket(open)),
+            (_, Some(&open)) => return Err(MatchError::UnmatchedParentheses(open)),
+            _ => (),
+        }
+        let mut partners = vec![None; len];
+        for (open, close) in self.pairs {
+            partners[open] = Some(close);
+            partners[close] = Some(open);
+        }
+        Ok(JumpTable { partners })
+    }
+}
@@ -0,0 +1,190 @@
+// Syntax highlighting grammars made from the parser's own alphabet, so editors can't drift from it.
+use std::{fmt::Write, str::FromStr};
+
+use super::types::{Extensions, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GrammarFormat {
+    /// A `.tmLanguage.json`, for VS Code, Sublime and most others.
+    TextMate,
+    /// A tree-sitter `grammar.js`.
+    TreeSitter,
+}
+
+impl FromStr for GrammarFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "textmate" => Ok(GrammarFormat::TextMate),
+            "tree-sitter" => Ok(GrammarFormat::TreeSitter),
+            _ => Err(format!("unknown grammar format {:?}", name)),
+        }
+    }
+}
+
+// how instructions are grouped for highlighting, the rule name and TextMate scope of each
+const GROUPS: &[(&str, &str)] = &[
+    ("move", "keyword.operator.move"),
+    ("update", "keyword.operator.update"),
+    ("loop", "keyword.control.loop"),
+    ("timeline", "keyword.control.timeline"),
+    ("io", "support.function.io"),
+    ("time_travel", "keyword.control.rewind"),
+    ("sync", "keyword.control.sync"),
+    ("other", "keyword.other"),
+];
+
+fn group(token: &Token) -> &'static str {
+    use Token::*;
+
+    match token {
+        Move(_) => "move",
+        Update(_) => "update",
+        Jump { .. } | Goto { .. } | Call { .. } | Return => "loop",
+        Spawn { .. } | Fork { .. } | SpawnIf { .. } | Kill | SplitPointer | MergePointers => {
+            "timeline"
+        }
+        Write | Read | WriteNumber => "io",
+        Rewind | SetMarker | RewindToMarker | ResetTape => "time_travel",
+        Await | Barrier | Sleep | RaisePriority | LowerPriority => "sync",
+        _ => "other",
+    }
+}
+
+/// Every character that is an instruction with `extensions`, in order.
+pub fn alphabet(extensions: Extensions) -> Vec<(char, Token)> {
+    (' '..='~')
+        .filter_map(|c| Token::from(c).ok().map(|token| (c, token)))
+        .filter(|(_, token)| token.extension().is_none_or(|e| extensions.contains(e)))
+        .collect()
+}
+
+// the quoted names that are on: opening and closing character, rule name and TextMate scope
+fn labels(extensions: Extensions) -> Vec<(char, char, &'static str, &'static str)> {
+    let mut labels = vec![];
+    // procedures are defined with labels too
+    if extensions.intersects(Extensions::LABELS | Extensions::PROCEDURES) {
+        labels.push(('\'', '\'', "label", "entity.name.label"));
+    }
+    if extensions.contains(Extensions::LABELS) {
+        labels.push(('"', '"', "goto", "variable.other.label"));
+    }
+    if extensions.contains(Extensions::PROCEDURES) {
+        labels.push(('{', '}', "call", "entity.name.function"));
+    }
+    labels
+}
+
+// a regex character class that reads the same in Oniguruma, JavaScript and tree-sitter, which
+// takes only escapes of characters that mean something in some regex
+fn class(chars: impl Iterator<Item = char>, negated: bool) -> String {
+    let mut class = String::from(if negated { "[^" } else { "[" });
+    for c in chars {
+        if "\\.+*?()|[]{}^$#&-~/".contains(c) {
+            class.push('\\');
+        }
+        class.push(c);
+    }
+    class.push(']');
+    class
+}
+
+/// The grammar for `extensions`, anything that isn't an instruction or label is a comment.
+pub fn grammar(format: GrammarFormat, extensions: Extensions) -> String {
+    let alphabet = alphabet(extensions);
+    let labels = labels(extensions);
+    let groups = GROUPS
+        .iter()
+        .map(|&(name, scope)| {
+            let chars = alphabet
+                .iter()
+                .filter(|(_, token)| group(token) == name)
+                .map(|(c, _)| *c)
+                .collect::<Vec<_>>();
+            (name, scope, chars)
+        })
+        .filter(|(_, _, chars)| !chars.is_empty())
+        .collect::<Vec<_>>();
+    let comment = class(
+        alphabet
+            .iter()
+            .map(|(c, _)| *c)
+            .chain(labels.iter().map(|(open, _, _, _)| *open)),
+        true,
+    ) + "+";
+    let label = |open: char, close: char| {
+        format!(
+            "{}{}*{}",
+            class(Some(open).into_iter(), false),
+            class(Some(close).into_iter(), true),
+            class(Some(close).into_iter(), false)
+        )
+    };
+
+    // the patterns are all ASCII, `{:?}` quotes them the same way JSON and JavaScript do
+    let mut out = String::new();
+    match format {
+        GrammarFormat::TextMate => {
+            let mut patterns = vec![];
+            for (open, close, name, scope) in labels.iter() {
+                patterns.push(format!(
+                    "    {{ \"name\": \"{}.bf5d\", \"match\": {:?}, \"comment\": {:?} }}",
+                    scope,
+                    label(*open, *close),
+                    name
+                ));
+            }
+            for (name, scope, chars) in groups.iter() {
+                patterns.push(format!(
+                    "    {{ \"name\": \"{}.bf5d\", \"match\": {:?}, \"comment\": {:?} }}",
+                    scope,
+                    class(chars.iter().copied(), false),
+                    name
+                ));
+            }
+            patterns.push(format!(
+                "    {{ \"name\": \"comment.block.bf5d\", \"match\": {:?} }}",
+                comment
+            ));
+
+            writeln!(out, "{{").unwrap();
+            writeln!(out, "  \"name\": \"BF5D\",").unwrap();
+            writeln!(out, "  \"scopeName\": \"source.bf5d\",").unwrap();
+            writeln!(out, "  \"fileTypes\": [\"bf5d\"],").unwrap();
+            writeln!(out, "  \"patterns\": [\n{}\n  ]", patterns.join(",\n")).unwrap();
+            writeln!(out, "}}").unwrap();
+        }
+        GrammarFormat::TreeSitter => {
+            let mut rules = vec![];
+            for (open, close, name, _) in labels.iter() {
+                rules.push((*name, label(*open, *close)));
+            }
+            for (name, _, chars) in groups.iter() {
+                rules.push((*name, class(chars.iter().copied(), false)));
+            }
+            rules.push(("comment", comment));
+
+            writeln!(out, "module.exports = grammar({{").unwrap();
+            writeln!(out, "  name: 'bf5d',").unwrap();
+            writeln!(out, "  extras: $ => [],").unwrap();
+            writeln!(out, "  rules: {{").unwrap();
+            let choices = rules
+                .iter()
+                .map(|(name, _)| format!("$.{}", name))
+                .collect::<Vec<_>>();
+            writeln!(
+                out,
+                "    program: $ => repeat(choice({})),",
+                choices.join(", ")
+            )
+            .unwrap();
+            for (name, pattern) in rules.iter() {
+                writeln!(out, "    {}: $ => /{}/,", name, pattern).unwrap();
+            }
+            writeln!(out, "  }},").unwrap();
+            writeln!(out, "}});").unwrap();
+        }
+    }
+    out
+}
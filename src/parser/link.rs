@@ -0,0 +1,180 @@
+// Programs spread over several files, a library and a main say, parsed as the one they make in
+// the order they're given.
+//
+// The engine sees a single source, `SourceMap` knows which file each byte of it came from. Labels
+// are shared, a procedure one file declares can be called from the others, but a loop or a spawn
+// has to close in the file it opens in.
+use std::{fmt, ops::Range};
+
+use super::types::{JumpType, Token};
+
+/// One of the files, `range` being where it ended up in the linked source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    pub name: String,
+    pub range: Range<usize>,
+}
+
+/// A place in one of the files, `line` and `column` count from 1, the column in characters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location<'a> {
+    pub file: &'a str,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// The linked source and where each file is in it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    pub source: String,
+    pub files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    /// `files` as `(name, source)` one after the other, each on lines of its own.
+    pub fn link<'a>(files: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        let mut map = SourceMap::default();
+        for (name, source) in files {
+            let start = map.source.len();
+            map.source.push_str(source);
+            map.files.push(SourceFile {
+                name: name.to_string(),
+                range: start..map.source.len(),
+            });
+            if !source.is_empty() && !source.ends_with('\n') {
+                map.source.push('\n');
+            }
+        }
+        map
+    }
+
+    /// The file byte `offset` of the linked source is in, `None` for the newlines put between
+    /// them and past the end.
+    pub fn file_of(&self, offset: usize) -> Option<&SourceFile> {
+        self.files.iter().find(|file| file.range.contains(&offset))
+    }
+
+    pub fn locate(&self, offset: usize) -> Option<Location<'_>> {
+        let file = self.file_of(offset)?;
+        let before = &self.source[file.range.start..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Some(Location {
+            file: &file.name,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        })
+    }
+
+    /// Every loop and spawn of the linked program closing in the file it opens in, or the first
+    /// one that doesn't.
+    ///
+    /// `tokens` and `spans` are what the linked source parsed into.
+    pub fn check_brackets(
+        &self,
+        tokens: &[Token],
+        spans: &[Range<usize>],
+    ) -> Result<(), LinkError> {
+        for (open, token) in tokens.iter().enumerate() {
+            let close = match *token {
+                Token::Jump {
+                    type_: JumpType::IfZero,
+                    index,
+                }
+                | Token::Spawn { index }
+                | Token::Fork { index }
+                | Token::SpawnIf { index } => index,
+                _ => continue,
+            };
+            let (opens, closes) = match (spans.get(open), spans.get(close)) {
+                (Some(opens), Some(closes)) => (opens.start, closes.start),
+                _ => continue,
+            };
+            let file = |offset| {
+                self.files
+                    .iter()
+                    .position(|file| file.range.contains(&offset))
+            };
+            if file(opens) != file(closes) {
+                return Err(LinkError {
+                    open,
+                    close,
+                    open_at: opens,
+                    close_at: closes,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A loop or spawn opening in one file and closing in another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkError {
+    pub open: usize,
+    pub close: usize,
+    // where the two instructions start in the linked source
+    pub open_at: usize,
+    pub close_at: usize,
+}
+
+impl LinkError {
+    /// What the message says, with the files and where in them.
+    pub fn describe(&self, map: &SourceMap) -> String {
+        let at = |offset| {
+            map.locate(offset).map_or_else(
+                || format!("byte {}", offset),
+                |location| location.to_string(),
+            )
+        };
+        format!(
+            "instruction {} at {} is closed by instruction {} at {}, in another file",
+            self.open,
+            at(self.open_at),
+            self.close,
+            at(self.close_at)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::bf5d::parse_with_spans, *};
+
+    fn at(map: &SourceMap, offset: usize) -> Option<String> {
+        map.locate(offset).map(|location| location.to_string())
+    }
+
+    #[test]
+    fn locates_offsets_in_their_files() {
+        let map = SourceMap::link(vec![("lib.b5", "+\n[-]"), ("main.b5", "é[\n>]\n")]);
+        assert_eq!(map.source, "+\n[-]\né[\n>]\n");
+        assert_eq!(at(&map, 0).as_deref(), Some("lib.b5:1:1"));
+        assert_eq!(at(&map, 3).as_deref(), Some("lib.b5:2:2"));
+        // the newline put after the library
+        assert_eq!(at(&map, 5), None);
+        assert_eq!(at(&map, 6).as_deref(), Some("main.b5:1:1"));
+        // columns count characters, `é` is two bytes
+        assert_eq!(at(&map, 8).as_deref(), Some("main.b5:1:2"));
+        assert_eq!(at(&map, 11).as_deref(), Some("main.b5:2:2"));
+        assert_eq!(at(&map, 12).as_deref(), Some("main.b5:2:3"));
+        assert_eq!(at(&map, 13), None);
+    }
+
+    #[test]
+    fn describes_a_loop_closed_in_another_file() {
+        let map = SourceMap::link(vec![("lib.b5", "+["), ("main.b5", "-]")]);
+        let (tokens, spans) = parse_with_spans(&map.source, Default::default()).unwrap();
+        let error = map.check_brackets(&tokens, &spans).unwrap_err();
+        assert_eq!((error.open_at, error.close_at), (1, 4));
+        assert_eq!(
+            error.describe(&map),
+            "instruction 1 at lib.b5:1:2 is closed by instruction 3 at main.b5:1:2, in another file"
+        );
+    }
+}
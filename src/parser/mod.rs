@@ -1,3 +1,13 @@
+#[cfg(feature = "arbitrary")]
+pub mod arbitrary;
 pub mod bf5d;
-pub mod utils;
+pub mod brackets;
+pub mod grammar;
+pub mod link;
+pub mod stats;
+pub mod tape;
+pub mod termination;
+pub mod timelines;
 pub mod types;
+pub mod utils;
+pub mod validate;
@@ -0,0 +1,111 @@
+// Numbers about a program as written, for golfing and for reviewing programs a tool generated.
+use super::{
+    tape::{self, CellRange},
+    types::{JumpType, MoveDirection, Token, UpdateType},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub instructions: usize,
+    /// How many times each instruction appears, by how it's written, in the order they first
+    /// do.
+    pub counts: Vec<(&'static str, usize)>,
+    /// Loops inside loops, 0 for a program without any.
+    pub max_nesting: usize,
+    // `(`, `Y` and `I`
+    pub spawn_sites: usize,
+    pub await_sites: usize,
+    // `~` and `\`
+    pub rewind_sites: usize,
+    /// The cells all of the timelines together can reach, a tape that size is enough; `Err` is
+    /// the instruction `tape_bounds` couldn't follow.
+    pub tape: Result<CellRange, usize>,
+}
+
+pub fn stats(tokens: &[Token]) -> Stats {
+    let mut counts: Vec<(&'static str, usize)> = vec![];
+    let mut depth = 0;
+    let mut max_nesting = 0;
+    for token in tokens {
+        let symbol = symbol(token);
+        match counts.iter_mut().find(|(written, _)| *written == symbol) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((symbol, 1)),
+        }
+        match token {
+            Token::Jump {
+                type_: JumpType::IfZero,
+                ..
+            } => {
+                depth += 1;
+                max_nesting = max_nesting.max(depth);
+            }
+            Token::Jump {
+                type_: JumpType::IfNotZero,
+                ..
+            } => depth -= 1,
+            _ => (),
+        }
+    }
+    let sites = |matches: fn(&Token) -> bool| tokens.iter().filter(|&t| matches(t)).count();
+
+    Stats {
+        instructions: tokens.len(),
+        counts,
+        max_nesting,
+        spawn_sites: sites(|t| {
+            matches!(
+                t,
+                Token::Spawn { .. } | Token::Fork { .. } | Token::SpawnIf { .. }
+            )
+        }),
+        await_sites: sites(|t| *t == Token::Await),
+        rewind_sites: sites(|t| matches!(t, Token::Rewind | Token::RewindToMarker)),
+        tape: tape::tape_bounds(tokens).map(|bounds| bounds.all()),
+    }
+}
+
+// how each instruction is written, labels by the quotes around them
+fn symbol(token: &Token) -> &'static str {
+    match token {
+        Token::Move(MoveDirection::Left) => "<",
+        Token::Move(MoveDirection::Right) => ">",
+        Token::Move(MoveDirection::Up) => "^",
+        Token::Move(MoveDirection::Down) => "v",
+        Token::Update(UpdateType::Increment) => "+",
+        Token::Update(UpdateType::Decrement) => "-",
+        Token::Jump {
+            type_: JumpType::IfZero,
+            ..
+        } => "[",
+        Token::Jump {
+            type_: JumpType::IfNotZero,
+            ..
+        } => "]",
+        Token::Write => ".",
+        Token::Read => ",",
+        Token::Rewind => "~",
+        Token::Spawn { .. } => "(",
+        Token::Kill => ")",
+        Token::Await => "@",
+        Token::DebugDump => "#",
+        Token::Random => "?",
+        Token::WriteNumber => ":",
+        Token::Goto { .. } => "\"\"",
+        Token::Call { .. } => "{}",
+        Token::Return => ";",
+        Token::RaisePriority => "!",
+        Token::LowerPriority => "_",
+        Token::Sleep => "%",
+        Token::CopyToBelow => "$",
+        Token::CopyFromBelow => "&",
+        Token::SplitPointer => "*",
+        Token::MergePointers => "=",
+        Token::Fork { .. } => "Y",
+        Token::SpawnIf { .. } => "I",
+        Token::Barrier => "|",
+        Token::SetMarker => "`",
+        Token::RewindToMarker => "\\",
+        Token::ResetTape => "0",
+    }
+}
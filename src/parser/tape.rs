@@ -0,0 +1,187 @@
+// Which cells a program can reach, for each kind of timeline it makes.
+//
+// A timeline's pointers all move together and `*` puts the new one on the first one's cell, so
+// without `^` and `v` they're all on one cell. That's tracked as a range through the program, a
+// loop that keeps moving them widens the range to no bound at all.
+use std::fmt;
+
+use super::{
+    timelines,
+    types::{JumpType, MoveDirection, Token},
+};
+
+/// The cells a timeline can have a pointer on, `None` is no bound that way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellRange {
+    pub lowest: Option<isize>,
+    pub highest: Option<isize>,
+}
+
+impl CellRange {
+    pub fn join(self, other: CellRange) -> CellRange {
+        CellRange {
+            lowest: self.lowest.zip(other.lowest).map(|(a, b)| a.min(b)),
+            highest: self.highest.zip(other.highest).map(|(a, b)| a.max(b)),
+        }
+    }
+
+    /// How many cells that is, `None` if there's no bound.
+    pub fn cells(&self) -> Option<usize> {
+        self.lowest
+            .zip(self.highest)
+            .map(|(lowest, highest)| (highest - lowest) as usize + 1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TapeBounds {
+    pub root: CellRange,
+    /// The timelines each `(`, `Y` and `I` makes, by its instruction, in program order.
+    ///
+    /// `$` writes the cells of the timeline below at the writer's pointers, those count for the
+    /// writer here.
+    pub spawned: Vec<(usize, CellRange)>,
+}
+
+impl TapeBounds {
+    /// Every cell any timeline can reach.
+    pub fn all(&self) -> CellRange {
+        self.spawned
+            .iter()
+            .fold(self.root, |all, (_, range)| all.join(*range))
+    }
+}
+
+impl CellRange {
+    fn shift(self, by: isize) -> CellRange {
+        CellRange {
+            lowest: self.lowest.map(|lowest| lowest.saturating_add(by)),
+            highest: self.highest.map(|highest| highest.saturating_add(by)),
+        }
+    }
+
+    // `self` with whatever grew on the way to `next` let go, so loops get to a fixed point
+    fn widen(self, next: CellRange) -> CellRange {
+        CellRange {
+            lowest: match (self.lowest, next.lowest) {
+                (Some(old), Some(new)) if new >= old => Some(old),
+                _ => None,
+            },
+            highest: match (self.highest, next.highest) {
+                (Some(old), Some(new)) if new <= old => Some(old),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// The cells each timeline of a run of `tokens` can reach, or the instruction that's beyond the
+/// analysis: `^` and `v` hand pointers to other timelines, labels and calls jump around loops,
+/// and a bracket closing across another kind isn't nested the way the walk follows them.
+pub fn tape_bounds(tokens: &[Token]) -> Result<TapeBounds, usize> {
+    let unfollowable = tokens.iter().position(|token| {
+        matches!(
+            token,
+            Token::Move(MoveDirection::Up)
+                | Token::Move(MoveDirection::Down)
+                | Token::Goto { .. }
+                | Token::Call { .. }
+        )
+    });
+    if let Some(instruction) = unfollowable.or_else(|| timelines::interleaved(tokens)) {
+        return Err(instruction);
+    }
+
+    let start = CellRange {
+        lowest: Some(0),
+        highest: Some(0),
+    };
+    let mut walk = Walk {
+        tokens,
+        reached: vec![(None, start)],
+    };
+    walk.walk(0, tokens.len(), None, start);
+
+    let mut reached = walk.reached.into_iter();
+    let root = reached.next().map_or(start, |(_, range)| range);
+    let mut spawned = reached
+        .filter_map(|(site, range)| site.map(|site| (site, range)))
+        .collect::<Vec<_>>();
+    spawned.sort_by_key(|(site, _)| *site);
+    Ok(TapeBounds { root, spawned })
+}
+
+struct Walk<'a> {
+    tokens: &'a [Token],
+    // the root's under `None`, the timelines a spawn makes under its instruction
+    reached: Vec<(Option<usize>, CellRange)>,
+}
+
+impl<'a> Walk<'a> {
+    fn reach(&mut self, site: Option<usize>, range: CellRange) {
+        match self.reached.iter_mut().find(|(known, _)| *known == site) {
+            Some((_, reached)) => *reached = reached.join(range),
+            None => self.reached.push((site, range)),
+        }
+    }
+
+    // where the pointers can be after the timelines of `site` ran [start, end) from `pointers`
+    fn walk(
+        &mut self,
+        start: usize,
+        end: usize,
+        site: Option<usize>,
+        entry: CellRange,
+    ) -> CellRange {
+        let mut pointers = entry;
+        let mut i = start;
+        while i < end {
+            match self.tokens[i] {
+                Token::Move(MoveDirection::Left) => pointers = pointers.shift(-1),
+                Token::Move(MoveDirection::Right) => pointers = pointers.shift(1),
+                Token::Jump {
+                    type_: JumpType::IfZero,
+                    index,
+                } => {
+                    // the body runs any number of times, none included
+                    loop {
+                        let after = self.walk(i + 1, index, site, pointers);
+                        let joined = pointers.join(after);
+                        if joined == pointers {
+                            break;
+                        }
+                        pointers = pointers.widen(joined);
+                    }
+                    i = index;
+                }
+                // the child starts where the parent is, the parent goes on as it was, a fork's
+                // children each take one of the pointers but those are all on the same cell
+                Token::Spawn { index } | Token::SpawnIf { index } | Token::Fork { index } => {
+                    self.spawn(i, index, pointers);
+                    i = index;
+                }
+                _ => (),
+            }
+            self.reach(site, pointers);
+            i += 1;
+        }
+        pointers
+    }
+
+    fn spawn(&mut self, site: usize, end: usize, pointers: CellRange) {
+        self.reach(Some(site), pointers);
+        self.walk(site + 1, end, Some(site), pointers);
+    }
+}
+
+impl fmt::Display for CellRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.lowest, self.highest) {
+            (Some(lowest), Some(highest)) if lowest == highest => write!(f, "cell {}", lowest),
+            (Some(lowest), Some(highest)) => write!(f, "cells {} to {}", lowest, highest),
+            (Some(lowest), None) => write!(f, "cells from {} up", lowest),
+            (None, Some(highest)) => write!(f, "cells up to {}", highest),
+            (None, None) => write!(f, "any cell"),
+        }
+    }
+}
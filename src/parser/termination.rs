@@ -0,0 +1,338 @@
+// Static guesses at whether a program stops, right where they can be proven and silent elsewhere.
+use std::{collections::HashMap, fmt};
+
+use super::types::{JumpType, MoveDirection, Token, UpdateType};
+
+/// What could be proven about a whole run. Stopping includes failing, deadlocks too.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Stops,
+    /// The run gets into a loop it can never leave, the `[` at `instruction`.
+    NeverStops {
+        instruction: usize,
+    },
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopVerdict {
+    /// Leaves after at most 256 rounds: the body nets an odd change of the cell it started on.
+    Bounded,
+    /// Never changes its own condition or waits, so it spins forever once entered on a nonzero
+    /// cell.
+    InfiniteIfEntered,
+    /// `InfiniteIfEntered`, and the cell is known to be nonzero when the program gets there.
+    Infinite,
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoopReport {
+    // the `[` and its `]`
+    pub start: usize,
+    pub end: usize,
+    pub verdict: LoopVerdict,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    pub verdict: Verdict,
+    // every loop, nested ones included, by their `[`
+    pub loops: Vec<LoopReport>,
+}
+
+// what running a stretch of instructions once does to the tape, relative to where it started
+#[derive(Debug, Clone, Default)]
+struct Effect {
+    moves: isize,
+    // net change of each cell, `None` for cells it sets to whatever
+    writes: HashMap<isize, Option<i64>>,
+    // `~`, `\` and `0` can put back or zero any cell
+    clobbers_all: bool,
+    // `@` and `|` may end the run in a deadlock instead
+    waits: bool,
+}
+
+impl Effect {
+    fn update(&mut self, delta: i64) {
+        let change = self.writes.entry(self.moves).or_insert(Some(0));
+        *change = change.map(|change| change + delta);
+    }
+
+    fn overwrite(&mut self, offset: isize) {
+        self.writes.insert(offset, None);
+    }
+}
+
+/// Only loops that can be judged on their own, with every timeline keeping a single pointer,
+/// and assuming cells wrap: `OverflowPolicy::Trap` can stop what would spin forever here.
+///
+/// A program with `$`, `^`, `v` or `*` can reach a timeline's cells or add pointers to it from
+/// elsewhere, its loops are never proven either way. Labels and calls jump around the loops,
+/// a program with them is only proven to stop if it has no loops and never jumps back.
+pub fn analyze(tokens: &[Token]) -> Analysis {
+    let crosstalk = tokens.iter().any(|token| {
+        matches!(
+            token,
+            Token::CopyToBelow
+                | Token::Move(MoveDirection::Up)
+                | Token::Move(MoveDirection::Down)
+                | Token::SplitPointer
+        )
+    });
+    let jumps = tokens
+        .iter()
+        .any(|token| matches!(token, Token::Goto { .. } | Token::Call { .. }));
+
+    let mut loops = tokens
+        .iter()
+        .enumerate()
+        .filter_map(|(i, token)| match token {
+            Token::Jump {
+                type_: JumpType::IfZero,
+                index,
+            } => Some(LoopReport {
+                start: i,
+                end: *index,
+                verdict: if crosstalk || jumps {
+                    LoopVerdict::Unknown
+                } else {
+                    loop_verdict(tokens, i, *index)
+                },
+            }),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let verdict = if jumps {
+        let jumps_back = tokens.iter().enumerate().any(|(i, token)| match token {
+            Token::Goto { index } => *index <= i,
+            Token::Call { .. } => true,
+            _ => false,
+        });
+        if loops.is_empty() && !jumps_back {
+            Verdict::Stops
+        } else {
+            Verdict::Unknown
+        }
+    } else if let Some(start) = entered_nonzero(tokens, &loops) {
+        for report in loops.iter_mut().filter(|report| report.start == start) {
+            report.verdict = LoopVerdict::Infinite;
+        }
+        Verdict::NeverStops { instruction: start }
+    } else if !crosstalk && effect(tokens, 0, tokens.len(), false).is_some() {
+        Verdict::Stops
+    } else {
+        Verdict::Unknown
+    };
+
+    Analysis { verdict, loops }
+}
+
+fn loop_verdict(tokens: &[Token], start: usize, end: usize) -> LoopVerdict {
+    let body = match effect(tokens, start + 1, end, true) {
+        Some(body) if body.moves == 0 && !body.clobbers_all => body,
+        _ => return LoopVerdict::Unknown,
+    };
+    match body.writes.get(&0) {
+        None if !body.waits => LoopVerdict::InfiniteIfEntered,
+        Some(Some(change)) if change.rem_euclid(256) == 0 && !body.waits => {
+            LoopVerdict::InfiniteIfEntered
+        }
+        Some(Some(change)) if change % 2 != 0 => LoopVerdict::Bounded,
+        _ => LoopVerdict::Unknown,
+    }
+}
+
+// `None` if something in there can't be told to finish, like a loop that isn't `Bounded`
+fn effect(tokens: &[Token], start: usize, end: usize, in_loop: bool) -> Option<Effect> {
+    let mut total = Effect::default();
+    let mut i = start;
+    while i < end {
+        match tokens[i] {
+            Token::Move(MoveDirection::Left) => total.moves -= 1,
+            Token::Move(MoveDirection::Right) => total.moves += 1,
+            Token::Update(UpdateType::Increment) => total.update(1),
+            Token::Update(UpdateType::Decrement) => total.update(-1),
+            Token::Read | Token::Random | Token::CopyFromBelow => total.overwrite(total.moves),
+            Token::Rewind | Token::RewindToMarker | Token::ResetTape => total.clobbers_all = true,
+            Token::Await | Token::Barrier => total.waits = true,
+            Token::Jump {
+                type_: JumpType::IfZero,
+                index,
+            } => {
+                let body = effect_of_loop(tokens, i, index)?;
+                for offset in body.writes.keys() {
+                    total.overwrite(total.moves + offset);
+                }
+                i = index;
+            }
+            // the child runs the body and dies at the `)`, the parent goes straight there
+            Token::Spawn { index } | Token::Fork { index } | Token::SpawnIf { index } => {
+                effect(tokens, i + 1, index, false)?;
+                i = index;
+            }
+            Token::Goto { .. } | Token::Call { .. } => return None,
+            _ => (),
+        }
+        if in_loop && total.clobbers_all {
+            return None;
+        }
+        i += 1;
+    }
+    Some(total)
+}
+
+fn effect_of_loop(tokens: &[Token], start: usize, end: usize) -> Option<Effect> {
+    if loop_verdict(tokens, start, end) != LoopVerdict::Bounded {
+        return None;
+    }
+    let mut body = effect(tokens, start + 1, end, true)?;
+    // the counter ends up at zero, whatever it started at
+    body.overwrite(0);
+    Some(body)
+}
+
+// the first loop that can't stop and is entered on a cell known to be nonzero, found by running
+// the start of the program for as long as every cell is known
+fn entered_nonzero(tokens: &[Token], loops: &[LoopReport]) -> Option<usize> {
+    let mut cells = HashMap::new();
+    let mut pointer = 0isize;
+    let mut i = 0;
+    while i < tokens.len() {
+        match tokens[i] {
+            Token::Move(MoveDirection::Left) => pointer -= 1,
+            Token::Move(MoveDirection::Right) => pointer += 1,
+            Token::Update(UpdateType::Increment) => {
+                let cell = cells.entry(pointer).or_insert(0u8);
+                *cell = cell.wrapping_add(1);
+            }
+            Token::Update(UpdateType::Decrement) => {
+                let cell = cells.entry(pointer).or_insert(0u8);
+                *cell = cell.wrapping_sub(1);
+            }
+            Token::Jump {
+                type_: JumpType::IfZero,
+                index,
+            } => {
+                if cells.get(&pointer).copied().unwrap_or(0) == 0 {
+                    i = index;
+                } else {
+                    return loops
+                        .iter()
+                        .find(|report| report.start == i)
+                        .filter(|report| report.verdict == LoopVerdict::InfiniteIfEntered)
+                        .map(|report| report.start);
+                }
+            }
+            Token::Write
+            | Token::WriteNumber
+            | Token::DebugDump
+            | Token::RaisePriority
+            | Token::LowerPriority
+            | Token::SetMarker => (),
+            _ => return None,
+        }
+        i += 1;
+    }
+    None
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Stops => write!(f, "always stops"),
+            Verdict::NeverStops { instruction } => {
+                write!(f, "never stops, the loop at {} spins forever", instruction)
+            }
+            Verdict::Unknown => write!(f, "may or may not stop"),
+        }
+    }
+}
+
+impl fmt::Display for LoopVerdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            LoopVerdict::Bounded => "bounded",
+            LoopVerdict::InfiniteIfEntered => "infinite once entered on a nonzero cell",
+            LoopVerdict::Infinite => "infinite",
+            LoopVerdict::Unknown => "unknown",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{bf5d::parse_with, types::Extensions};
+    use super::*;
+
+    fn analysis(source: &str) -> (Verdict, Vec<LoopVerdict>) {
+        let analysis = analyze(&parse_with(source, Extensions::all()).unwrap());
+        let loops = analysis.loops.iter().map(|report| report.verdict).collect();
+        (analysis.verdict, loops)
+    }
+
+    #[test]
+    fn proves_counted_loops_stop() {
+        assert_eq!(
+            analysis("+++[-]"),
+            (Verdict::Stops, vec![LoopVerdict::Bounded])
+        );
+        assert_eq!(
+            analysis(",[>+<-]>[-(+[-])]"),
+            (
+                Verdict::Stops,
+                vec![
+                    LoopVerdict::Bounded,
+                    LoopVerdict::Bounded,
+                    LoopVerdict::Bounded
+                ]
+            )
+        );
+        assert_eq!(analysis("+(-.)+."), (Verdict::Stops, vec![]));
+    }
+
+    #[test]
+    fn proves_loops_entered_on_nonzero_cells_never_stop() {
+        assert_eq!(
+            analysis("+[]"),
+            (
+                Verdict::NeverStops { instruction: 1 },
+                vec![LoopVerdict::Infinite]
+            )
+        );
+        assert_eq!(
+            analysis("[-]+>+[>+<]"),
+            (
+                Verdict::NeverStops { instruction: 6 },
+                vec![LoopVerdict::Bounded, LoopVerdict::Infinite]
+            )
+        );
+    }
+
+    #[test]
+    fn stays_silent_where_it_cant_tell() {
+        assert_eq!(
+            analysis("[]"),
+            (Verdict::Unknown, vec![LoopVerdict::InfiniteIfEntered])
+        );
+        assert_eq!(analysis(",[]"), analysis("[]"));
+        assert_eq!(
+            analysis("+[--]"),
+            (Verdict::Unknown, vec![LoopVerdict::Unknown])
+        );
+        assert_eq!(
+            analysis("+[@]"),
+            (Verdict::Unknown, vec![LoopVerdict::Unknown])
+        );
+        assert_eq!(
+            analysis("+[-]v"),
+            (Verdict::Unknown, vec![LoopVerdict::Unknown])
+        );
+        assert_eq!(
+            analysis("+[-~]"),
+            (Verdict::Unknown, vec![LoopVerdict::Unknown])
+        );
+    }
+}
@@ -0,0 +1,251 @@
+// Static bounds on how many timelines are alive at once, to hold a program against a timeline
+// limit before running it.
+use std::fmt;
+
+use super::{
+    termination::{self, LoopReport, LoopVerdict},
+    types::{JumpType, MoveDirection, Token},
+};
+
+// a loop `termination` calls `Bounded` goes around at most this often
+const MAX_ROUNDS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimelineBound {
+    /// Never more than this many at once, the root included.
+    AtMost(usize),
+    /// No bound was found: `instruction` spawns in a loop the root can go around any number of
+    /// times, or is a `*` in one, adding pointers a fork makes a timeline for each.
+    Unbounded { instruction: usize },
+    /// The jump, the pointer crossing over from another timeline, or the bracket closing across
+    /// another kind at `instruction` is beyond what the analysis follows.
+    Unknown { instruction: usize },
+}
+
+// how many pointers a timeline can have, or why that can't be told
+type Pointers = Result<usize, TimelineBound>;
+
+/// The most timelines a run of `tokens` can have alive at once.
+///
+/// Only the root lives on past the `)` of what it spawns, every other timeline dies there. So a
+/// timeline other than the root spawns once at most, and only a loop run by the root can spawn
+/// over and over, staying finite only if `termination` proves it `Bounded`. Labels and calls
+/// jump out of that structure, `^` and `v` leave the pointer count of forks open, and `[]` and
+/// `()` are matched apart so `( [ ) ]` parses without being nested. A program spawning with any
+/// of those is `Unknown`.
+pub fn max_timelines(tokens: &[Token]) -> TimelineBound {
+    if !tokens.iter().any(is_spawn) {
+        return TimelineBound::AtMost(1);
+    }
+    let jump = tokens
+        .iter()
+        .position(|token| matches!(token, Token::Goto { .. } | Token::Call { .. }));
+    let crossing = tokens.iter().position(|token| {
+        matches!(
+            token,
+            Token::Move(MoveDirection::Up) | Token::Move(MoveDirection::Down)
+        )
+    });
+    let forks = tokens
+        .iter()
+        .any(|token| matches!(token, Token::Fork { .. }));
+    let crossing = crossing.filter(|_| forks);
+    if let Some(instruction) = jump.or(crossing).or_else(|| interleaved(tokens)) {
+        return TimelineBound::Unknown { instruction };
+    }
+
+    let bounds = Bounds {
+        tokens,
+        loops: termination::analyze(tokens).loops,
+    };
+    let pointers = bounds
+        .splits(0, tokens.len())
+        .map(|splits| splits.saturating_add(1));
+    match bounds.spawned_by_root(0, tokens.len(), pointers) {
+        Ok(spawned) => TimelineBound::AtMost(spawned.saturating_add(1)),
+        Err(bound) => bound,
+    }
+}
+
+/// The first `]` or `)` that closes something other than the innermost bracket open there.
+///
+/// The parser matches `[]` and `()` each on their own, so a loop can end inside what it spawned
+/// and a spawn inside a loop; analyses that follow the two as nested give up on those.
+pub fn interleaved(tokens: &[Token]) -> Option<usize> {
+    let mut open = vec![];
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Jump {
+                type_: JumpType::IfZero,
+                ..
+            } => open.push(true),
+            token if is_spawn(token) => open.push(false),
+            Token::Jump {
+                type_: JumpType::IfNotZero,
+                ..
+            }
+            | Token::Kill => {
+                let closes_loop = *token != Token::Kill;
+                if open.pop() != Some(closes_loop) {
+                    return Some(i);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+fn is_spawn(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Spawn { .. } | Token::Fork { .. } | Token::SpawnIf { .. }
+    )
+}
+
+struct Bounds<'a> {
+    tokens: &'a [Token],
+    loops: Vec<LoopReport>,
+}
+
+impl<'a> Bounds<'a> {
+    // `count` for each round of the loop at `start`, blaming `culprit` if there's no end to them
+    fn repeat(&self, start: usize, count: usize, culprit: usize) -> Result<usize, TimelineBound> {
+        let bounded = self
+            .loops
+            .iter()
+            .any(|report| report.start == start && report.verdict == LoopVerdict::Bounded);
+        if bounded {
+            Ok(count.saturating_mul(MAX_ROUNDS))
+        } else {
+            Err(TimelineBound::Unbounded {
+                instruction: culprit,
+            })
+        }
+    }
+
+    // the pointers a timeline running [start, end) can add, what it spawns left to the children
+    fn splits(&self, start: usize, end: usize) -> Pointers {
+        let mut splits = 0usize;
+        let mut i = start;
+        while i < end {
+            match self.tokens[i] {
+                Token::SplitPointer => splits = splits.saturating_add(1),
+                Token::Jump {
+                    type_: JumpType::IfZero,
+                    index,
+                } => {
+                    let body = self.splits(i + 1, index)?;
+                    if body > 0 {
+                        let culprit = (i..index)
+                            .find(|&i| self.tokens[i] == Token::SplitPointer)
+                            .unwrap_or(i);
+                        splits = splits.saturating_add(self.repeat(i, body, culprit)?);
+                    }
+                    i = index;
+                }
+                Token::Spawn { index } | Token::Fork { index } | Token::SpawnIf { index } => {
+                    i = index
+                }
+                _ => (),
+            }
+            i += 1;
+        }
+        Ok(splits)
+    }
+
+    // the timelines alive at once because the root ran [start, end), the root aside
+    fn spawned_by_root(
+        &self,
+        start: usize,
+        end: usize,
+        pointers: Pointers,
+    ) -> Result<usize, TimelineBound> {
+        // what one spawn leaves alive can still be there for all that come after it
+        let mut spawned = 0usize;
+        let mut i = start;
+        while i < end {
+            let more = match self.tokens[i] {
+                Token::Jump {
+                    type_: JumpType::IfZero,
+                    index,
+                } => {
+                    let body = self.spawned_by_root(i + 1, index, pointers)?;
+                    let more = if body > 0 {
+                        let culprit = (i..index).find(|&i| is_spawn(&self.tokens[i])).unwrap_or(i);
+                        self.repeat(i, body, culprit)?
+                    } else {
+                        0
+                    };
+                    i = index;
+                    more
+                }
+                Token::Spawn { index } | Token::SpawnIf { index } => {
+                    let child = self.child(i + 1, index, pointers)?;
+                    i = index;
+                    child
+                }
+                // one child per pointer, each with only that one
+                Token::Fork { index } => {
+                    let child = self.child(i + 1, index, Ok(1))?;
+                    let forked = pointers?.saturating_mul(child);
+                    i = index;
+                    forked
+                }
+                _ => 0,
+            };
+            spawned = spawned.saturating_add(more);
+            i += 1;
+        }
+        Ok(spawned)
+    }
+
+    // the timelines alive at once because a child ran [start, end), the child included
+    //
+    // it goes to the `)` of the first thing it spawns and dies there, whichever that is it's the
+    // only one
+    fn child(&self, start: usize, end: usize, inherited: Pointers) -> Result<usize, TimelineBound> {
+        let pointers = inherited.and_then(|inherited| {
+            self.splits(start, end)
+                .map(|splits| inherited.saturating_add(splits))
+        });
+        let mut peak = 1;
+        let mut i = start;
+        while i < end {
+            match self.tokens[i] {
+                Token::Spawn { index } | Token::SpawnIf { index } => {
+                    // until it gets to the `)`, the spawning timeline is still there too
+                    peak = peak.max(self.child(i + 1, index, pointers)?.saturating_add(1));
+                    i = index;
+                }
+                Token::Fork { index } => {
+                    let child = self.child(i + 1, index, Ok(1))?;
+                    peak = peak.max(pointers?.saturating_mul(child).saturating_add(1));
+                    i = index;
+                }
+                _ => (),
+            }
+            i += 1;
+        }
+        Ok(peak)
+    }
+}
+
+impl fmt::Display for TimelineBound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimelineBound::AtMost(1) => write!(f, "never more than the root timeline"),
+            TimelineBound::AtMost(max) => write!(f, "at most {} timelines at once", max),
+            TimelineBound::Unbounded { instruction } => write!(
+                f,
+                "no bound on the timelines at once, instruction {} can run any number of times",
+                instruction
+            ),
+            TimelineBound::Unknown { instruction } => write!(
+                f,
+                "can't bound the timelines at once, instruction {} is beyond the analysis",
+                instruction
+            ),
+        }
+    }
+}
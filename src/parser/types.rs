@@ -1,3 +1,6 @@
+use std::{fmt, ops::Range, str::FromStr};
+
+use bitflags::bitflags;
 use nom::error::{ErrorKind, FromExternalError, ParseError};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -32,11 +35,125 @@ pub enum Token {
     Spawn { index: usize },                 // '('
     Kill,                                   // ')'
     Await,                                  // '@'
+    DebugDump,                              // '#'
+    Random,                                 // '?'
+    WriteNumber,                            // ':'
+    Goto { index: usize },                  // '"label"'
+    Call { index: usize },                  // '{label}'
+    Return,                                 // ';'
+    RaisePriority,                          // '!'
+    LowerPriority,                          // '_'
+    Sleep,                                  // '%'
+    CopyToBelow,                            // '$'
+    CopyFromBelow,                          // '&'
+    SplitPointer,                           // '*'
+    MergePointers,                          // '='
+    Fork { index: usize },                  // 'Y'
+    SpawnIf { index: usize },               // 'I'
+    Barrier,                                // '|'
+    SetMarker,                              // '`'
+    RewindToMarker,                         // '\\'
+    ResetTape,                              // '0'
 }
+
+bitflags! {
+    /// Non-standard instructions, all of them off unless asked for.
+    ///
+    /// The parser leaves the characters of disabled extensions as comments.
+    #[derive(Default)]
+    pub struct Extensions: u32 {
+        const DEBUG_DUMP = 1 << 0;  // '#'
+        const RANDOM = 1 << 1;      // '?'
+        const NUMERIC_IO = 1 << 2;  // ':'
+        const LABELS = 1 << 3;      // "'label'", '"label"'
+        const PROCEDURES = 1 << 4;  // "'label'", '{label}', ';'
+        const PRIORITY = 1 << 5;    // '!', '_'
+        const SLEEP = 1 << 6;       // '%'
+        const COPY = 1 << 7;        // '$', '&'
+        const SPLIT = 1 << 8;       // '*', '='
+        const FORK = 1 << 9;        // 'Y'
+        const SPAWN_IF = 1 << 10;   // 'I'
+        const BARRIER = 1 << 11;    // '|'
+        const MARKERS = 1 << 12;    // '`', '\\'
+        const RESET = 1 << 13;      // '0'
+    }
+}
+
+impl Extensions {
+    /// The names accepted by `from_str`, e.g. `"random,labels"`.
+    pub const NAMES: &'static [(&'static str, Extensions)] = &[
+        ("debug_dump", Extensions::DEBUG_DUMP),
+        ("random", Extensions::RANDOM),
+        ("numeric_io", Extensions::NUMERIC_IO),
+        ("labels", Extensions::LABELS),
+        ("procedures", Extensions::PROCEDURES),
+        ("priority", Extensions::PRIORITY),
+        ("sleep", Extensions::SLEEP),
+        ("copy", Extensions::COPY),
+        ("split", Extensions::SPLIT),
+        ("fork", Extensions::FORK),
+        ("spawn_if", Extensions::SPAWN_IF),
+        ("barrier", Extensions::BARRIER),
+        ("markers", Extensions::MARKERS),
+        ("reset", Extensions::RESET),
+    ];
+}
+
+impl FromStr for Extensions {
+    type Err = String;
+
+    /// Comma separated names, `all` turns everything on.
+    fn from_str(list: &str) -> Result<Self, Self::Err> {
+        let mut extensions = Extensions::empty();
+        for name in list
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            extensions |= match name {
+                "all" => Extensions::all(),
+                _ => Extensions::NAMES
+                    .iter()
+                    .find(|(known, _)| *known == name)
+                    .map(|(_, extension)| *extension)
+                    .ok_or_else(|| format!("unknown extension {:?}", name))?,
+            };
+        }
+        Ok(extensions)
+    }
+}
+
+impl Token {
+    /// The extension needed to run this token, `None` for standard instructions.
+    pub fn extension(&self) -> Option<Extensions> {
+        use Token::*;
+
+        match self {
+            DebugDump => Some(Extensions::DEBUG_DUMP),
+            Random => Some(Extensions::RANDOM),
+            WriteNumber => Some(Extensions::NUMERIC_IO),
+            Goto { .. } => Some(Extensions::LABELS),
+            Call { .. } | Return => Some(Extensions::PROCEDURES),
+            RaisePriority | LowerPriority => Some(Extensions::PRIORITY),
+            Sleep => Some(Extensions::SLEEP),
+            CopyToBelow | CopyFromBelow => Some(Extensions::COPY),
+            SplitPointer | MergePointers => Some(Extensions::SPLIT),
+            Fork { .. } => Some(Extensions::FORK),
+            SpawnIf { .. } => Some(Extensions::SPAWN_IF),
+            Barrier => Some(Extensions::BARRIER),
+            SetMarker | RewindToMarker => Some(Extensions::MARKERS),
+            ResetTape => Some(Extensions::RESET),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct BF5DParseError {
     message: String,
     location: usize,
+    // the bytes of the source the error is about, when the parser got that far
+    span: Option<Range<usize>>,
 }
 
 impl BF5DParseError {
@@ -44,8 +161,35 @@ impl BF5DParseError {
         BF5DParseError {
             message: message.to_string(),
             location,
+            span: None,
         }
     }
+
+    pub fn with_span(self, span: Range<usize>) -> Self {
+        BF5DParseError {
+            span: Some(span),
+            ..self
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The instruction the error is about.
+    pub fn location(&self) -> usize {
+        self.location
+    }
+
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.span.clone()
+    }
+}
+
+impl fmt::Display for BF5DParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at instruction {}", self.message, self.location)
+    }
 }
 
 impl<I> ParseError<I> for BF5DParseError {
@@ -60,9 +204,6 @@ impl<I> ParseError<I> for BF5DParseError {
 impl<I, E> FromExternalError<I, E> for BF5DParseError {
     /// Create a new error from an input position and an external error
     fn from_external_error(_: I, _: ErrorKind, _e: E) -> Self {
-        BF5DParseError {
-            message: "".to_string(),
-            location: 0,
-        }
+        BF5DParseError::new("", 0)
     }
 }
@@ -0,0 +1,93 @@
+// Checks on token streams that didn't come out of the parser, which never produces these.
+use std::{fmt, ops::Range};
+
+use super::types::{JumpType, Token};
+
+/// Something about an instruction the engine can't run sensibly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub instruction: usize,
+    // where it came from in the source, if the tokens were parsed
+    pub span: Option<Range<usize>>,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at instruction {}", self.message, self.instruction)?;
+        if let Some(span) = &self.span {
+            write!(f, " (bytes {}..{})", span.start, span.end)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every jump, label and spawn whose target is out of range or not its partner, in order.
+///
+/// `spans` may be empty, or shorter than `tokens`, the violations just lack a span then.
+pub fn validate(tokens: &[Token], spans: &[Range<usize>]) -> Vec<Violation> {
+    let mut violations = vec![];
+    let mut violation = |instruction: usize, message: String| {
+        violations.push(Violation {
+            instruction,
+            span: spans.get(instruction).cloned(),
+            message,
+        })
+    };
+    let mut brackets = vec![];
+    let mut parens = vec![];
+
+    for (i, token) in tokens.iter().enumerate() {
+        match *token {
+            // the partners are checked when the walk gets to the `]`
+            Token::Jump {
+                type_: JumpType::IfZero,
+                index,
+            } => {
+                if index >= tokens.len() {
+                    violation(i, format!("jump to {} past the end", index));
+                }
+                brackets.push((i, index));
+            }
+            Token::Jump {
+                type_: JumpType::IfNotZero,
+                index,
+            } => match brackets.pop() {
+                Some((open, target)) if target == i && index == open => (),
+                Some((open, _)) => violation(
+                    i,
+                    format!("jump to {} doesn't pair up with the `[` at {}", index, open),
+                ),
+                None => violation(i, "unmatched bracket".to_string()),
+            },
+            Token::Spawn { index } | Token::Fork { index } | Token::SpawnIf { index } => {
+                if index >= tokens.len() {
+                    violation(i, format!("spawn body ending at {} past the end", index));
+                }
+                parens.push((i, index));
+            }
+            Token::Kill => match parens.pop() {
+                Some((_, end)) if end == i => (),
+                Some((open, end)) => violation(
+                    open,
+                    format!("spawn body ends at {} instead of the `)` at {}", end, i),
+                ),
+                None => violation(i, "unmatched parentheses".to_string()),
+            },
+            // a label at the very end is fine, jumping there ends the timeline
+            Token::Goto { index } | Token::Call { index } if index > tokens.len() => {
+                violation(i, format!("label at {} past the end", index));
+            }
+            _ => (),
+        }
+    }
+
+    for (open, _) in brackets {
+        violation(open, "unmatched bracket".to_string());
+    }
+    for (open, _) in parens {
+        violation(open, "unmatched parentheses".to_string());
+    }
+    violations.sort_by_key(|violation| violation.instruction);
+    violations
+}
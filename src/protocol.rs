@@ -0,0 +1,111 @@
+// The JSON a run is asked for and answered with, the same for the playground server and the
+// daemon.
+//
+// A request has `program`, and optionally `input`, `extensions`, `seed`, `trace` and `limits`
+// (`steps`, `time_ms`, `timelines`, `memory` and `output`). Turning one down comes with the HTTP
+// status it would get, hosts that aren't HTTP pass it on as a field.
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::{
+    interpreter::{
+        bf5d::Engine,
+        error::RuntimeError,
+        outcome::Metrics,
+        sandbox::{Limits, SandboxedRun},
+    },
+    parser::types::{BF5DParseError, Extensions},
+};
+
+pub const CRASHED: &str = "the interpreter crashed on this program";
+
+/// The run a request asks for, or the status and answer turning it down.
+pub fn prepare(body: &Value, ceiling: &Limits) -> Result<SandboxedRun, (u16, Value)> {
+    prepare_with(body, ceiling, |program, extensions| {
+        Engine::from_source_with(program, "", extensions)
+    })
+}
+
+/// Like `prepare`, with `load` turning the program into an engine that hasn't run yet, for hosts
+/// that keep programs they've seen before around.
+pub fn prepare_with(
+    body: &Value,
+    ceiling: &Limits,
+    load: impl FnOnce(&str, Extensions) -> Result<Engine, BF5DParseError>,
+) -> Result<SandboxedRun, (u16, Value)> {
+    let program = body["program"]
+        .as_str()
+        .ok_or_else(|| (400, error("`program` has to be a string")))?;
+    let input = body["input"].as_str().unwrap_or("");
+    let extensions = body["extensions"]
+        .as_str()
+        .unwrap_or("")
+        .parse::<Extensions>()
+        .map_err(|e| (400, error(&e)))?;
+    let mut engine = load(program, extensions).map_err(|e| {
+        let mut answer = json!({
+            "error": e.message(),
+            "instruction": e.location(),
+        });
+        if let Some(span) = e.span() {
+            answer["span"] = json!([span.start, span.end]);
+        }
+        (422, answer)
+    })?;
+    engine.context.program_input = input.to_string();
+
+    // the same request always gets the same answer, timeline ids included
+    engine.make_deterministic(body["seed"].as_u64().unwrap_or(0));
+    // for the deaths in the trace, counted against the memory limit like the live timelines
+    engine.keep_dead_timelines(body["trace"] == true);
+    Ok(SandboxedRun::new(engine, limits(&body["limits"], ceiling)))
+}
+
+/// What a finished run is answered with, the trace aside.
+pub fn report(engine: &Engine, result: &Result<(), RuntimeError>) -> Value {
+    json!({
+        "output": engine.context.program_output,
+        "halted": result.is_ok(),
+        "error": result.as_ref().err().map(|e| e.to_string()),
+        "stats": stats(engine),
+    })
+}
+
+// what the request asked for, never more than `ceiling`
+fn limits(requested: &Value, ceiling: &Limits) -> Limits {
+    let asked = |key: &str| requested[key].as_u64().map(|asked| asked as usize);
+    let min = |key: &str, max: Option<usize>| match (asked(key), max) {
+        (Some(asked), Some(max)) => Some(asked.min(max)),
+        (asked, max) => max.or(asked),
+    };
+    Limits {
+        steps: min("steps", ceiling.steps),
+        time: min(
+            "time_ms",
+            ceiling.time.map(|time| time.as_millis() as usize),
+        )
+        .map(|ms| Duration::from_millis(ms as u64)),
+        timelines: min("timelines", ceiling.timelines),
+        memory: min("memory", ceiling.memory),
+        output: min("output", ceiling.output),
+    }
+}
+
+/// Numbers about a finished run, the same for every backend of the playground.
+pub fn stats(engine: &Engine) -> Value {
+    let metrics = Metrics::of(engine);
+    json!({
+        "steps": metrics.steps,
+        "instructions": metrics.instructions,
+        "timelines": metrics.timelines,
+        "spawned": metrics.spawned,
+        "input_read": metrics.input_read,
+        "warnings": metrics.warnings,
+        "memory": metrics.memory,
+    })
+}
+
+pub fn error(message: &str) -> Value {
+    json!({ "error": message })
+}
@@ -0,0 +1,220 @@
+// An HTTP backend for a web playground, `bf5d-cli serve` with the `server` feature.
+//
+// `tiny_http` reads the requests and a thread per request runs it, it only has to keep up with
+// people pressing run.
+use std::{
+    io::{self, Read},
+    panic::{self, AssertUnwindSafe},
+    thread,
+    time::Duration,
+};
+
+use serde_json::Value;
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+use crate::{
+    interpreter::sandbox::Limits,
+    protocol::{error, prepare, report, CRASHED},
+};
+
+pub mod trace;
+pub mod websocket;
+
+/// What the server allows at most, requests can only ask for less.
+pub const CEILING: Limits = Limits {
+    steps: Some(1_000_000),
+    time: Some(Duration::from_secs(5)),
+    timelines: Some(1024),
+    memory: Some(16 << 20),
+    output: Some(64 << 10),
+};
+
+// bigger requests are turned away before they're read
+const MAX_BODY: usize = 1 << 20;
+
+/// Serves `POST /run`, and the same run streamed over a WebSocket at `/trace`, on `address`
+/// until the process is stopped.
+pub fn serve(address: &str, ceiling: Limits) -> io::Result<()> {
+    let server = Server::http(address).map_err(io::Error::other)?;
+    for request in server.incoming_requests() {
+        let ceiling = ceiling.clone();
+        thread::spawn(move || handle(request, &ceiling));
+    }
+    Ok(())
+}
+
+fn handle(mut request: Request, ceiling: &Limits) -> io::Result<()> {
+    if request.url() == "/trace" && websocket::is_upgrade(&request) {
+        return websocket::stream_trace(request, ceiling);
+    }
+    let length = request.body_length();
+    let (status, body) = match read_body(length, request.as_reader()) {
+        Ok(body) => route(request.method(), request.url(), &body, ceiling),
+        Err(status) => (status, error(status_text(status))),
+    };
+    request.respond(response(status, &body))
+}
+
+/// The value of the header `name` of `request`, if it has one.
+pub fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+// the body, or the status to turn it away with; chunked bodies come without a length
+fn read_body(length: Option<usize>, reader: impl Read) -> Result<String, u16> {
+    if length.is_some_and(|length| length > MAX_BODY) {
+        return Err(413);
+    }
+    let mut body = vec![];
+    reader
+        .take(MAX_BODY as u64 + 1)
+        .read_to_end(&mut body)
+        .map_err(|_| 400u16)?;
+    if body.len() > MAX_BODY {
+        return Err(413);
+    }
+    String::from_utf8(body).map_err(|_| 400)
+}
+
+/// The status and JSON body to answer a request for `path` with.
+pub fn route(method: &Method, path: &str, body: &str, ceiling: &Limits) -> (u16, Value) {
+    match (method, path) {
+        (Method::Post, "/run") => match serde_json::from_str(body) {
+            Ok(body) => run(&body, ceiling),
+            Err(_) => (400, error("the body isn't JSON")),
+        },
+        // preflight for playgrounds hosted somewhere else, the headers do the answering
+        (Method::Options, _) => (204, Value::Null),
+        (_, "/run") => (405, error(status_text(405))),
+        _ => (404, error(status_text(404))),
+    }
+}
+
+/// Runs `program` (with `input`, `extensions`, `seed` and `limits` if given) and reports on it.
+///
+/// `limits` takes `steps`, `time_ms`, `timelines`, `memory` and `output`, each capped by
+/// `ceiling`. `trace` set to `true` adds the events of `trace::events`.
+pub fn run(body: &Value, ceiling: &Limits) -> (u16, Value) {
+    let mut sandbox = match prepare(body, ceiling) {
+        Ok(sandbox) => sandbox,
+        Err(rejected) => return rejected,
+    };
+    let result = match panic::catch_unwind(AssertUnwindSafe(|| sandbox.run())) {
+        Ok(result) => result,
+        Err(_) => return (500, error(CRASHED)),
+    };
+    let engine = &sandbox.engine;
+
+    let mut report = report(engine, &result);
+    if body["trace"] == true {
+        report["trace"] = trace::events(engine, 0..engine.context.steps + 1).into();
+    }
+    (200, report)
+}
+
+fn status_text(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        _ => "Internal Server Error",
+    }
+}
+
+fn response(status: u16, body: &Value) -> Response<io::Cursor<Vec<u8>>> {
+    let body = match body {
+        Value::Null => String::new(),
+        body => body.to_string(),
+    };
+    let headers = [
+        ("Content-Type", "application/json"),
+        ("Access-Control-Allow-Origin", "*"),
+        ("Access-Control-Allow-Methods", "POST, OPTIONS"),
+        ("Access-Control-Allow-Headers", "Content-Type"),
+    ];
+    headers.iter().fold(
+        Response::from_data(body).with_status_code(StatusCode(status)),
+        |response, &(name, value)| {
+            response.with_header(Header::from_bytes(name, value).expect("the headers are ASCII"))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(body: &str, ceiling: &Limits) -> (u16, Value) {
+        route(&Method::Post, "/run", body, ceiling)
+    }
+
+    #[test]
+    fn runs_the_program_posted() {
+        let (status, body) = post(r#"{"program": ",+.", "input": "a"}"#, &CEILING);
+        assert_eq!(status, 200);
+        assert_eq!(body["output"], "b");
+        assert_eq!(body["halted"], true);
+        assert!(body["error"].is_null());
+    }
+
+    #[test]
+    fn turns_away_what_isnt_a_run() {
+        assert_eq!(post(r#"{"program": "+"#, &CEILING).0, 400);
+        assert_eq!(post(r#"{"program": 5}"#, &CEILING).0, 400);
+        assert_eq!(
+            post(r#"{"program": "[", "extensions": "all"}"#, &CEILING).0,
+            422
+        );
+        assert_eq!(route(&Method::Get, "/run", "", &CEILING).0, 405);
+        assert_eq!(route(&Method::Post, "/", "", &CEILING).0, 404);
+        assert_eq!(
+            route(&Method::Options, "/run", "", &CEILING),
+            (204, Value::Null)
+        );
+    }
+
+    #[test]
+    fn limits_are_capped_by_the_ceiling() {
+        let ceiling = Limits {
+            steps: Some(100),
+            ..CEILING
+        };
+        let steps = |body: &str| {
+            let (status, body) = post(body, &ceiling);
+            assert_eq!(status, 200);
+            assert_eq!(body["halted"], false);
+            assert!(body["error"].is_string());
+            body["stats"]["steps"].as_u64()
+        };
+        assert_eq!(
+            steps(r#"{"program": "+[]", "limits": {"steps": 10}}"#),
+            Some(10)
+        );
+        assert_eq!(
+            steps(r#"{"program": "+[]", "limits": {"steps": 1000}}"#),
+            Some(100)
+        );
+        assert_eq!(steps(r#"{"program": "+[]"}"#), Some(100));
+    }
+
+    #[test]
+    fn bodies_too_large_get_413() {
+        let body = vec![b' '; MAX_BODY + 1];
+        assert_eq!(read_body(Some(body.len()), &body[..0]), Err(413));
+        // a chunked body has no length to go by
+        assert_eq!(read_body(None, &body[..]), Err(413));
+        assert_eq!(
+            read_body(None, &body[..MAX_BODY]).map(|body| body.len()),
+            Ok(MAX_BODY)
+        );
+        assert_eq!(read_body(Some(2), &[0xc3, 0x28][..]), Err(400));
+    }
+}
@@ -0,0 +1,181 @@
+// What happened during a run as a flat list of JSON events, the schema clients visualize.
+use std::ops::Range;
+
+use serde_json::{json, Value};
+
+use crate::interpreter::bf5d::Engine;
+
+/// The spawns, reads, writes and warnings of the rounds in `steps`, in the order they happened.
+///
+/// Every event has a `step`, a `type` and the `timeline` it happened in, plus:
+/// - `spawn`: the `parent` it came from
+/// - `death`: the `instruction` it was at, only for timelines the engine kept once dead
+/// - `input`: the `instruction` that read and the `value` it got
+/// - `output`: the `text` written
+/// - `warning`: a `message`
+pub fn events(engine: &Engine, steps: Range<usize>) -> Vec<Value> {
+    gather(engine, &Cursor::default(), steps).0
+}
+
+/// How far into the engine's logs a run's events were handed out, so streaming them reads every
+/// entry once instead of the whole logs each round.
+#[derive(Debug, Clone, Default)]
+pub struct Cursor {
+    births: usize,
+    dead: usize,
+    reads: usize,
+    writes: usize,
+    warnings: usize,
+}
+
+impl Cursor {
+    /// The events of the rounds before `step` that weren't handed out yet, as `events` has them.
+    pub fn events(&mut self, engine: &Engine, step: usize) -> Vec<Value> {
+        let (events, next) = gather(engine, self, 0..step);
+        *self = next;
+        events
+    }
+}
+
+// the events in `steps` among the entries `from` on, and where the entries after `steps` start
+fn gather(engine: &Engine, from: &Cursor, steps: Range<usize>) -> (Vec<Value>, Cursor) {
+    let context = &engine.context;
+    let event = |step: usize, type_: &str, timeline: usize, fields: Value| {
+        let mut event = json!({ "step": step, "type": type_, "timeline": timeline });
+        if let (Some(event), Value::Object(mut fields)) = (event.as_object_mut(), fields) {
+            event.append(&mut fields);
+        }
+        (step, event)
+    };
+
+    let births = before(&context.births, from.births, steps.end, |birth| birth.step);
+    let dead = before(&context.dead, from.dead, steps.end, |timeline| {
+        timeline.died_at_step.unwrap_or(0)
+    });
+    let reads = before(&context.input_log, from.reads, steps.end, |read| read.step);
+    let writes = before(&context.output_log, from.writes, steps.end, |chunk| {
+        chunk.step
+    });
+    let warnings = before(&context.warnings, from.warnings, steps.end, |warning| {
+        warning.step
+    });
+    let next = Cursor {
+        births: from.births + births.len(),
+        dead: from.dead + dead.len(),
+        reads: from.reads + reads.len(),
+        writes: from.writes + writes.len(),
+        warnings: from.warnings + warnings.len(),
+    };
+
+    let mut events = vec![];
+    events.extend(
+        births
+            .iter()
+            .filter(|birth| steps.contains(&birth.step))
+            .map(|birth| {
+                event(
+                    birth.step,
+                    "spawn",
+                    birth.id,
+                    json!({ "parent": birth.parent_id }),
+                )
+            }),
+    );
+    events.extend(
+        dead.iter()
+            .filter_map(|timeline| Some((timeline.died_at_step?, timeline)))
+            .filter(|(step, _)| steps.contains(step))
+            .map(|(step, timeline)| {
+                event(
+                    step,
+                    "death",
+                    timeline.id,
+                    json!({ "instruction": timeline.instruction_pointer }),
+                )
+            }),
+    );
+    events.extend(
+        reads
+            .iter()
+            .filter(|read| steps.contains(&read.step))
+            .map(|read| {
+                event(
+                    read.step,
+                    "input",
+                    read.timeline,
+                    json!({ "instruction": read.instruction, "value": read.value }),
+                )
+            }),
+    );
+    events.extend(
+        writes
+            .iter()
+            .filter(|chunk| steps.contains(&chunk.step))
+            .map(|chunk| {
+                // bytes read as chars, the way `program_output` holds them
+                let text = chunk.bytes.iter().map(|&b| b as char).collect::<String>();
+                event(
+                    chunk.step,
+                    "output",
+                    chunk.timeline,
+                    json!({ "text": text }),
+                )
+            }),
+    );
+    events.extend(
+        warnings
+            .iter()
+            .filter(|warning| steps.contains(&warning.step))
+            .map(|warning| {
+                event(
+                    warning.step,
+                    "warning",
+                    warning.timeline,
+                    json!({ "message": warning.kind.to_string() }),
+                )
+            }),
+    );
+    // stable, so what happened in the same round keeps the order above
+    events.sort_by_key(|(step, _)| *step);
+    (events.into_iter().map(|(_, event)| event).collect(), next)
+}
+
+// the entries of `log` from `from` on that are from before `end`, every log is in step order
+fn before<T>(log: &[T], from: usize, end: usize, step: impl Fn(&T) -> usize) -> &[T] {
+    let log = &log[from..];
+    &log[..log.iter().take_while(|entry| step(entry) < end).count()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::types::Extensions;
+
+    #[test]
+    fn streams_the_same_events() {
+        let source = "~,.(>,.-)+(.)@.";
+        let mut engine = Engine::from_source_with(source, "abc", Extensions::all()).unwrap();
+        engine.keep_dead_timelines(true);
+        let mut cursor = Cursor::default();
+        let mut streamed = vec![];
+        let result = engine.run_while(100, |engine| {
+            streamed.extend(cursor.events(engine, engine.context.steps));
+            true
+        });
+        assert_eq!(result, Ok(true));
+        streamed.extend(cursor.events(&engine, engine.context.steps + 1));
+
+        let events = events(&engine, 0..engine.context.steps + 1);
+        let types = |events: &[Value]| {
+            let mut types = events
+                .iter()
+                .map(|event| event["type"].to_string())
+                .collect::<Vec<_>>();
+            types.sort();
+            types.dedup();
+            types
+        };
+        assert_eq!(types(&events).len(), 5);
+        assert_eq!(streamed, events);
+    }
+}
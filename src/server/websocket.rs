@@ -0,0 +1,206 @@
+// `GET /trace` upgraded to a WebSocket: the run's events are sent as they happen.
+//
+// The client sends one text message, the same JSON `POST /run` takes, and gets one text message
+// per event of `trace::events` back. The last one has `"type": "done"`, the HTTP `status` `/run`
+// would have answered with and the rest of its answer, then the connection is closed.
+use std::{
+    io::{self, Read, Write},
+    panic::{self, AssertUnwindSafe},
+};
+
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, StatusCode};
+use tungstenite::{handshake::derive_accept_key, Message, Role, WebSocket, WebSocketConfig};
+
+use super::{header, trace, MAX_BODY};
+use crate::{
+    interpreter::sandbox::{Limits, SandboxedRun},
+    protocol::{error, prepare, report, CRASHED},
+};
+
+pub fn is_upgrade(request: &Request) -> bool {
+    *request.method() == Method::Get
+        && header(request, "Upgrade")
+            .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+}
+
+/// Finishes the handshake and streams the run the client asks for.
+pub fn stream_trace(request: Request, ceiling: &Limits) -> io::Result<()> {
+    let accept = match header(&request, "Sec-WebSocket-Key") {
+        Some(key) => accept_key(key),
+        None => return request.respond(Response::empty(StatusCode(400))),
+    };
+    let accept = Header::from_bytes("Sec-WebSocket-Accept", accept).expect("the key is base64");
+    // whatever the client sent right after its handshake comes out of this stream first
+    let stream = request.upgrade(
+        "websocket",
+        Response::empty(StatusCode(101)).with_header(accept),
+    );
+    let config = WebSocketConfig {
+        max_message_size: Some(MAX_BODY),
+        ..WebSocketConfig::default()
+    };
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, Some(config));
+    talk(&mut socket, ceiling).map_err(io::Error::other)
+}
+
+/// `Sec-WebSocket-Accept` for a client's `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    derive_accept_key(key.trim().as_bytes())
+}
+
+// one run per connection, pings are answered by the socket itself
+fn talk<S: Read + Write>(socket: &mut WebSocket<S>, ceiling: &Limits) -> tungstenite::Result<()> {
+    let body = loop {
+        match socket.read()? {
+            Message::Text(body) => break body,
+            Message::Close(_) => return socket.flush(),
+            _ => (),
+        }
+    };
+    let (status, answer) = match serde_json::from_str(&body) {
+        Ok(body) => match prepare(&body, ceiling) {
+            Ok(sandbox) => stream_run(socket, sandbox)?,
+            Err(rejected) => rejected,
+        },
+        Err(_) => (400, error("the message isn't JSON")),
+    };
+    let mut done = json!({ "type": "done", "status": status });
+    if let (Some(done), Value::Object(mut rest)) = (done.as_object_mut(), answer) {
+        done.append(&mut rest);
+    }
+    socket.send(Message::text(done.to_string()))?;
+    socket.close(None)?;
+    socket.flush()
+}
+
+// a client that went away stops getting events, the run still ends at its limits
+fn stream_run<S: Read + Write>(
+    socket: &mut WebSocket<S>,
+    mut sandbox: SandboxedRun,
+) -> tungstenite::Result<(u16, Value)> {
+    // for the deaths among the events
+    sandbox.engine.keep_dead_timelines(true);
+    let mut cursor = trace::Cursor::default();
+    let mut failed = None;
+    let mut send = |socket: &mut WebSocket<S>, events: Vec<Value>| {
+        for event in events {
+            if failed.is_none() {
+                failed = socket.send(Message::text(event.to_string())).err();
+            }
+        }
+    };
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        sandbox.run_watched(|engine| {
+            // what a round did is all in once the next one is about to start
+            send(socket, cursor.events(engine, engine.context.steps));
+        })
+    }));
+    let result = match result {
+        Ok(result) => result,
+        Err(_) => return Ok((500, error(CRASHED))),
+    };
+    let engine = &sandbox.engine;
+    send(socket, cursor.events(engine, engine.context.steps + 1));
+    match failed {
+        Some(e) => Err(e),
+        None => Ok((200, report(engine, &result))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::server::CEILING;
+
+    // what the client sent on one side, what the server answered on the other
+    struct Duplex {
+        sent: Cursor<Vec<u8>>,
+        answered: Vec<u8>,
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.sent.read(buf)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.answered.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // the messages a client would get for sending `messages` all at once with its handshake
+    fn exchange(messages: Vec<Message>) -> Vec<Message> {
+        let mut client = WebSocket::from_raw_socket(Cursor::new(Vec::new()), Role::Client, None);
+        for message in messages {
+            client.send(message).unwrap();
+        }
+        let stream = Duplex {
+            sent: Cursor::new(client.get_ref().get_ref().clone()),
+            answered: Vec::new(),
+        };
+        let mut server = WebSocket::from_raw_socket(stream, Role::Server, None);
+        talk(&mut server, &CEILING).unwrap();
+
+        let answered = Cursor::new(server.get_ref().answered.clone());
+        let mut client = WebSocket::from_raw_socket(answered, Role::Client, None);
+        let mut received = Vec::new();
+        while let Ok(message) = client.read() {
+            received.push(message);
+        }
+        received
+    }
+
+    fn text(message: &Message) -> Value {
+        match message {
+            Message::Text(text) => serde_json::from_str(text).unwrap(),
+            other => panic!("{:?} isn't text", other),
+        }
+    }
+
+    #[test]
+    fn accepts_the_rfc_sample_key() {
+        // RFC 6455 section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn messages_sent_with_the_handshake_are_answered() {
+        let received = exchange(vec![
+            Message::Ping(b"hi".to_vec()),
+            Message::text(r#"{"program": ",+.", "input": "a"}"#),
+        ]);
+        assert!(matches!(received.first(), Some(Message::Pong(_))));
+        assert!(matches!(received.last(), Some(Message::Close(_))));
+
+        let done = text(&received[received.len() - 2]);
+        assert_eq!(done["type"], "done");
+        assert_eq!(done["status"], 200);
+        assert_eq!(done["output"], "b");
+        let events = &received[1..received.len() - 2];
+        assert!(!events.is_empty());
+        assert!(events.iter().all(|event| text(event)["type"].is_string()));
+    }
+
+    #[test]
+    fn rejected_runs_still_end_with_done() {
+        let received = exchange(vec![Message::text(r#"{"program": 5}"#)]);
+        assert_eq!(received.len(), 2);
+        let done = text(&received[0]);
+        assert_eq!(done["type"], "done");
+        assert_eq!(done["status"], 400);
+        assert!(done["error"].is_string());
+    }
+}
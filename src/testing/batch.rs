@@ -0,0 +1,506 @@
+// Many programs at once, each with its own input and limits, for grading assignments and corpus
+// regressions.
+//
+// A manifest is TOML: the keys at the top are the defaults, each `[[program]]` is a run.
+//
+//     steps = 100_000
+//     extensions = "labels,procedures"
+//
+//     [[program]]
+//     path = "alice/hello.bf5d"
+//     expected = "Hello, World!\n"
+//
+//     [[program]]
+//     name = "bob, echo"
+//     path = "bob/echo.bf5d"
+//     input_file = "echo.in"
+//     expected_file = "echo.out"
+//     time_ms = 500
+use std::{
+    fmt, fs, io,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    interpreter::{
+        bf5d::Engine,
+        error::RuntimeErrorKind,
+        sandbox::{Limit, Limits, SandboxedRun},
+    },
+    parser::types::Extensions,
+};
+
+/// A `[[program]]` of a manifest, its files read and its paths resolved against the manifest's.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchEntry {
+    pub name: String,
+    pub program: PathBuf,
+    pub input: String,
+    /// What the output has to be to pass, without it halting within the limits is enough.
+    pub expected: Option<String>,
+    pub extensions: Extensions,
+    pub limits: Limits,
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BatchOutcome {
+    Pass,
+    WrongOutput { expected: String },
+    LimitExceeded(Limit),
+    RuntimeError(String),
+    ParseError(String),
+    // the program file couldn't be read
+    Unreadable(String),
+    Crashed,
+}
+
+impl BatchOutcome {
+    /// The outcome's name in reports.
+    pub fn status(&self) -> &'static str {
+        match self {
+            BatchOutcome::Pass => "pass",
+            BatchOutcome::WrongOutput { .. } => "wrong_output",
+            BatchOutcome::LimitExceeded(_) => "limit_exceeded",
+            BatchOutcome::RuntimeError(_) => "runtime_error",
+            BatchOutcome::ParseError(_) => "parse_error",
+            BatchOutcome::Unreadable(_) => "unreadable",
+            BatchOutcome::Crashed => "crashed",
+        }
+    }
+
+    // what went wrong, in words
+    fn error(&self) -> Option<String> {
+        match self {
+            BatchOutcome::Pass => None,
+            BatchOutcome::WrongOutput { .. } => Some("output differs from the expected".into()),
+            BatchOutcome::LimitExceeded(limit) => Some(format!("{} limit exceeded", limit)),
+            BatchOutcome::RuntimeError(e)
+            | BatchOutcome::ParseError(e)
+            | BatchOutcome::Unreadable(e) => Some(e.clone()),
+            BatchOutcome::Crashed => Some("the interpreter crashed".into()),
+        }
+    }
+}
+
+/// Numbers about a run, zero for the ones that never started.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BatchStats {
+    pub steps: usize,
+    pub timelines: usize,
+    pub spawned: usize,
+    pub input_read: usize,
+    pub warnings: usize,
+    pub memory: usize,
+    pub time: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchResult {
+    pub name: String,
+    pub program: PathBuf,
+    pub outcome: BatchOutcome,
+    pub output: String,
+    pub stats: BatchStats,
+}
+
+impl BatchResult {
+    pub fn passed(&self) -> bool {
+        self.outcome == BatchOutcome::Pass
+    }
+}
+
+impl fmt::Display for BatchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.outcome.error() {
+            None => write!(f, "pass {}", self.name),
+            Some(e) => write!(f, "FAIL {}: {}", self.name, e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => Err(format!("unknown report format {:?}", name)),
+        }
+    }
+}
+
+/// The entries of the manifest at `path`, in the order they're listed.
+pub fn load_manifest(path: &Path) -> io::Result<Vec<BatchEntry>> {
+    let invalid = |e: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    };
+    let manifest = fs::read_to_string(path)?
+        .parse::<toml::Table>()
+        .map_err(|e| invalid(e.to_string()))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let programs = match manifest.get("program") {
+        Some(programs) => programs
+            .as_array()
+            .ok_or_else(|| invalid("`program` has to be an array of tables".into()))?
+            .as_slice(),
+        None => &[],
+    };
+    programs
+        .iter()
+        .enumerate()
+        .map(|(i, program)| {
+            entry(program, &manifest, dir).map_err(|e| invalid(format!("program {}: {}", i + 1, e)))
+        })
+        .collect()
+}
+
+// one `[[program]]`, the keys it leaves out taken from the top of the manifest
+fn entry(program: &toml::Value, defaults: &toml::Table, dir: &Path) -> Result<BatchEntry, String> {
+    let lookup = |key: &str| program.get(key).or_else(|| defaults.get(key));
+    let string = |key: &str| match lookup(key) {
+        Some(value) => value
+            .as_str()
+            .map(Some)
+            .ok_or_else(|| format!("`{}` has to be a string", key)),
+        None => Ok(None),
+    };
+    let number = |key: &str| match lookup(key) {
+        Some(value) => value
+            .as_integer()
+            .filter(|n| *n >= 0)
+            .map(|n| Some(n as usize))
+            .ok_or_else(|| format!("`{}` has to be a number", key)),
+        None => Ok(None),
+    };
+    // `key` itself or what's in the file `key_file` names
+    let text = |key: &str| match (string(key)?, string(&format!("{}_file", key))?) {
+        (Some(text), _) => Ok(Some(text.to_string())),
+        (None, Some(file)) => fs::read_to_string(dir.join(file))
+            .map(Some)
+            .map_err(|e| format!("{}: {}", file, e)),
+        (None, None) => Ok(None),
+    };
+
+    let path = string("path")?.ok_or("`path` is missing")?;
+    Ok(BatchEntry {
+        name: string("name")?.unwrap_or(path).to_string(),
+        program: dir.join(path),
+        input: text("input")?.unwrap_or_default(),
+        expected: text("expected")?,
+        extensions: match string("extensions")? {
+            Some(list) => list.parse()?,
+            None => Extensions::default(),
+        },
+        limits: Limits {
+            steps: number("steps")?,
+            time: number("time_ms")?.map(|ms| Duration::from_millis(ms as u64)),
+            timelines: number("timelines")?,
+            memory: number("memory")?,
+            output: number("output")?,
+        },
+        seed: number("seed")?.unwrap_or(0) as u64,
+    })
+}
+
+/// Runs `entry` in a sandbox, a crash only fails this entry.
+pub fn run_entry(entry: &BatchEntry) -> BatchResult {
+    let result = |outcome, output, stats| BatchResult {
+        name: entry.name.clone(),
+        program: entry.program.clone(),
+        outcome,
+        output,
+        stats,
+    };
+    let unstarted = |outcome| result(outcome, String::new(), BatchStats::default());
+
+    let raw_program = match fs::read_to_string(&entry.program) {
+        Ok(raw_program) => raw_program,
+        Err(e) => return unstarted(BatchOutcome::Unreadable(e.to_string())),
+    };
+    let mut engine = match Engine::from_source_with(&raw_program, &entry.input, entry.extensions) {
+        Ok(engine) => engine,
+        Err(e) => return unstarted(BatchOutcome::ParseError(e.to_string())),
+    };
+    // an entry gets the same result whichever worker runs it, next to whichever others
+    engine.make_deterministic(entry.seed);
+
+    let mut sandbox = SandboxedRun::new(engine, entry.limits.clone());
+    let started = Instant::now();
+    let halted = panic::catch_unwind(AssertUnwindSafe(|| sandbox.run()));
+    let time = started.elapsed();
+
+    let engine = sandbox.engine;
+    let context = &engine.context;
+    let stats = BatchStats {
+        steps: context.steps,
+        timelines: engine.timelines.len(),
+        spawned: context.births.len(),
+        input_read: context.input_consumed,
+        warnings: context.warning_count(),
+        memory: engine.timelines.iter().map(|t| t.memory_usage()).sum(),
+        time,
+    };
+    let output = context.program_output.clone();
+    let outcome = match (halted, &entry.expected) {
+        (Err(_), _) => BatchOutcome::Crashed,
+        (Ok(Err(e)), _) => match e.kind {
+            RuntimeErrorKind::LimitExceeded(limit) => BatchOutcome::LimitExceeded(limit),
+            _ => BatchOutcome::RuntimeError(e.to_string()),
+        },
+        (Ok(Ok(())), Some(expected)) if *expected != output => BatchOutcome::WrongOutput {
+            expected: expected.clone(),
+        },
+        (Ok(Ok(())), _) => BatchOutcome::Pass,
+    };
+    result(outcome, output, stats)
+}
+
+/// Runs every entry on `workers` threads, the results in the order of the entries.
+pub fn run_batch(entries: &[BatchEntry], workers: usize) -> Vec<BatchResult> {
+    let entries = Arc::new(entries.to_vec());
+    let next = Arc::new(AtomicUsize::new(0));
+    let (sender, results) = mpsc::channel();
+
+    let workers = (0..workers.max(1).min(entries.len()))
+        .map(|_| {
+            let entries = entries.clone();
+            let next = next.clone();
+            let sender = sender.clone();
+            thread::spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                match entries.get(i) {
+                    Some(entry) => {
+                        if sender.send((i, run_entry(entry))).is_err() {
+                            return;
+                        }
+                    }
+                    None => return,
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(sender);
+
+    let mut results = results.into_iter().collect::<Vec<_>>();
+    for worker in workers {
+        // the runs catch their own panics, a worker can't die halfway
+        worker.join().unwrap();
+    }
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// The results as one document, a summary and an object per entry for JSON, a row each for CSV.
+pub fn report(results: &[BatchResult], format: ReportFormat) -> String {
+    match format {
+        ReportFormat::Json => format!("{}\n", json_report(results)),
+        ReportFormat::Csv => csv_report(results),
+    }
+}
+
+fn json_report(results: &[BatchResult]) -> Value {
+    let passed = results.iter().filter(|r| r.passed()).count();
+    let entries = results
+        .iter()
+        .map(|result| {
+            let stats = &result.stats;
+            let expected = match &result.outcome {
+                BatchOutcome::WrongOutput { expected } => Some(expected),
+                _ => None,
+            };
+            json!({
+                "name": result.name,
+                "program": result.program.display().to_string(),
+                "status": result.outcome.status(),
+                "error": result.outcome.error(),
+                "output": result.output,
+                "expected": expected,
+                "stats": {
+                    "steps": stats.steps,
+                    "timelines": stats.timelines,
+                    "spawned": stats.spawned,
+                    "input_read": stats.input_read,
+                    "warnings": stats.warnings,
+                    "memory": stats.memory,
+                    "time_ms": stats.time.as_millis() as usize,
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "passed": passed,
+        "failed": results.len() - passed,
+        "results": entries,
+    })
+}
+
+const CSV_HEADER: &str =
+    "name,program,status,error,steps,timelines,spawned,input_read,warnings,memory,time_ms,output";
+
+fn csv_report(results: &[BatchResult]) -> String {
+    let mut csv = format!("{}\r\n", CSV_HEADER);
+    for result in results {
+        let stats = &result.stats;
+        let fields = [
+            result.name.clone(),
+            result.program.display().to_string(),
+            result.outcome.status().to_string(),
+            result.outcome.error().unwrap_or_default(),
+            stats.steps.to_string(),
+            stats.timelines.to_string(),
+            stats.spawned.to_string(),
+            stats.input_read.to_string(),
+            stats.warnings.to_string(),
+            stats.memory.to_string(),
+            stats.time.as_millis().to_string(),
+            result.output.clone(),
+        ];
+        let row = fields
+            .iter()
+            .map(|field| csv_field(field))
+            .collect::<Vec<_>>();
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+// RFC 4180, quoted only when it has to be
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    // the entries of `manifest`, written into a directory of its own along with `files`
+    fn load(name: &str, manifest: &str, files: &[(&str, &str)]) -> io::Result<Vec<BatchEntry>> {
+        let dir = env::temp_dir().join(format!("bf5d-batch-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            fs::write(dir.join(file), contents).unwrap();
+        }
+        fs::write(dir.join("batch.toml"), manifest).unwrap();
+        let entries = load_manifest(&dir.join("batch.toml"));
+        fs::remove_dir_all(&dir).unwrap();
+        entries.map(|entries| {
+            // relative to the manifest, whichever directory that was
+            entries
+                .into_iter()
+                .map(|entry| BatchEntry {
+                    program: entry.program.strip_prefix(&dir).unwrap().to_path_buf(),
+                    ..entry
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn reads_every_key() {
+        let manifest = r#"
+            steps = 100_000
+            extensions = "labels,procedures"
+
+            [[program]]
+            path = "alice/hello.bf5d"
+            expected = "Hello, World!\n"
+
+            [[program]]
+            name = "bob, echo"
+            path = "echo.bf5d"
+            input_file = "echo.in"
+            expected_file = "echo.out"
+            extensions = ""
+            steps = 50
+            time_ms = 500
+            timelines = 4
+            memory = 1024
+            output = 16
+            seed = 7
+        "#;
+        let files = [("echo.in", "hi"), ("echo.out", "hi")];
+        let entries = load("keys", manifest, &files).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                BatchEntry {
+                    name: "alice/hello.bf5d".to_string(),
+                    program: PathBuf::from("alice/hello.bf5d"),
+                    input: String::new(),
+                    expected: Some("Hello, World!\n".to_string()),
+                    extensions: Extensions::LABELS | Extensions::PROCEDURES,
+                    limits: Limits {
+                        steps: Some(100_000),
+                        ..Limits::default()
+                    },
+                    seed: 0,
+                },
+                BatchEntry {
+                    name: "bob, echo".to_string(),
+                    program: PathBuf::from("echo.bf5d"),
+                    input: "hi".to_string(),
+                    expected: Some("hi".to_string()),
+                    extensions: Extensions::empty(),
+                    limits: Limits {
+                        steps: Some(50),
+                        time: Some(Duration::from_millis(500)),
+                        timelines: Some(4),
+                        memory: Some(1024),
+                        output: Some(16),
+                    },
+                    seed: 7,
+                },
+            ]
+        );
+        assert_eq!(load("empty", "steps = 5", &[]).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn says_which_program_is_wrong() {
+        let error = |name, manifest| load(name, manifest, &[]).unwrap_err().to_string();
+        assert!(
+            error("path", "[[program]]\nname = \"a\"").ends_with("program 1: `path` is missing")
+        );
+        assert!(error("steps", "steps = -1\n[[program]]\npath = \"a\"")
+            .ends_with("program 1: `steps` has to be a number"));
+        assert!(
+            error("name", "[[program]]\npath = \"a\"\n[[program]]\npath = 5")
+                .ends_with("program 2: `path` has to be a string")
+        );
+        assert!(error(
+            "file",
+            "[[program]]\npath = \"a\"\ninput_file = \"missing.in\""
+        )
+        .contains("program 1: missing.in: "));
+        assert!(error("array", "program = 5").ends_with("`program` has to be an array of tables"));
+        assert!(error("syntax", "steps = ").contains("batch.toml: "));
+    }
+}
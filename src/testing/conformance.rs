@@ -0,0 +1,214 @@
+// The shared definition of what BF5D does, for backends and forks to hold themselves to.
+use std::fmt;
+
+use super::differential::{Backend, RunResult};
+use crate::parser::{bf5d::parse_with, types::Extensions};
+
+/// `conformance/suite.txt`, the format is described at the top of it.
+pub const SUITE: &str = include_str!("../../conformance/suite.txt");
+
+const DEFAULT_STEPS: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConformanceCase {
+    pub name: String,
+    pub program: String,
+    pub input: String,
+    pub extensions: Extensions,
+    pub output: String,
+    pub timelines: usize,
+    pub steps: usize,
+}
+
+/// A case the backend got wrong, `actual` is `None` if the program didn't even parse.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConformanceFailure {
+    pub case: ConformanceCase,
+    pub actual: Option<RunResult>,
+}
+
+/// The cases in `SUITE`.
+pub fn cases() -> Vec<ConformanceCase> {
+    parse_suite(SUITE).expect("the bundled suite parses")
+}
+
+/// Reads a suite in the format of `SUITE`, the error names the line it gave up on.
+pub fn parse_suite(text: &str) -> Result<Vec<ConformanceCase>, String> {
+    let mut cases = vec![];
+    let mut case: Option<ConformanceCase> = None;
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        if line.is_empty() {
+            cases.extend(case.take());
+            continue;
+        }
+
+        let error = |message: &str| format!("line {}: {}", i + 1, message);
+        let (key, value) = line.split_at(line.find(' ').unwrap_or(line.len()));
+        let value = value.trim();
+        if key == "name" {
+            cases.extend(case.take());
+            case = Some(ConformanceCase {
+                name: value.to_string(),
+                program: "".to_string(),
+                input: "".to_string(),
+                extensions: Extensions::empty(),
+                output: "".to_string(),
+                timelines: 1,
+                steps: DEFAULT_STEPS,
+            });
+            continue;
+        }
+
+        let case = case
+            .as_mut()
+            .ok_or_else(|| error("expected a name first"))?;
+        match key {
+            "program" => case.program = unquote(value).ok_or_else(|| error("bad string"))?,
+            "input" => case.input = unquote(value).ok_or_else(|| error("bad string"))?,
+            "output" => case.output = unquote(value).ok_or_else(|| error("bad string"))?,
+            "extensions" => {
+                let names = unquote(value).ok_or_else(|| error("bad string"))?;
+                case.extensions = names.parse().map_err(|e: String| error(&e))?;
+            }
+            "timelines" => case.timelines = value.parse().map_err(|_| error("bad number"))?,
+            "steps" => case.steps = value.parse().map_err(|_| error("bad number"))?,
+            _ => return Err(error(&format!("unknown key {:?}", key))),
+        }
+    }
+    cases.extend(case);
+    Ok(cases)
+}
+
+// the inverse of `{:?}` on strings
+fn unquote(quoted: &str) -> Option<String> {
+    let mut chars = quoted.strip_prefix('"')?.strip_suffix('"')?.chars();
+    let mut unquoted = String::new();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+        unquoted.push(match chars.next()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            'u' => {
+                if chars.next()? != '{' {
+                    return None;
+                }
+                let digits = chars.by_ref().take_while(|c| *c != '}').collect::<String>();
+                std::char::from_u32(u32::from_str_radix(&digits, 16).ok()?)?
+            }
+            c @ ('\\' | '"' | '\'') => c,
+            _ => return None,
+        });
+    }
+    Some(unquoted)
+}
+
+impl ConformanceCase {
+    /// Whether `result` is what the case expects, the timelines only by how many are left.
+    pub fn accepts(&self, result: &RunResult) -> bool {
+        result.halted
+            && result.error.is_none()
+            && result.output == self.output
+            && result.timelines.len() == self.timelines
+    }
+}
+
+/// Every case `backend` gets wrong, none means it conforms; the cases it can't run are left out.
+pub fn run_suite(backend: &dyn Backend, cases: &[ConformanceCase]) -> Vec<ConformanceFailure> {
+    cases
+        .iter()
+        .filter_map(|case| {
+            let tokens = parse_with(&case.program, case.extensions).ok();
+            if tokens.as_ref().is_some_and(|tokens| !backend.runs(tokens)) {
+                return None;
+            }
+            let actual = tokens.map(|tokens| backend.run(&tokens, &case.input, case.steps));
+            match &actual {
+                Some(result) if case.accepts(result) => None,
+                _ => Some(ConformanceFailure {
+                    case: case.clone(),
+                    actual,
+                }),
+            }
+        })
+        .collect()
+}
+
+impl fmt::Display for ConformanceFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let case = &self.case;
+        write!(
+            f,
+            "FAIL {}: expected output {:?} and {} timelines",
+            case.name, case.output, case.timelines
+        )?;
+        match &self.actual {
+            None => write!(f, ", the program doesn't parse"),
+            Some(actual) => {
+                write!(
+                    f,
+                    ", got output {:?} and {} timelines",
+                    actual.output,
+                    actual.timelines.len()
+                )?;
+                if let Some(error) = &actual.error {
+                    write!(f, ", {}", error)?;
+                } else if !actual.halted {
+                    write!(f, ", still running after {} steps", case.steps)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::differential::{self, backends};
+
+    #[test]
+    fn every_backend_conforms() {
+        let cases = cases();
+        assert!(!cases.is_empty());
+        for backend in backends() {
+            let failures = run_suite(backend.as_ref(), &cases);
+            let report = failures.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+            assert!(
+                failures.is_empty(),
+                "{}:\n{}",
+                backend.name(),
+                report.join("\n")
+            );
+        }
+    }
+
+    #[test]
+    fn backends_agree_on_the_suite() {
+        let backends = backends();
+        for case in cases() {
+            let tokens = parse_with(&case.program, case.extensions).unwrap();
+            if let Err(divergence) =
+                differential::compare(&backends, &tokens, &case.input, case.steps)
+            {
+                panic!("{}: {:#?}", case.name, divergence);
+            }
+        }
+    }
+
+    #[test]
+    fn reports_the_line_it_gave_up_on() {
+        assert!(parse_suite("name a\nprogram \"+\"\nbogus 1\n")
+            .unwrap_err()
+            .contains("line 3"));
+    }
+}
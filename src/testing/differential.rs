@@ -0,0 +1,283 @@
+use std::{fs, io, num::Wrapping, path::Path};
+
+use crate::{
+    interpreter::{
+        bf5d::Engine,
+        error::RuntimeError,
+        types::{BF5DContext, Timeline},
+    },
+    optimizer::{
+        ir::{Machine, Program},
+        Pipeline,
+    },
+    parser::{
+        bf5d::parse,
+        types::{Extensions, Token},
+    },
+};
+
+/// The observable result of a run, ids and allocation sizes left out so backends can differ there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunResult {
+    pub output: String,
+    pub halted: bool,
+    pub error: Option<RuntimeError>,
+    pub timelines: Vec<TimelineState>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineState {
+    pub data: Vec<u8>,
+    pub data_backwards: Vec<u8>,
+    pub pointers: Vec<isize>,
+    pub instruction_pointer: usize,
+}
+
+impl From<&Timeline> for TimelineState {
+    fn from(timeline: &Timeline) -> Self {
+        TimelineState {
+            data: trimmed(&timeline.data),
+            data_backwards: trimmed(&timeline.data_backwards),
+            pointers: timeline.pointers.clone(),
+            instruction_pointer: timeline.instruction_pointer,
+        }
+    }
+}
+
+// untouched cells are zero no matter how far a backend allocated
+fn trimmed(data: &[Wrapping<u8>]) -> Vec<u8> {
+    let len = data.iter().rposition(|x| x.0 != 0).map_or(0, |i| i + 1);
+    data[..len].iter().map(|x| x.0).collect()
+}
+
+pub trait Backend {
+    fn name(&self) -> &str;
+
+    /// Whether it can run `tokens` at all, `compare` and `run_suite` leave out those it can't.
+    fn runs(&self, _tokens: &[Token]) -> bool {
+        true
+    }
+
+    /// Whether a step is a round of the reference's, a backend doing more in one is only
+    /// compared on the programs the reference halts in.
+    fn steps_are_rounds(&self) -> bool {
+        true
+    }
+
+    fn run(&self, tokens: &[Token], input: &str, max_steps: usize) -> RunResult;
+}
+
+/// The reference `Engine`, stepping one instruction per timeline at a time.
+pub struct Naive;
+
+impl Backend for Naive {
+    fn name(&self) -> &str {
+        "naive"
+    }
+
+    fn run(&self, tokens: &[Token], input: &str, max_steps: usize) -> RunResult {
+        let mut context = BF5DContext::new();
+        // the parser already left out every extension the caller didn't want
+        context.extensions = Extensions::all();
+        context.set_tokens(tokens.to_vec());
+        context.program_input = input.to_string();
+
+        let mut engine = Engine::new(context);
+        let outcome = engine.run(max_steps);
+
+        RunResult {
+            halted: outcome.halted(),
+            error: outcome.error().cloned(),
+            output: outcome.output,
+            timelines: engine.timelines.iter().map(TimelineState::from).collect(),
+        }
+    }
+}
+
+/// The programs of `<>+-[].,` lowered to `optimizer::ir` and run by its `Machine`, after the
+/// passes of an `--opt-level`.
+pub struct Optimized {
+    pub level: usize,
+    name: String,
+}
+
+impl Optimized {
+    pub fn new(level: usize) -> Self {
+        Optimized {
+            level,
+            name: format!("ir -O{}", level),
+        }
+    }
+}
+
+impl Backend for Optimized {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn runs(&self, tokens: &[Token]) -> bool {
+        Program::lower(tokens).is_ok()
+    }
+
+    // fused ops stand for more than one instruction
+    fn steps_are_rounds(&self) -> bool {
+        false
+    }
+
+    fn run(&self, tokens: &[Token], input: &str, max_steps: usize) -> RunResult {
+        let mut program = Program::lower(tokens).expect("`runs` turns these down");
+        if let Err(e) = Pipeline::level(self.level).run(&mut program) {
+            panic!("{}", e);
+        }
+        let mut machine = Machine::new(&program, input);
+        while !machine.is_halted() && machine.steps < max_steps {
+            machine.step();
+        }
+
+        let (mut data, mut data_backwards) = (vec![], vec![]);
+        for (cell, value) in machine.nonzero_cells() {
+            let (cells, index) = match cell {
+                0.. => (&mut data, cell as usize),
+                _ => (&mut data_backwards, -(cell + 1) as usize),
+            };
+            if cells.len() <= index {
+                cells.resize(index + 1, 0);
+            }
+            cells[index] = value;
+        }
+        let halted = machine.is_halted();
+        RunResult {
+            halted,
+            error: None,
+            timelines: vec![TimelineState {
+                data,
+                data_backwards,
+                pointers: vec![machine.pointer],
+                // the instructions were lined up with the ops only before the passes
+                instruction_pointer: if halted { tokens.len() } else { machine.ip },
+            }],
+            output: machine.output,
+        }
+    }
+}
+
+/// Every backend built into this crate, the first one is the reference.
+pub fn backends() -> Vec<Box<dyn Backend>> {
+    vec![
+        Box::new(Naive),
+        Box::new(Optimized::new(0)),
+        Box::new(Optimized::new(1)),
+        Box::new(Optimized::new(2)),
+    ]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub reference: String,
+    pub backend: String,
+    pub expected: RunResult,
+    pub actual: RunResult,
+}
+
+/// Runs the program through every backend that can run it and compares each against the first.
+pub fn compare(
+    backends: &[Box<dyn Backend>],
+    tokens: &[Token],
+    input: &str,
+    max_steps: usize,
+) -> Result<(), Box<Divergence>> {
+    let (reference, rest) = match backends.split_first() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+
+    let expected = reference.run(tokens, input, max_steps);
+    for backend in rest {
+        if !backend.runs(tokens) || !(backend.steps_are_rounds() || expected.halted) {
+            continue;
+        }
+        let actual = backend.run(tokens, input, max_steps);
+        if actual != expected {
+            return Err(Box::new(Divergence {
+                reference: reference.name().to_string(),
+                backend: backend.name().to_string(),
+                expected,
+                actual,
+            }));
+        }
+    }
+    Ok(())
+}
+
+pub fn assert_equivalent(tokens: &[Token], input: &str, max_steps: usize) {
+    if let Err(divergence) = compare(&backends(), tokens, input, max_steps) {
+        panic!(
+            "{} and {} diverged\nexpected: {:#?}\nactual: {:#?}",
+            divergence.reference, divergence.backend, divergence.expected, divergence.actual
+        );
+    }
+}
+
+/// Corpus entries hold the program, optionally followed by a NUL byte and the program input.
+pub fn split_corpus_entry(bytes: &[u8]) -> (String, String) {
+    let (program, input) = match bytes.iter().position(|&b| b == 0) {
+        Some(nul) => (&bytes[..nul], &bytes[nul + 1..]),
+        None => (bytes, &[][..]),
+    };
+    (
+        String::from_utf8_lossy(program).into_owned(),
+        String::from_utf8_lossy(input).into_owned(),
+    )
+}
+
+/// Checks every entry of a fuzz corpus directory, entries that don't parse are skipped.
+pub fn check_corpus(dir: &Path, max_steps: usize) -> io::Result<Vec<(String, Divergence)>> {
+    let backends = backends();
+    let mut divergences = vec![];
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        let (program, input) = split_corpus_entry(&fs::read(&path)?);
+        if let Ok(tokens) = parse(program.as_str()) {
+            if let Err(divergence) = compare(&backends, &tokens, input.as_str(), max_steps) {
+                divergences.push((path.display().to_string(), *divergence));
+            }
+        }
+    }
+
+    Ok(divergences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::generate::{Generator, GeneratorConfig};
+
+    #[test]
+    fn optimized_backends_agree() {
+        let config = GeneratorConfig {
+            max_len: 40,
+            instructions: "<>+-.,[]".chars().collect(),
+            ..GeneratorConfig::default()
+        };
+        let mut generator = Generator::new(config, 733);
+        for _ in 0..2000 {
+            assert_equivalent(&generator.program(), "ab\u{5}z", 3000);
+        }
+        // a clear loop, offsets and a tape left of the start
+        for source in ["++[-]>+++[<+>-]<.", "<<+[>+<-]>.>,."].iter() {
+            assert_equivalent(&parse(source).unwrap(), "x", 3000);
+        }
+    }
+
+    #[test]
+    fn leaves_out_programs_a_backend_cant_run() {
+        let tokens = parse("+(.)@").unwrap();
+        assert!(!Optimized::new(2).runs(&tokens));
+        assert_eq!(compare(&backends(), &tokens, "", 100), Ok(()));
+    }
+}
@@ -0,0 +1,183 @@
+// Whether two programs, typically one and its optimized or converted self, behave the same.
+use std::{fmt, mem};
+
+use crate::{
+    interpreter::{
+        bf5d::Engine,
+        error::{RuntimeError, RuntimeErrorKind},
+        sandbox::{Limit, Limits, SandboxedRun},
+    },
+    parser::types::{BF5DParseError, Extensions},
+};
+
+/// How one run ended, with everything it wrote.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Behavior {
+    Halted { output: String },
+    LimitExceeded { limit: Limit, output: String },
+    Failed { error: RuntimeError, output: String },
+}
+
+impl Behavior {
+    /// Runs `run` through to how it ends.
+    pub fn of(mut run: SandboxedRun) -> Self {
+        let result = run.run();
+        let output = run.engine.context.program_output;
+        match result {
+            Ok(()) => Behavior::Halted { output },
+            Err(RuntimeError {
+                kind: RuntimeErrorKind::LimitExceeded(limit),
+                ..
+            }) => Behavior::LimitExceeded { limit, output },
+            Err(error) => Behavior::Failed { error, output },
+        }
+    }
+
+    /// Programs that do the same in different ways still agree: any two limits count as the
+    /// same so long as one output leads into the other, and errors only have to be of one kind.
+    pub fn agrees_with(&self, other: &Behavior) -> bool {
+        use Behavior::*;
+
+        match (self, other) {
+            (Halted { output: a }, Halted { output: b }) => a == b,
+            (LimitExceeded { output: a, .. }, LimitExceeded { output: b, .. }) => {
+                a.starts_with(b.as_str()) || b.starts_with(a.as_str())
+            }
+            (
+                Failed {
+                    error: a,
+                    output: output_a,
+                },
+                Failed {
+                    error: b,
+                    output: output_b,
+                },
+            ) => mem::discriminant(&a.kind) == mem::discriminant(&b.kind) && output_a == output_b,
+            _ => false,
+        }
+    }
+}
+
+/// The first input the two programs don't agree on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub input: String,
+    pub left: Behavior,
+    pub right: Behavior,
+}
+
+/// Runs `left` and `right` on every input in turn, stopping at the first one they disagree on.
+pub fn first_divergence<'a>(
+    left: &str,
+    right: &str,
+    extensions: Extensions,
+    inputs: impl IntoIterator<Item = &'a str>,
+    limits: &Limits,
+) -> Result<Option<Divergence>, BF5DParseError> {
+    for input in inputs {
+        let left = Behavior::of(SandboxedRun::new(
+            Engine::from_source_with(left, input, extensions)?,
+            limits.clone(),
+        ));
+        let right = Behavior::of(SandboxedRun::new(
+            Engine::from_source_with(right, input, extensions)?,
+            limits.clone(),
+        ));
+        if !left.agrees_with(&right) {
+            return Ok(Some(Divergence {
+                input: input.to_string(),
+                left,
+                right,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+impl fmt::Display for Behavior {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Behavior::Halted { output } => write!(f, "halted with output {:?}", output),
+            Behavior::LimitExceeded { limit, output } => {
+                write!(f, "hit the {} limit with output {:?}", limit, output)
+            }
+            Behavior::Failed { error, output } => {
+                write!(f, "failed with {}, output {:?}", error, output)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "programs differ on input {:?}\n  left:  {}\n  right: {}",
+            self.input, self.left, self.right
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::types::OverflowPolicy;
+
+    fn divergence(left: &str, right: &str, inputs: &[&str]) -> Option<Divergence> {
+        let limits = Limits {
+            steps: Some(1000),
+            ..Limits::default()
+        };
+        first_divergence(
+            left,
+            right,
+            Extensions::all(),
+            inputs.iter().copied(),
+            &limits,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn finds_the_first_input_they_differ_on() {
+        let divergence = divergence(",.", ",-[+.[-]]", &["a", "\u{1}", "b"]).unwrap();
+        assert_eq!(divergence.input, "\u{1}");
+        assert_eq!(
+            divergence.left,
+            Behavior::Halted {
+                output: "\u{1}".to_string()
+            }
+        );
+        assert_eq!(
+            divergence.right,
+            Behavior::Halted {
+                output: "".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn agrees_on_the_same_behavior_done_differently() {
+        assert_eq!(divergence(",.", ",+-.", &["a", "", "\u{ff}"]), None);
+        assert_eq!(divergence("+[.]", "+[..]", &[""]), None);
+        assert!(divergence("+[.]", "++[.]", &[""]).is_some());
+    }
+
+    #[test]
+    fn agrees_on_errors_of_one_kind() {
+        let trapped = |source| {
+            let mut engine = Engine::from_source(source, "").unwrap();
+            engine.context.overflow = OverflowPolicy::Trap;
+            Behavior::of(SandboxedRun::new(engine, Limits::default()))
+        };
+        assert!(trapped("-").agrees_with(&trapped("+--")));
+        assert!(!trapped("-").agrees_with(&trapped(".-")));
+        assert!(!trapped("-").agrees_with(&trapped("+")));
+    }
+
+    #[test]
+    fn hands_back_parse_errors() {
+        let limits = Limits::default();
+        assert!(first_divergence("[", "", Extensions::all(), vec![""], &limits).is_err());
+    }
+}
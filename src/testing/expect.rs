@@ -0,0 +1,271 @@
+use std::fmt;
+
+use crate::{
+    interpreter::{
+        bf5d::{Engine, TimelineSelector},
+        error::RuntimeError,
+        outcome::ExitReason,
+    },
+    parser::types::Extensions,
+};
+
+// without `expect_halts_within` a run that doesn't halt by then fails as well
+const DEFAULT_MAX_STEPS: usize = 100_000;
+
+/// What a program has to do on a run, checked all at once so a failure lists every mismatch.
+///
+/// ```ignore
+/// RunExpectation::new("+++:")
+///     .extensions(Extensions::NUMERIC_IO)
+///     .expect_output("3 ")
+///     .expect_cell(TimelineSelector::Root, 0, 3)
+///     .expect_halts_within(10)
+///     .assert();
+/// ```
+#[derive(Debug, Clone)]
+pub struct RunExpectation {
+    program: String,
+    input: String,
+    extensions: Extensions,
+    max_steps: Option<usize>,
+    output: Option<String>,
+    cells: Vec<(TimelineSelector, isize, u8)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+    Parse(String),
+    Runtime(RuntimeError),
+    DidNotHalt {
+        steps: usize,
+    },
+    Output {
+        expected: String,
+        actual: String,
+    },
+    // `actual` is `None` when there is no such timeline
+    Cell {
+        timeline: TimelineSelector,
+        index: isize,
+        expected: u8,
+        actual: Option<u8>,
+    },
+}
+
+/// Every way a run didn't meet its `RunExpectation`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpectationFailure {
+    pub program: String,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl RunExpectation {
+    pub fn new(program: &str) -> Self {
+        RunExpectation {
+            program: program.to_string(),
+            input: "".to_string(),
+            extensions: Extensions::default(),
+            max_steps: None,
+            output: None,
+            cells: vec![],
+        }
+    }
+
+    pub fn input(mut self, input: &str) -> Self {
+        self.input = input.to_string();
+        self
+    }
+
+    pub fn extensions(mut self, extensions: Extensions) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    pub fn expect_output(mut self, output: &str) -> Self {
+        self.output = Some(output.to_string());
+        self
+    }
+
+    /// The cell as it is once the run stops.
+    pub fn expect_cell(mut self, timeline: TimelineSelector, index: isize, value: u8) -> Self {
+        self.cells.push((timeline, index, value));
+        self
+    }
+
+    pub fn expect_halts_within(mut self, steps: usize) -> Self {
+        self.max_steps = Some(steps);
+        self
+    }
+
+    /// Runs the program and compares, the engine is handed back either way to look at further.
+    pub fn check(&self) -> Result<Engine, ExpectationFailure> {
+        let mut engine = match Engine::from_source_with(&self.program, &self.input, self.extensions)
+        {
+            Ok(engine) => engine,
+            Err(e) => return Err(self.failure(vec![Mismatch::Parse(format!("{:?}", e))])),
+        };
+
+        let steps = self.max_steps.unwrap_or(DEFAULT_MAX_STEPS);
+        let mut mismatches = vec![];
+        match engine.run(steps).exit {
+            ExitReason::Halted => (),
+            ExitReason::Deadlock(e) | ExitReason::Error(e) => mismatches.push(Mismatch::Runtime(e)),
+            _ => mismatches.push(Mismatch::DidNotHalt { steps }),
+        }
+
+        if let Some(expected) = self.output.as_ref() {
+            if *expected != engine.context.program_output {
+                mismatches.push(Mismatch::Output {
+                    expected: expected.clone(),
+                    actual: engine.context.program_output.clone(),
+                });
+            }
+        }
+        for (timeline, index, expected) in self.cells.iter().copied() {
+            let actual = engine.cell(timeline, index);
+            if actual != Some(expected) {
+                mismatches.push(Mismatch::Cell {
+                    timeline,
+                    index,
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        if mismatches.is_empty() {
+            Ok(engine)
+        } else {
+            Err(self.failure(mismatches))
+        }
+    }
+
+    /// `check`, panicking with the mismatches for use in `#[test]`s.
+    pub fn assert(&self) -> Engine {
+        match self.check() {
+            Ok(engine) => engine,
+            Err(failure) => panic!("{}", failure),
+        }
+    }
+
+    fn failure(&self, mismatches: Vec<Mismatch>) -> ExpectationFailure {
+        ExpectationFailure {
+            program: self.program.clone(),
+            mismatches,
+        }
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Parse(e) => write!(f, "parse error: {}", e),
+            Mismatch::Runtime(e) => write!(f, "runtime error: {}", e),
+            Mismatch::DidNotHalt { steps } => write!(f, "didn't halt within {} steps", steps),
+            Mismatch::Output { expected, actual } => {
+                let at = expected
+                    .chars()
+                    .zip(actual.chars())
+                    .position(|(e, a)| e != a)
+                    .unwrap_or_else(|| expected.chars().count().min(actual.chars().count()));
+                // the caret lines up under the quoted strings, escapes included
+                let prefix = actual.chars().take(at).collect::<String>();
+                let column = format!("{:?}", prefix).chars().count() - 1;
+                write!(
+                    f,
+                    "output differs at char {}\n  expected: {:?}\n  actual:   {:?}\n            {}^",
+                    at,
+                    expected,
+                    actual,
+                    " ".repeat(column)
+                )
+            }
+            Mismatch::Cell {
+                timeline,
+                index,
+                expected,
+                actual: Some(actual),
+            } => write!(
+                f,
+                "cell {} of {:?} is {}, expected {}",
+                index, timeline, actual, expected
+            ),
+            Mismatch::Cell {
+                timeline,
+                index,
+                expected,
+                actual: None,
+            } => write!(
+                f,
+                "no timeline {:?} to read cell {} from, expected {}",
+                timeline, index, expected
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ExpectationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "program {:?} didn't run as expected:", self.program)?;
+        for mismatch in self.mismatches.iter() {
+            write!(f, "\n- {}", mismatch)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ExpectationFailure {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meets_its_expectations() {
+        RunExpectation::new("+++:")
+            .extensions(Extensions::NUMERIC_IO)
+            .expect_output("3 ")
+            .expect_cell(TimelineSelector::Root, 0, 3)
+            .expect_halts_within(10)
+            .assert();
+    }
+
+    #[test]
+    fn lists_every_mismatch() {
+        let failure = RunExpectation::new("+[]")
+            .expect_output("a")
+            .expect_cell(TimelineSelector::Root, 0, 2)
+            .expect_cell(TimelineSelector::Position(1), 0, 0)
+            .expect_halts_within(10)
+            .check()
+            .unwrap_err();
+        assert_eq!(
+            failure.mismatches,
+            vec![
+                Mismatch::DidNotHalt { steps: 10 },
+                Mismatch::Output {
+                    expected: "a".to_string(),
+                    actual: "".to_string(),
+                },
+                Mismatch::Cell {
+                    timeline: TimelineSelector::Root,
+                    index: 0,
+                    expected: 2,
+                    actual: Some(1),
+                },
+                Mismatch::Cell {
+                    timeline: TimelineSelector::Position(1),
+                    index: 0,
+                    expected: 0,
+                    actual: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_on_programs_that_dont_parse() {
+        let failure = RunExpectation::new("[").check().unwrap_err();
+        assert!(matches!(failure.mismatches[..], [Mismatch::Parse(_)]));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{
+    interpreter::{bf5d::Engine, rng::Rng},
+    parser::{
+        bf5d::parse_with,
+        types::{Extensions, Token},
+    },
+};
+
+// each opener is closed by the closer at the same position
+const OPENERS: [char; 4] = ['[', '(', 'Y', 'I'];
+const CLOSERS: [char; 4] = [']', ')', ')', ')'];
+// left out of terminating programs, they could add pointers to a timeline
+const ADD_POINTERS: [char; 3] = ['^', 'v', '*'];
+// left out of terminating loop bodies, they could move onto or change the counter
+const TOUCH_COUNTER: [char; 6] = ['<', '>', '~', '\\', '$', '&'];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorConfig {
+    /// upper bound on the number of instructions
+    pub max_len: usize,
+    /// how deep `[]` and `()` may nest
+    pub max_depth: u32,
+    /// single character instructions to draw from, `[`, `(`, `Y` and `I` bring their closers along
+    pub instructions: Vec<char>,
+    /// only generate programs that halt, see `Generator`
+    pub terminating: bool,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            max_len: 64,
+            max_depth: 4,
+            instructions: "<>^v+-.,~[]()@".chars().collect(),
+            terminating: false,
+        }
+    }
+}
+
+/// Seeded source of valid programs, the same seed and config always give the same programs.
+///
+/// Brackets and parens always balance, labels and procedure calls are never generated.
+///
+/// With `terminating` every loop counts down the cell it starts on, `[->` ... `<]`, and its body
+/// only moves right and back in `>` ... `<` pairs so it never gets to the counter again. Nothing
+/// in a loop body restores or copies over cells either (`~`, `\`, `$`, `&`), and `^`, `v` and
+/// `*` are left out altogether: a second pointer on another counter could keep a loop going
+/// forever. Such a program can still stop on a deadlock or another runtime error instead, and
+/// `%` and `_` in loops can make it take a great many rounds to halt.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    config: GeneratorConfig,
+    rng: Rng,
+}
+
+impl Generator {
+    pub fn new(config: GeneratorConfig, seed: u64) -> Self {
+        Generator {
+            config,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// The extensions the allowed instructions need, parse and run generated programs with these.
+    pub fn extensions(&self) -> Extensions {
+        self.config
+            .instructions
+            .iter()
+            .filter_map(|c| Token::from(*c).ok()?.extension())
+            .fold(Extensions::empty(), |all, extension| all | extension)
+    }
+
+    pub fn source(&mut self) -> String {
+        let len = self.below(self.config.max_len + 1);
+        let mut source = String::new();
+        self.block(&mut source, len, 0, false);
+        source
+    }
+
+    pub fn program(&mut self) -> Vec<Token> {
+        let source = self.source();
+        parse_with(source.as_str(), self.extensions()).expect("generated programs are balanced")
+    }
+
+    pub fn engine(&mut self, program_input: &str) -> Engine {
+        let source = self.source();
+        Engine::from_source_with(source.as_str(), program_input, self.extensions())
+            .expect("generated programs are balanced")
+    }
+
+    // appends up to `budget` characters, all of them balanced
+    fn block(&mut self, source: &mut String, budget: usize, depth: u32, in_loop: bool) {
+        let leaves = self.leaves(in_loop);
+        let openers = OPENERS
+            .iter()
+            .zip(CLOSERS.iter())
+            .filter(|(opener, _)| self.config.instructions.contains(opener))
+            .map(|(opener, closer)| (*opener, *closer))
+            .collect::<Vec<_>>();
+        let pairs = in_loop && self.config.instructions.contains(&'>');
+
+        let end = source.len() + budget;
+        while source.len() < end {
+            let left = end - source.len();
+            let choice = self.below(8);
+            if choice == 0 && depth < self.config.max_depth && !openers.is_empty() {
+                let (opener, closer) = openers[self.below(openers.len())];
+                let counted = self.config.terminating && opener == '[';
+                let (open, close) = if counted {
+                    ("[->".to_string(), "<]".to_string())
+                } else {
+                    (opener.to_string(), closer.to_string())
+                };
+                if left < open.len() + close.len() {
+                    break;
+                }
+                source.push_str(&open);
+                let inner = self.below(left - open.len() - close.len() + 1);
+                self.block(source, inner, depth + 1, in_loop || counted);
+                source.push_str(&close);
+            } else if choice == 1 && pairs && left >= 2 {
+                source.push('>');
+                let inner = self.below(left - 2 + 1);
+                self.block(source, inner, depth, in_loop);
+                source.push('<');
+            } else if leaves.is_empty() {
+                break;
+            } else {
+                source.push(leaves[self.below(leaves.len())]);
+            }
+        }
+    }
+
+    fn leaves(&self, in_loop: bool) -> Vec<char> {
+        self.config
+            .instructions
+            .iter()
+            .copied()
+            .filter(|c| Token::from(*c).is_ok())
+            .filter(|c| !OPENERS.contains(c) && !CLOSERS.contains(c))
+            .filter(|c| !(self.config.terminating && ADD_POINTERS.contains(c)))
+            .filter(|c| !(self.config.terminating && in_loop && TOUCH_COUNTER.contains(c)))
+            .collect()
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        if n == 0 {
+            0
+        } else {
+            (self.rng.next_u64() % n as u64) as usize
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{outcome::ExitReason, sandbox::Limit};
+
+    #[test]
+    fn the_seed_decides_the_programs() {
+        let sources = |seed| {
+            let mut generator = Generator::new(GeneratorConfig::default(), seed);
+            (0..20).map(|_| generator.source()).collect::<Vec<_>>()
+        };
+        assert_eq!(sources(716), sources(716));
+        assert_ne!(sources(716), sources(717));
+    }
+
+    #[test]
+    fn generates_within_the_config() {
+        let config = GeneratorConfig {
+            max_len: 16,
+            max_depth: 1,
+            instructions: "+-[]()YI".chars().collect(),
+            terminating: false,
+        };
+        let mut generator = Generator::new(config, 716);
+        assert_eq!(
+            generator.extensions(),
+            Extensions::FORK | Extensions::SPAWN_IF
+        );
+        for _ in 0..1000 {
+            let source = generator.source();
+            assert!(source.len() <= 16, "{:?}", source);
+            assert!(
+                !source.contains("[[") && !source.contains("(("),
+                "{:?}",
+                source
+            );
+            parse_with(&source, generator.extensions()).unwrap();
+        }
+    }
+
+    // loops don't nest and awaits are left out, either can make a program take a great many
+    // rounds to halt
+    #[test]
+    fn terminating_programs_halt() {
+        let config = GeneratorConfig {
+            max_len: 32,
+            max_depth: 1,
+            instructions: "<>^v*+-.,~[]()$&".chars().collect(),
+            terminating: true,
+        };
+        let mut generator = Generator::new(config, 716);
+        for _ in 0..1000 {
+            let source = generator.source();
+            let mut engine =
+                Engine::from_source_with(&source, "xyz", generator.extensions()).unwrap();
+            let outcome = engine.run(100_000);
+            assert_ne!(
+                outcome.exit,
+                ExitReason::Limit(Limit::Steps),
+                "{:?}",
+                source
+            );
+        }
+    }
+}
@@ -0,0 +1,250 @@
+// A program can start with a header of `//! key value` lines, the keys and values of a batch
+// manifest for the extensions and limits it runs with:
+//
+//     //! extensions labels,procedures
+//     //! steps 500
+//     //! timelines 10
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    interpreter::{
+        bf5d::Engine,
+        error::{RuntimeError, RuntimeErrorKind},
+        sandbox::{Limit, Limits, SandboxedRun},
+    },
+    parser::types::Extensions,
+};
+
+/// A `name.bf5d` program with the expected output in `name.out` and, optionally, input in `name.in`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenCase {
+    pub program: PathBuf,
+    pub input: String,
+    pub expected: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GoldenOutcome {
+    Pass,
+    Fail { expected: String, actual: String },
+    LimitExceeded { limit: Limit, output: String },
+    ParseError(String),
+    RuntimeError { error: String, output: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenResult {
+    pub program: PathBuf,
+    pub outcome: GoldenOutcome,
+}
+
+impl GoldenResult {
+    pub fn passed(&self) -> bool {
+        self.outcome == GoldenOutcome::Pass
+    }
+}
+
+impl fmt::Display for GoldenResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let program = self.program.display();
+        match &self.outcome {
+            GoldenOutcome::Pass => write!(f, "pass {}", program),
+            GoldenOutcome::Fail { expected, actual } => {
+                let at = expected
+                    .chars()
+                    .zip(actual.chars())
+                    .position(|(e, a)| e != a)
+                    .unwrap_or_else(|| expected.chars().count().min(actual.chars().count()));
+                write!(
+                    f,
+                    "FAIL {}: output differs at char {}\n  expected: {:?}\n  actual:   {:?}",
+                    program, at, expected, actual
+                )
+            }
+            GoldenOutcome::LimitExceeded { limit, output } => {
+                write!(
+                    f,
+                    "FAIL {}: {} limit hit, output so far {:?}",
+                    program, limit, output
+                )
+            }
+            GoldenOutcome::ParseError(e) => write!(f, "FAIL {}: {}", program, e),
+            GoldenOutcome::RuntimeError { error, output } => {
+                write!(f, "FAIL {}: {}, output so far {:?}", program, error, output)
+            }
+        }
+    }
+}
+
+/// Finds every `*.bf5d` in `dir` that has a sibling `*.out`, sorted by path.
+pub fn discover(dir: &Path) -> io::Result<Vec<GoldenCase>> {
+    let mut programs = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "bf5d") && path.with_extension("out").is_file()
+        {
+            programs.push(path);
+        }
+    }
+    programs.sort();
+
+    programs
+        .into_iter()
+        .map(|program| {
+            let input_path = program.with_extension("in");
+            let input = if input_path.is_file() {
+                fs::read_to_string(input_path)?
+            } else {
+                "".to_string()
+            };
+            Ok(GoldenCase {
+                expected: fs::read_to_string(program.with_extension("out"))?,
+                input,
+                program,
+            })
+        })
+        .collect()
+}
+
+/// The extensions and limits the header of `raw_program` asks for, the steps `max_steps` unless
+/// it says otherwise.
+pub fn header(raw_program: &str, max_steps: usize) -> Result<(Extensions, Limits), String> {
+    let mut extensions = Extensions::default();
+    let mut limits = Limits {
+        steps: Some(max_steps),
+        ..Limits::default()
+    };
+    let lines = raw_program
+        .lines()
+        .map_while(|line| line.trim().strip_prefix("//!"));
+    for line in lines {
+        let (key, value) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let value = value.trim();
+        let number = || {
+            value
+                .parse::<usize>()
+                .map_err(|_| format!("{} has to be a number, not {:?}", key, value))
+        };
+        match key {
+            "extensions" => extensions = value.parse()?,
+            "steps" => limits.steps = Some(number()?),
+            "time_ms" => limits.time = Some(Duration::from_millis(number()? as u64)),
+            "timelines" => limits.timelines = Some(number()?),
+            "memory" => limits.memory = Some(number()?),
+            "output" => limits.output = Some(number()?),
+            _ => return Err(format!("unknown header key {:?}", key)),
+        }
+    }
+    Ok((extensions, limits))
+}
+
+/// Runs `case` with the extensions and limits of its header, see `header`.
+pub fn run_case(case: &GoldenCase, max_steps: usize) -> io::Result<GoldenResult> {
+    let raw_program = fs::read_to_string(&case.program)?;
+
+    let outcome = match header(&raw_program, max_steps).and_then(|(extensions, limits)| {
+        Engine::from_source_with(&raw_program, &case.input, extensions)
+            .map(|engine| SandboxedRun::new(engine, limits))
+            .map_err(|e| e.to_string())
+    }) {
+        Err(e) => GoldenOutcome::ParseError(e),
+        Ok(mut run) => {
+            let result = run.run();
+            let actual = run.engine.context.program_output.clone();
+            match result {
+                Err(RuntimeError {
+                    kind: RuntimeErrorKind::LimitExceeded(limit),
+                    ..
+                }) => GoldenOutcome::LimitExceeded {
+                    limit,
+                    output: actual,
+                },
+                Err(e) => GoldenOutcome::RuntimeError {
+                    error: e.to_string(),
+                    output: actual,
+                },
+                Ok(()) if actual == case.expected => GoldenOutcome::Pass,
+                Ok(()) => GoldenOutcome::Fail {
+                    expected: case.expected.clone(),
+                    actual,
+                },
+            }
+        }
+    };
+
+    Ok(GoldenResult {
+        program: case.program.clone(),
+        outcome,
+    })
+}
+
+pub fn run_dir(dir: &Path, max_steps: usize) -> io::Result<Vec<GoldenResult>> {
+    discover(dir)?
+        .iter()
+        .map(|case| run_case(case, max_steps))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn reads_the_header() {
+        let (extensions, limits) = header("//! extensions labels\n//! steps 50\n+.", 1000).unwrap();
+        assert_eq!(extensions, Extensions::LABELS);
+        assert_eq!(limits.steps, Some(50));
+        // the header ends at the first other line
+        let (_, limits) = header("+\n//! steps 50", 1000).unwrap();
+        assert_eq!(limits.steps, Some(1000));
+        assert!(header("//! step 50", 1000).is_err());
+        assert!(header("//! steps many", 1000).is_err());
+    }
+
+    #[test]
+    fn runs_the_cases_with_their_headers() {
+        let dir = env::temp_dir().join(format!("bf5d-golden-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cases = [
+            ("a_pass", "//! extensions numeric_io\n+++:", "3 "),
+            ("b_fail", "+++.", "\u{2}"),
+            ("c_limit", "//! steps 10\n+[]", ""),
+            ("d_parse", "[", ""),
+        ];
+        for (name, program, expected) in cases.iter() {
+            fs::write(dir.join(name).with_extension("bf5d"), program).unwrap();
+            fs::write(dir.join(name).with_extension("out"), expected).unwrap();
+        }
+
+        let outcomes = run_dir(&dir, 1000)
+            .unwrap()
+            .into_iter()
+            .map(|result| result.outcome)
+            .collect::<Vec<_>>();
+        fs::remove_dir_all(dir).unwrap();
+        assert_eq!(outcomes[0], GoldenOutcome::Pass);
+        assert_eq!(
+            outcomes[1],
+            GoldenOutcome::Fail {
+                expected: "\u{2}".to_string(),
+                actual: "\u{3}".to_string()
+            }
+        );
+        assert_eq!(
+            outcomes[2],
+            GoldenOutcome::LimitExceeded {
+                limit: Limit::Steps,
+                output: "".to_string()
+            }
+        );
+        match &outcomes[3] {
+            GoldenOutcome::ParseError(e) => assert!(e.ends_with("at instruction 0"), "{}", e),
+            outcome => panic!("{:?}", outcome),
+        }
+    }
+}
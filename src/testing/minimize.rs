@@ -0,0 +1,202 @@
+// Delta debugging for programs that misbehave, down to a reproducer small enough to read.
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::{
+    interpreter::{bf5d::Engine, outcome::ExitReason, sandbox::Limit},
+    parser::{
+        bf5d::{parse_with, parse_with_spans},
+        types::{Extensions, JumpType, Token},
+    },
+};
+
+/// The ways a run can go wrong that `minimize` is usually asked to keep.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    Panic,
+    RuntimeError,
+    StepLimit,
+    /// Halts, with some other output than this.
+    WrongOutput(String),
+}
+
+impl Failure {
+    /// Whether running `source` fails this way, a program that doesn't parse never does.
+    pub fn reproduces(
+        &self,
+        source: &str,
+        program_input: &str,
+        extensions: Extensions,
+        max_steps: usize,
+    ) -> bool {
+        let mut engine = match Engine::from_source_with(source, program_input, extensions) {
+            Ok(engine) => engine,
+            Err(_) => return false,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| engine.run(max_steps)));
+        match (self, result) {
+            (Failure::Panic, result) => result.is_err(),
+            (Failure::RuntimeError, Ok(outcome)) => outcome.error().is_some(),
+            (Failure::StepLimit, Ok(outcome)) => outcome.exit == ExitReason::Limit(Limit::Steps),
+            (Failure::WrongOutput(expected), Ok(outcome)) => {
+                outcome.halted() && outcome.output != *expected
+            }
+            (_, Err(_)) => false,
+        }
+    }
+}
+
+/// The smallest program found that still `fails`, every candidate tried parses with `extensions`.
+///
+/// First whole ranges of characters go, comments included, halving the range size whenever none
+/// can be removed, then every loop and spawn block is tried emptied and unwrapped. Both repeat
+/// until neither removes anything. `fails` should be true for `source` itself.
+pub fn minimize(
+    source: &str,
+    extensions: Extensions,
+    mut fails: impl FnMut(&str) -> bool,
+) -> String {
+    let mut current = source.to_string();
+    let mut candidate_fails =
+        |candidate: &str| parse_with(candidate, extensions).is_ok() && fails(candidate);
+
+    loop {
+        let before = current.len();
+        current = remove_ranges(current, &mut candidate_fails);
+        current = reduce_blocks(current, extensions, &mut candidate_fails);
+        if current.len() == before {
+            return current;
+        }
+    }
+}
+
+// ddmin, only ever keeping the complement of one chunk
+fn remove_ranges(source: String, fails: &mut impl FnMut(&str) -> bool) -> String {
+    let mut chars = source.chars().collect::<Vec<_>>();
+    let mut chunks = 2;
+    while !chars.is_empty() {
+        let size = chars.len().div_ceil(chunks);
+        let mut removed = false;
+        let mut start = 0;
+        while start < chars.len() {
+            let end = (start + size).min(chars.len());
+            let candidate = chars[..start]
+                .iter()
+                .chain(chars[end..].iter())
+                .collect::<String>();
+            if fails(&candidate) {
+                chars.drain(start..end);
+                removed = true;
+            } else {
+                start = end;
+            }
+        }
+
+        if removed {
+            chunks = (chunks - 1).max(2);
+        } else if size == 1 {
+            break;
+        } else {
+            chunks = (chunks * 2).min(chars.len());
+        }
+    }
+    chars.into_iter().collect()
+}
+
+// tries emptying every block, then dropping its brackets, outer blocks first
+fn reduce_blocks(
+    mut source: String,
+    extensions: Extensions,
+    fails: &mut impl FnMut(&str) -> bool,
+) -> String {
+    let mut block = 0;
+    loop {
+        let blocks = match parse_with_spans(&source, extensions) {
+            Ok((tokens, spans)) => tokens
+                .iter()
+                .enumerate()
+                .filter_map(|(i, token)| match token {
+                    Token::Jump {
+                        type_: JumpType::IfZero,
+                        index,
+                    }
+                    | Token::Spawn { index }
+                    | Token::Fork { index }
+                    | Token::SpawnIf { index } => Some((spans[i].clone(), spans[*index].clone())),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            Err(_) => return source,
+        };
+        let (open, close) = match blocks.get(block) {
+            Some(block) => block.clone(),
+            None => return source,
+        };
+
+        let emptied = format!("{}{}", &source[..open.end], &source[close.start..]);
+        let unwrapped = format!(
+            "{}{}{}",
+            &source[..open.start],
+            &source[open.end..close.start],
+            &source[close.end..]
+        );
+        if close.start > open.end && fails(&emptied) {
+            source = emptied;
+        } else if fails(&unwrapped) {
+            // the blocks after it shift down by one
+            source = unwrapped;
+        } else {
+            block += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::types::OverflowPolicy;
+
+    const STEPS: usize = 1000;
+
+    fn minimize_failure(source: &str, failure: Failure) -> String {
+        let extensions = Extensions::all();
+        assert!(failure.reproduces(source, "", extensions, STEPS));
+        minimize(source, extensions, |candidate| {
+            assert!(parse_with(candidate, extensions).is_ok(), "{:?}", candidate);
+            failure.reproduces(candidate, "", extensions, STEPS)
+        })
+    }
+
+    #[test]
+    fn tells_the_failures_apart() {
+        let reproduces =
+            |failure: Failure, source| failure.reproduces(source, "", Extensions::all(), STEPS);
+        let a = "++++++[>++++++++++<-]>+++++.";
+        assert!(reproduces(Failure::StepLimit, "+[]"));
+        assert!(!reproduces(Failure::StepLimit, "[]"));
+        assert!(!reproduces(Failure::RuntimeError, "(v)"));
+        assert!(!reproduces(Failure::RuntimeError, "+[]"));
+        assert!(reproduces(Failure::WrongOutput("B".to_string()), a));
+        assert!(!reproduces(Failure::WrongOutput("A".to_string()), a));
+        assert!(!reproduces(Failure::WrongOutput("A".to_string()), "+[]"));
+        assert!(!reproduces(Failure::RuntimeError, "["));
+    }
+
+    #[test]
+    fn minimizes_to_the_loop_that_never_ends() {
+        let source = "a comment ++>+++[<+>-]<.(>>+[.>]<) [>]++[+[]]";
+        assert_eq!(minimize_failure(source, Failure::StepLimit), "+[]");
+    }
+
+    #[test]
+    fn unwraps_the_blocks_around_it() {
+        // only trapping overflows makes a runtime error of the `-` on a zero cell
+        let traps = |candidate: &str| {
+            let mut engine = Engine::from_source_with(candidate, "", Extensions::all()).unwrap();
+            engine.context.overflow = OverflowPolicy::Trap;
+            engine.run(STEPS).error().is_some()
+        };
+        let source = "++[>+++<-] a block (+[-]<(-)) .";
+        assert!(traps(source));
+        assert_eq!(minimize(source, Extensions::all(), traps), "-");
+    }
+}
@@ -0,0 +1,13 @@
+// helpers for writing tests against the engine
+pub mod batch;
+pub mod conformance;
+pub mod differential;
+pub mod equivalence;
+pub mod expect;
+pub mod generate;
+pub mod golden;
+pub mod minimize;
+pub mod mutation;
+pub mod snapshot;
+#[cfg(feature = "testing")]
+pub mod strategies;
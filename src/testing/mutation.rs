@@ -0,0 +1,307 @@
+// Known good programs broken one token at a time, the engine has to cope with every one of them.
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+};
+
+use super::conformance;
+use crate::{
+    interpreter::{
+        bf5d::Engine,
+        sandbox::{self, Limit, Limits, SandboxedRun},
+        types::BF5DContext,
+    },
+    parser::{
+        bf5d::parse_with,
+        types::{Extensions, JumpType, MoveDirection, Token, UpdateType},
+        validate,
+    },
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mutation {
+    Replace {
+        at: usize,
+        token: Token,
+    },
+    Delete {
+        at: usize,
+    },
+    Duplicate {
+        at: usize,
+    },
+    /// Points the jump, spawn or label at `at` somewhere else.
+    Retarget {
+        at: usize,
+        index: usize,
+    },
+}
+
+impl Mutation {
+    /// The mutant, deleting or doubling a token moves the targets after it along so the rest of
+    /// the program still pairs up.
+    pub fn apply(&self, tokens: &[Token]) -> Vec<Token> {
+        let mut mutant = tokens.to_vec();
+        match *self {
+            Mutation::Replace { at, token } => mutant[at] = token,
+            Mutation::Delete { at } => {
+                mutant.remove(at);
+                shift_targets(
+                    &mut mutant,
+                    |index| if index > at { index - 1 } else { index },
+                );
+            }
+            Mutation::Duplicate { at } => {
+                mutant.insert(at, tokens[at]);
+                shift_targets(
+                    &mut mutant,
+                    |index| if index > at { index + 1 } else { index },
+                );
+            }
+            Mutation::Retarget { at, index } => {
+                if let Some(target) = target_of(&mut mutant[at]) {
+                    *target = index;
+                }
+            }
+        }
+        mutant
+    }
+}
+
+fn shift_targets(tokens: &mut [Token], shift: impl Fn(usize) -> usize) {
+    for token in tokens.iter_mut() {
+        if let Some(target) = target_of(token) {
+            *target = shift(*target);
+        }
+    }
+}
+
+fn target_of(token: &mut Token) -> Option<&mut usize> {
+    match token {
+        Token::Jump { index, .. }
+        | Token::Spawn { index }
+        | Token::Fork { index }
+        | Token::SpawnIf { index }
+        | Token::Goto { index }
+        | Token::Call { index } => Some(index),
+        _ => None,
+    }
+}
+
+// one of each kind of token, the targets are fixed up by `Retarget` mutations
+fn alphabet() -> Vec<Token> {
+    use Token::*;
+
+    vec![
+        Move(MoveDirection::Left),
+        Move(MoveDirection::Right),
+        Move(MoveDirection::Up),
+        Move(MoveDirection::Down),
+        Update(UpdateType::Increment),
+        Update(UpdateType::Decrement),
+        Jump {
+            type_: JumpType::IfZero,
+            index: 0,
+        },
+        Jump {
+            type_: JumpType::IfNotZero,
+            index: 0,
+        },
+        Write,
+        Read,
+        Rewind,
+        Spawn { index: 0 },
+        Kill,
+        Await,
+        DebugDump,
+        Random,
+        WriteNumber,
+        Goto { index: 0 },
+        Call { index: 0 },
+        Return,
+        RaisePriority,
+        LowerPriority,
+        Sleep,
+        CopyToBelow,
+        CopyFromBelow,
+        SplitPointer,
+        MergePointers,
+        Fork { index: 0 },
+        SpawnIf { index: 0 },
+        Barrier,
+        SetMarker,
+        RewindToMarker,
+        ResetTape,
+    ]
+}
+
+/// Every single token mutation of `tokens`: each token replaced by every other kind of token,
+/// deleted and doubled, and each target moved to the start, itself, the end and past it.
+pub fn mutations(tokens: &[Token]) -> Vec<Mutation> {
+    let alphabet = alphabet();
+    let mut mutations = vec![];
+    for (at, token) in tokens.iter().enumerate() {
+        mutations.extend(
+            alphabet
+                .iter()
+                .filter(|replacement| *replacement != token)
+                .map(|replacement| Mutation::Replace {
+                    at,
+                    token: *replacement,
+                }),
+        );
+        mutations.push(Mutation::Delete { at });
+        mutations.push(Mutation::Duplicate { at });
+        let mut token = *token;
+        if target_of(&mut token).is_some() {
+            for index in [0, at, tokens.len(), tokens.len() + 1, usize::MAX].iter() {
+                mutations.push(Mutation::Retarget { at, index: *index });
+            }
+        }
+    }
+    mutations
+}
+
+/// What went wrong running a mutant.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    Panic(String),
+    /// More rounds ran than `Limits::steps` allows.
+    StepsOverLimit(usize),
+    /// Reported as halted while over this limit.
+    HaltedOverLimit(Limit),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MutationFailure {
+    pub mutation: Mutation,
+    pub tokens: Vec<Token>,
+    pub problem: Problem,
+}
+
+/// What `check_mutants` found.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MutationReport {
+    /// Mutants that got to run, those that failed included.
+    pub ran: usize,
+    /// Mutants validation turned down before the first step, they say nothing about the engine.
+    pub rejected: usize,
+    pub failures: Vec<MutationFailure>,
+}
+
+/// Runs every mutant of `tokens` in a sandbox, all extensions on, and collects the problems.
+///
+/// Errors are fine, mutants are mostly nonsense, what isn't is a panic or a run that gets past
+/// `limits`. Panics still go through the panic hook, so expect them on stderr as well.
+pub fn check_mutants(tokens: &[Token], input: &str, limits: &Limits) -> MutationReport {
+    let mut report = MutationReport::default();
+    for mutation in mutations(tokens) {
+        let mutant = mutation.apply(tokens);
+        if !validate::validate(&mutant, &[]).is_empty() {
+            report.rejected += 1;
+            continue;
+        }
+        report.ran += 1;
+        if let Some(problem) = run_mutant(&mutant, input, limits) {
+            report.failures.push(MutationFailure {
+                mutation,
+                tokens: mutant,
+                problem,
+            });
+        }
+    }
+    report
+}
+
+fn run_mutant(tokens: &[Token], input: &str, limits: &Limits) -> Option<Problem> {
+    let mut context = BF5DContext::new();
+    context.extensions = Extensions::all();
+    context.set_tokens(tokens.to_vec());
+    context.program_input = input.to_string();
+    let mut run = SandboxedRun::new(Engine::new(context), limits.clone());
+
+    let result = match panic::catch_unwind(AssertUnwindSafe(|| run.run())) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            return Some(Problem::Panic(message));
+        }
+    };
+
+    let engine = &run.engine;
+    if let Some(max) = limits.steps {
+        if engine.context.steps > max {
+            return Some(Problem::StepsOverLimit(engine.context.steps));
+        }
+    }
+    match result {
+        Ok(()) => sandbox::exceeded(limits, engine).map(Problem::HaltedOverLimit),
+        Err(_) => None,
+    }
+}
+
+/// `check_mutants` of every program in the conformance suite, by case name.
+pub fn check_suite(limits: &Limits) -> Vec<(String, MutationReport)> {
+    conformance::cases()
+        .into_iter()
+        .filter_map(|case| {
+            let tokens = parse_with(&case.program, case.extensions).ok()?;
+            Some((case.name, check_mutants(&tokens, &case.input, limits)))
+        })
+        .collect()
+}
+
+impl fmt::Display for MutationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let problem = match &self.problem {
+            Problem::Panic(message) => format!("panicked: {}", message),
+            Problem::StepsOverLimit(steps) => format!("ran {} steps, over the limit", steps),
+            Problem::HaltedOverLimit(limit) => format!("halted over the {} limit", limit),
+        };
+        write!(
+            f,
+            "{:?} {}\n  tokens: {:?}",
+            self.mutation, problem, self.tokens
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deleting_and_doubling_keep_the_targets() {
+        let tokens = parse_with("+[-(.)]", Extensions::all()).unwrap();
+        for at in 0..tokens.len() {
+            // taking out or doubling one of a pair can't leave the program valid
+            if matches!(
+                tokens[at],
+                Token::Jump { .. } | Token::Spawn { .. } | Token::Kill
+            ) {
+                continue;
+            }
+            for mutation in [Mutation::Delete { at }, Mutation::Duplicate { at }].iter() {
+                let mutant = mutation.apply(&tokens);
+                assert_eq!(validate::validate(&mutant, &[]), vec![], "{:?}", mutation);
+            }
+        }
+    }
+
+    #[test]
+    fn counts_rejected_mutants() {
+        let tokens = parse_with("+[-]", Extensions::all()).unwrap();
+        let limits = Limits {
+            steps: Some(1000),
+            ..Limits::default()
+        };
+        let report = check_mutants(&tokens, "", &limits);
+        assert_eq!(report.failures, vec![]);
+        assert!(report.rejected > 0);
+        assert_eq!(report.ran + report.rejected, mutations(&tokens).len());
+    }
+}
@@ -0,0 +1,230 @@
+// Snapshot tests of the machine state, so changes to the engine's semantics show up as diffs.
+use std::{env, fmt, fs, io, path::Path};
+
+use itertools::Itertools;
+
+use crate::interpreter::bf5d::Engine;
+
+/// Set to anything to overwrite snapshots that differ instead of failing.
+pub const UPDATE_VAR: &str = "BF5D_UPDATE_SNAPSHOTS";
+
+/// The state of `engine` as text that only changes when the observable state does.
+///
+/// Timeline ids are left out, they differ between runs, so timelines go by their position:
+///
+/// ```text
+/// step 4
+/// output "A"
+/// warnings 0
+/// timeline 0
+///   ip 5, pointers [1], priority 0, sleeping 0, suspended false
+///   cells 0: 65, 1: 1
+///   history 4, markers [], call stack []
+/// ```
+pub fn canonical(engine: &Engine) -> String {
+    let mut lines = vec![
+        format!("step {}", engine.context.steps),
+        format!("output {:?}", engine.context.program_output),
+        format!("warnings {}", engine.context.warning_count()),
+    ];
+    for (position, timeline) in engine.timelines.iter().enumerate() {
+        lines.push(format!("timeline {}", position));
+        lines.push(format!(
+            "  ip {}, pointers {:?}, priority {}, sleeping {}, suspended {}",
+            timeline.instruction_pointer,
+            timeline.pointers,
+            timeline.priority,
+            timeline.sleeping,
+            timeline.suspended
+        ));
+        lines.push(format!(
+            "  cells {}",
+            timeline
+                .iter_nonzero_cells()
+                .map(|(i, x)| format!("{}: {}", i, x))
+                .join(", ")
+        ));
+        lines.push(format!(
+            "  history {}, markers {:?}, call stack {:?}",
+            timeline.tape.len(),
+            timeline.markers,
+            timeline.call_stack
+        ));
+    }
+    lines.join("\n") + "\n"
+}
+
+/// A snapshot file that doesn't hold what it should, the new text is in `path.new`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotMismatch {
+    pub path: String,
+    // `None` for a snapshot that didn't exist yet
+    pub expected: Option<String>,
+    pub actual: String,
+}
+
+/// Compares `actual` with the snapshot at `path`.
+///
+/// A missing or different snapshot is written to `path.new` to look at and rename, or straight
+/// to `path` when `UPDATE_VAR` is set, which counts as a match.
+pub fn check_snapshot(path: &Path, actual: &str) -> io::Result<Result<(), SnapshotMismatch>> {
+    let expected = match fs::read_to_string(path) {
+        Ok(expected) => Some(expected),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    if expected.as_deref() == Some(actual) {
+        return Ok(Ok(()));
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if env::var_os(UPDATE_VAR).is_some() {
+        fs::write(path, actual)?;
+        return Ok(Ok(()));
+    }
+    let mut new = path.as_os_str().to_owned();
+    new.push(".new");
+    fs::write(new, actual)?;
+    Ok(Err(SnapshotMismatch {
+        path: path.display().to_string(),
+        expected,
+        actual: actual.to_string(),
+    }))
+}
+
+/// `check_snapshot` of `canonical(engine)`, panicking with a diff for use in `#[test]`s.
+pub fn assert_snapshot(path: &Path, engine: &Engine) {
+    match check_snapshot(path, &canonical(engine)) {
+        Ok(Ok(())) => (),
+        Ok(Err(mismatch)) => panic!("{}", mismatch),
+        Err(e) => panic!("can't check snapshot {}: {}", path.display(), e),
+    }
+}
+
+impl fmt::Display for SnapshotMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let expected = match &self.expected {
+            Some(expected) => expected,
+            None => {
+                write!(
+                    f,
+                    "new snapshot {}, written to {}.new",
+                    self.path, self.path
+                )?;
+                return Ok(());
+            }
+        };
+        write!(
+            f,
+            "snapshot {} differs, the new one is in {}.new",
+            self.path, self.path
+        )?;
+        for line in diff_lines(expected, &self.actual) {
+            write!(f, "\n{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+// `-` and `+` for lines only in `old` or `new`, over their longest common subsequence
+fn diff_lines(old: &str, new: &str) -> Vec<String> {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+
+    // common[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = vec![];
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(format!("  {}", old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(format!("- {}", old[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::types::Extensions;
+
+    fn engine(source: &str, max_steps: usize) -> Engine {
+        let mut engine = Engine::from_source_with(source, "", Extensions::all()).unwrap();
+        engine.run(max_steps);
+        engine
+    }
+
+    #[test]
+    fn writes_the_state_out() {
+        let expected = "\
+step 5
+output \"\"
+warnings 0
+timeline 0
+  ip 8, pointers [1], priority 0, sleeping 0, suspended false
+  cells 0: 1, 1: 1
+  history 0, markers [], call stack []
+timeline 1
+  ip 5, pointers [1], priority 0, sleeping 0, suspended false
+  cells 0: 1, 1: 2
+  history 0, markers [], call stack []
+";
+        assert_eq!(canonical(&engine("+(>++)>+++.", 5)), expected);
+    }
+
+    #[test]
+    fn leaves_out_the_timeline_ids() {
+        let mut engine = engine("+(>+)(>+)+", 10);
+        let before = canonical(&engine);
+        for (id, timeline) in engine.timelines.iter_mut().enumerate() {
+            timeline.id = 100 + id;
+        }
+        assert_eq!(canonical(&engine), before);
+    }
+
+    #[test]
+    fn checks_the_snapshot_file() {
+        let dir = env::temp_dir().join(format!("bf5d-snapshot-{}", std::process::id()));
+        let path = dir.join("state.snap");
+        let new = dir.join("state.snap.new");
+
+        let mismatch = check_snapshot(&path, "step 1\n").unwrap().unwrap_err();
+        assert_eq!(mismatch.expected, None);
+        assert_eq!(fs::read_to_string(&new).unwrap(), "step 1\n");
+
+        fs::rename(&new, &path).unwrap();
+        assert_eq!(check_snapshot(&path, "step 1\n").unwrap(), Ok(()));
+        let mismatch = check_snapshot(&path, "step 2\n").unwrap().unwrap_err();
+        assert_eq!(mismatch.expected.as_deref(), Some("step 1\n"));
+        assert_eq!(fs::read_to_string(&new).unwrap(), "step 2\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn diffs_by_line() {
+        assert_eq!(
+            diff_lines("a\nb\nc\n", "a\nx\nc\nd\n"),
+            vec!["  a", "- b", "+ x", "  c", "+ d"]
+        );
+    }
+}
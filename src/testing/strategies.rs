@@ -0,0 +1,139 @@
+use proptest::prelude::*;
+
+use crate::parser::{bf5d::parse, types::Token};
+
+const INSTRUCTIONS: [char; 7] = ['<', '>', '+', '-', '.', ',', '~'];
+const TIMELINE_INSTRUCTIONS: [char; 3] = ['^', 'v', '@'];
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramConfig {
+    /// upper bound on the number of instructions
+    pub max_len: usize,
+    /// how deep `[]` and `()` may nest
+    pub max_depth: u32,
+    /// whether to generate `(`, `)`, `^`, `v` and `@`
+    pub timeline_ops: bool,
+}
+
+impl Default for ProgramConfig {
+    fn default() -> Self {
+        ProgramConfig {
+            max_len: 64,
+            max_depth: 4,
+            timeline_ops: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Part {
+    Instruction(char),
+    Open(char, char),
+    Close,
+}
+
+/// Balanced BF5D source, shrinking removes instructions and flattens loops.
+///
+/// The source is laid out from a flat list of parts: an opener only goes in while the pair still
+/// fits in `max_len` and the nesting is under `max_depth`, a stray close is dropped and whatever
+/// is left open gets closed at the end, so no generated program needs filtering out.
+pub fn program_source(config: ProgramConfig) -> impl Strategy<Value = String> {
+    let mut alphabet = INSTRUCTIONS.to_vec();
+    let mut pairs = vec![('[', ']')];
+    if config.timeline_ops {
+        alphabet.extend_from_slice(&TIMELINE_INSTRUCTIONS);
+        pairs.push(('(', ')'));
+    }
+
+    // instructions come first so that shrinking turns openers and closes into them
+    let part = prop_oneof![
+        prop::sample::select(alphabet).prop_map(Part::Instruction),
+        prop::sample::select(pairs).prop_map(|(open, close)| Part::Open(open, close)),
+        Just(Part::Close),
+    ];
+
+    prop::collection::vec(part, 0..=config.max_len).prop_map(move |parts| lay_out(&parts, config))
+}
+
+fn lay_out(parts: &[Part], config: ProgramConfig) -> String {
+    let mut source = String::new();
+    // closing characters still owed, innermost last
+    let mut closers = Vec::new();
+
+    for part in parts {
+        let len = source.len() + closers.len();
+        match *part {
+            Part::Instruction(c) if len < config.max_len => source.push(c),
+            Part::Open(open, close)
+                if len + 2 <= config.max_len && closers.len() < config.max_depth as usize =>
+            {
+                source.push(open);
+                closers.push(close);
+            }
+            Part::Close => source.extend(closers.pop()),
+            _ => (),
+        }
+    }
+    source.extend(closers.into_iter().rev());
+    source
+}
+
+pub fn program(config: ProgramConfig) -> impl Strategy<Value = Vec<Token>> {
+    program_source(config)
+        .prop_map(|source| parse(source.as_str()).expect("generated programs are balanced"))
+}
+
+pub fn program_input() -> impl Strategy<Value = String> {
+    "[ -~]{0,16}"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depth(source: &str) -> u32 {
+        let (mut depth, mut deepest) = (0, 0);
+        for c in source.chars() {
+            match c {
+                '[' | '(' => {
+                    depth += 1;
+                    deepest = deepest.max(depth);
+                }
+                ']' | ')' => depth -= 1,
+                _ => (),
+            }
+        }
+        deepest
+    }
+
+    proptest! {
+        #[test]
+        fn programs_stay_within_their_config(
+            source in program_source(ProgramConfig { max_len: 16, max_depth: 2, timeline_ops: true })
+        ) {
+            prop_assert!(source.len() <= 16);
+            prop_assert!(depth(&source) <= 2);
+            prop_assert!(parse(source.as_str()).is_ok());
+        }
+    }
+
+    #[test]
+    fn unclosed_openers_are_closed_within_the_length() {
+        let config = ProgramConfig {
+            max_len: 5,
+            max_depth: 2,
+            timeline_ops: true,
+        };
+        let parts = [
+            Part::Open('[', ']'),
+            Part::Open('(', ')'),
+            Part::Open('[', ']'),
+            Part::Instruction('+'),
+            Part::Close,
+            Part::Close,
+            Part::Close,
+            Part::Instruction('-'),
+        ];
+        assert_eq!(lay_out(&parts, config), "[(+)]");
+    }
+}